@@ -1,6 +1,9 @@
 use super::schema::{
-    BOILER_SWAP_KEYSPACE,
-    columns::boiler_swap::{items, users},
+    BOILER_SWAP_KEYSPACE, HOME_KEYSPACE,
+    columns::{
+        accounts::{audit_events, passkeys, users},
+        boiler_swap::{items, user_items},
+    },
     tables,
 };
 use crate::{AppError, config::try_load};
@@ -11,61 +14,114 @@ use scylla::{
 use std::sync::Arc;
 
 #[derive(Clone)]
-pub struct BoilerSwap {
+pub struct Accounts {
     pub get_user: PreparedStatement,
     pub insert_user: PreparedStatement,
+    pub get_account: PreparedStatement,
+    pub insert_account: PreparedStatement,
     pub check_lock: PreparedStatement,
     pub update_lock: PreparedStatement,
     pub unlock_account: PreparedStatement,
+    pub update_password: PreparedStatement,
+    pub get_role: PreparedStatement,
+    pub get_totp: PreparedStatement,
+    pub update_totp: PreparedStatement,
+    pub remove_recovery_code: PreparedStatement,
+    pub get_passkeys: PreparedStatement,
+    pub insert_passkey: PreparedStatement,
+    pub get_passkey_records: PreparedStatement,
+    pub delete_passkeys: PreparedStatement,
+    pub delete_user: PreparedStatement,
+    pub insert_audit_event: PreparedStatement,
+    pub get_audit_events: PreparedStatement,
+    pub delete_audit_events: PreparedStatement,
+}
+
+#[derive(Clone)]
+pub struct BoilerSwap {
+    pub insert_user_item: PreparedStatement,
+    pub get_user_items: PreparedStatement,
+    pub delete_user_items: PreparedStatement,
+    pub delete_user_item: PreparedStatement,
     pub insert_item: PreparedStatement,
     pub get_items: PreparedStatement,
     pub delete_item: PreparedStatement,
+    pub get_item_ttl: PreparedStatement,
     pub get_cron_items: PreparedStatement,
 }
 
 #[derive(Clone)]
 pub struct DatabaseQueries {
     pub boiler_swap: BoilerSwap,
+    pub swap_accounts: Accounts,
+    pub home_accounts: Accounts,
 }
 
-impl BoilerSwap {
-    pub async fn init(session: &Session) -> Result<Self, AppError> {
+impl Accounts {
+    pub async fn init(session: &Session, keyspace: &str) -> Result<Self, AppError> {
         Ok(Self {
             get_user: session
             .prepare(format!(
                 "SELECT {}, {} FROM {}.{} WHERE {} = ?",
                 users::PASSWORD_HASH,
                 users::LOCKED,
-                BOILER_SWAP_KEYSPACE,
-                tables::boiler_swap::USERS,
+                keyspace,
+                tables::accounts::USERS,
                 users::PRIMARY_KEY
             ))
             .await?,
         insert_user: session
             .prepare(format!(
                 "INSERT INTO {}.{} ({}, {}, {}) VALUES (?, ?, ?) USING TTL {}",
-                BOILER_SWAP_KEYSPACE,
-                tables::boiler_swap::USERS,
+                keyspace,
+                tables::accounts::USERS,
                 users::EMAIL,
                 users::PASSWORD_HASH,
                 users::LOCKED,
                 users::TTL
             ))
             .await?,
+        get_account: session
+            .prepare(format!(
+                "SELECT {}, {}, {}, {}, {} FROM {}.{} WHERE {} = ?",
+                users::PASSWORD_HASH,
+                users::LOCKED,
+                users::TOTP_SECRET,
+                users::RECOVERY_CODES,
+                users::ROLE,
+                keyspace,
+                tables::accounts::USERS,
+                users::PRIMARY_KEY
+            ))
+            .await?,
+        insert_account: session
+            .prepare(format!(
+                "INSERT INTO {}.{} ({}, {}, {}, {}, {}, {}) VALUES (?, ?, ?, ?, ?, ?) IF NOT EXISTS USING TTL {}",
+                keyspace,
+                tables::accounts::USERS,
+                users::EMAIL,
+                users::PASSWORD_HASH,
+                users::LOCKED,
+                users::TOTP_SECRET,
+                users::RECOVERY_CODES,
+                users::ROLE,
+                users::TTL
+            ))
+            .await?,
         check_lock: session
             .prepare(format!(
                 "SELECT {} FROM {}.{} WHERE {} = ?",
                 users::LOCKED,
-                BOILER_SWAP_KEYSPACE,
-                tables::boiler_swap::USERS,
+                keyspace,
+                tables::accounts::USERS,
                 users::PRIMARY_KEY
             ))
             .await?,
         update_lock: session
             .prepare(format!(
                 "UPDATE {}.{} SET {} = ? WHERE {} = ?",
-                BOILER_SWAP_KEYSPACE,
-                tables::boiler_swap::USERS,
+                keyspace,
+                tables::accounts::USERS,
                 users::LOCKED,
                 users::PRIMARY_KEY
             ))
@@ -73,13 +129,188 @@ impl BoilerSwap {
         unlock_account: session
             .prepare(format!(
                 "UPDATE {}.{} SET {} = false, {} = ? WHERE {} = ?",
-                BOILER_SWAP_KEYSPACE,
-                tables::boiler_swap::USERS,
+                keyspace,
+                tables::accounts::USERS,
                 users::LOCKED,
                 users::PASSWORD_HASH,
                 users::PRIMARY_KEY
             ))
             .await?,
+        update_password: session
+            .prepare(format!(
+                "UPDATE {}.{} SET {} = ? WHERE {} = ?",
+                keyspace,
+                tables::accounts::USERS,
+                users::PASSWORD_HASH,
+                users::PRIMARY_KEY
+            ))
+            .await?,
+        get_role: session
+            .prepare(format!(
+                "SELECT {} FROM {}.{} WHERE {} = ?",
+                users::ROLE,
+                keyspace,
+                tables::accounts::USERS,
+                users::PRIMARY_KEY
+            ))
+            .await?,
+        get_totp: session
+            .prepare(format!(
+                "SELECT {}, {} FROM {}.{} WHERE {} = ?",
+                users::TOTP_SECRET,
+                users::RECOVERY_CODES,
+                keyspace,
+                tables::accounts::USERS,
+                users::PRIMARY_KEY
+            ))
+            .await?,
+        update_totp: session
+            .prepare(format!(
+                "UPDATE {}.{} SET {} = ?, {} = ? WHERE {} = ?",
+                keyspace,
+                tables::accounts::USERS,
+                users::TOTP_SECRET,
+                users::RECOVERY_CODES,
+                users::PRIMARY_KEY
+            ))
+            .await?,
+        remove_recovery_code: session
+            .prepare(format!(
+                "UPDATE {}.{} SET {} = ? WHERE {} = ? IF {} = ?",
+                keyspace,
+                tables::accounts::USERS,
+                users::RECOVERY_CODES,
+                users::PRIMARY_KEY,
+                users::RECOVERY_CODES
+            ))
+            .await?,
+        get_passkeys: session
+            .prepare(format!(
+                "SELECT {} FROM {}.{} WHERE {} = ?",
+                passkeys::PASSKEY,
+                keyspace,
+                tables::accounts::PASSKEYS,
+                passkeys::PARTITION_KEY
+            ))
+            .await?,
+        insert_passkey: session
+            .prepare(format!(
+                "INSERT INTO {}.{} ({}, {}, {}) VALUES (?, ?, ?) USING TTL {}",
+                keyspace,
+                tables::accounts::PASSKEYS,
+                passkeys::EMAIL,
+                passkeys::CREDENTIAL_ID,
+                passkeys::PASSKEY,
+                passkeys::TTL
+            ))
+            .await?,
+        get_passkey_records: session
+            .prepare(format!(
+                "SELECT {}, {} FROM {}.{} WHERE {} = ?",
+                passkeys::CREDENTIAL_ID,
+                passkeys::PASSKEY,
+                keyspace,
+                tables::accounts::PASSKEYS,
+                passkeys::PARTITION_KEY
+            ))
+            .await?,
+        delete_passkeys: session
+            .prepare(format!(
+                "DELETE FROM {}.{} WHERE {} = ?",
+                keyspace,
+                tables::accounts::PASSKEYS,
+                passkeys::PARTITION_KEY
+            ))
+            .await?,
+        delete_user: session
+            .prepare(format!(
+                "DELETE FROM {}.{} WHERE {} = ?",
+                keyspace,
+                tables::accounts::USERS,
+                users::PRIMARY_KEY
+            ))
+            .await?,
+        insert_audit_event: session
+            .prepare(format!(
+                "INSERT INTO {}.{} ({}, {}, {}, {}, {}, {}, {}, {}) VALUES (?, ?, ?, ?, ?, ?, ?, ?) USING TTL {}",
+                keyspace,
+                tables::accounts::AUDIT_EVENTS,
+                audit_events::EMAIL,
+                audit_events::BUCKET,
+                audit_events::EVENT_TIME,
+                audit_events::EVENT_ID,
+                audit_events::EVENT_TYPE,
+                audit_events::OUTCOME,
+                audit_events::HASHED_IP,
+                audit_events::USER_AGENT,
+                audit_events::TTL
+            ))
+            .await?,
+        get_audit_events: session
+            .prepare(format!(
+                "SELECT {}, {}, {}, {}, {} FROM {}.{} WHERE {} = ? AND {} = ? LIMIT ?",
+                audit_events::EVENT_TIME,
+                audit_events::EVENT_TYPE,
+                audit_events::OUTCOME,
+                audit_events::HASHED_IP,
+                audit_events::USER_AGENT,
+                keyspace,
+                tables::accounts::AUDIT_EVENTS,
+                audit_events::PARTITION_KEY.0,
+                audit_events::PARTITION_KEY.1
+            ))
+            .await?,
+        delete_audit_events: session
+            .prepare(format!(
+                "DELETE FROM {}.{} WHERE {} = ? AND {} IN ?",
+                keyspace,
+                tables::accounts::AUDIT_EVENTS,
+                audit_events::PARTITION_KEY.0,
+                audit_events::PARTITION_KEY.1
+            ))
+            .await?,
+        })
+    }
+}
+
+impl BoilerSwap {
+    pub async fn init(session: &Session) -> Result<Self, AppError> {
+        Ok(Self {
+            insert_user_item: session
+            .prepare(format!(
+                "INSERT INTO {}.{} ({}, {}) VALUES (?, ?) USING TTL ?",
+                BOILER_SWAP_KEYSPACE,
+                tables::boiler_swap::USER_ITEMS,
+                user_items::EMAIL,
+                user_items::ITEM_ID,
+            ))
+            .await?,
+        get_user_items: session
+            .prepare(format!(
+                "SELECT {} FROM {}.{} WHERE {} = ?",
+                user_items::ITEM_ID,
+                BOILER_SWAP_KEYSPACE,
+                tables::boiler_swap::USER_ITEMS,
+                user_items::PARTITION_KEY
+            ))
+            .await?,
+        delete_user_items: session
+            .prepare(format!(
+                "DELETE FROM {}.{} WHERE {} = ?",
+                BOILER_SWAP_KEYSPACE,
+                tables::boiler_swap::USER_ITEMS,
+                user_items::PARTITION_KEY
+            ))
+            .await?,
+        delete_user_item: session
+            .prepare(format!(
+                "DELETE FROM {}.{} WHERE {} = ? AND {} = ?",
+                BOILER_SWAP_KEYSPACE,
+                tables::boiler_swap::USER_ITEMS,
+                user_items::PARTITION_KEY,
+                user_items::CLUSTERING_KEY
+            ))
+            .await?,
         insert_item: session
             .prepare(format!(
                 "INSERT INTO {}.{} ({}, {}, {}, {}, {}, {}, {}, {}) VALUES (?, ?, ?, ?, ?, ?, ?, ?) USING TTL ?",
@@ -131,6 +362,15 @@ impl BoilerSwap {
                 items::ITEM_ID,
             ))
             .await?,
+        get_item_ttl: session
+            .prepare(format!(
+                "SELECT TTL({}) FROM {}.{} WHERE {} = ?",
+                items::TITLE,
+                BOILER_SWAP_KEYSPACE,
+                tables::boiler_swap::ITEMS,
+                items::PRIMARY_KEY
+            ))
+            .await?,
         })
     }
 }
@@ -139,6 +379,8 @@ impl DatabaseQueries {
     pub async fn init(session: &Session) -> Result<Self, AppError> {
         Ok(Self {
             boiler_swap: BoilerSwap::init(session).await?,
+            swap_accounts: Accounts::init(session, BOILER_SWAP_KEYSPACE).await?,
+            home_accounts: Accounts::init(session, HOME_KEYSPACE).await?,
         })
     }
 }
@@ -159,11 +401,9 @@ pub async fn init_database() -> Result<(Arc<Session>, DatabaseQueries), AppError
 }
 
 async fn create_tables(session: &Session) -> Result<(), AppError> {
-    session.query_unpaged(
-        format!("CREATE KEYSPACE IF NOT EXISTS {} WITH REPLICATION = {{'class': 'SimpleStrategy', 'replication_factor': 1}}", BOILER_SWAP_KEYSPACE),
-        &[],
-    )
-    .await?;
+    for keyspace in [BOILER_SWAP_KEYSPACE, HOME_KEYSPACE] {
+        create_account_tables(session, keyspace).await?;
+    }
 
     session
         .query_unpaged(
@@ -171,18 +411,16 @@ async fn create_tables(session: &Session) -> Result<(), AppError> {
                 "CREATE TABLE IF NOT EXISTS {}.{} (
             {} {},
             {} {},
-            {} {},
-            PRIMARY KEY({})
+            PRIMARY KEY(({}), {})
         )",
                 BOILER_SWAP_KEYSPACE,
-                tables::boiler_swap::USERS,
-                users::EMAIL,
-                users::EMAIL_TYPE,
-                users::PASSWORD_HASH,
-                users::PASSWORD_HASH_TYPE,
-                users::LOCKED,
-                users::LOCKED_TYPE,
-                users::PRIMARY_KEY,
+                tables::boiler_swap::USER_ITEMS,
+                user_items::EMAIL,
+                user_items::EMAIL_TYPE,
+                user_items::ITEM_ID,
+                user_items::ITEM_ID_TYPE,
+                user_items::PARTITION_KEY,
+                user_items::CLUSTERING_KEY,
             ),
             &[],
         )
@@ -228,3 +466,151 @@ async fn create_tables(session: &Session) -> Result<(), AppError> {
 
     Ok(())
 }
+
+async fn create_account_tables(session: &Session, keyspace: &str) -> Result<(), AppError> {
+    session.query_unpaged(
+        format!("CREATE KEYSPACE IF NOT EXISTS {} WITH REPLICATION = {{'class': 'SimpleStrategy', 'replication_factor': 1}}", keyspace),
+        &[],
+    )
+    .await?;
+
+    session
+        .query_unpaged(
+            format!(
+                "CREATE TABLE IF NOT EXISTS {}.{} (
+            {} {},
+            {} {},
+            {} {},
+            PRIMARY KEY({})
+        )",
+                keyspace,
+                tables::accounts::USERS,
+                users::EMAIL,
+                users::EMAIL_TYPE,
+                users::PASSWORD_HASH,
+                users::PASSWORD_HASH_TYPE,
+                users::LOCKED,
+                users::LOCKED_TYPE,
+                users::PRIMARY_KEY,
+            ),
+            &[],
+        )
+        .await?;
+
+    add_missing_columns(
+        session,
+        keyspace,
+        tables::accounts::USERS,
+        &[
+            (users::TOTP_SECRET, users::TOTP_SECRET_TYPE),
+            (users::RECOVERY_CODES, users::RECOVERY_CODES_TYPE),
+            (users::ROLE, users::ROLE_TYPE),
+        ],
+    )
+    .await?;
+
+    session
+        .query_unpaged(
+            format!(
+                "CREATE TABLE IF NOT EXISTS {}.{} (
+            {} {},
+            {} {},
+            {} {},
+            PRIMARY KEY(({}), {})
+        )",
+                keyspace,
+                tables::accounts::PASSKEYS,
+                passkeys::EMAIL,
+                passkeys::EMAIL_TYPE,
+                passkeys::CREDENTIAL_ID,
+                passkeys::CREDENTIAL_ID_TYPE,
+                passkeys::PASSKEY,
+                passkeys::PASSKEY_TYPE,
+                passkeys::PARTITION_KEY,
+                passkeys::CLUSTERING_KEY,
+            ),
+            &[],
+        )
+        .await?;
+
+    session
+        .query_unpaged(
+            format!(
+                "CREATE TABLE IF NOT EXISTS {}.{} (
+            {} {},
+            {} {},
+            {} {},
+            {} {},
+            {} {},
+            {} {},
+            {} {},
+            {} {},
+            PRIMARY KEY(({}, {}), {}, {})
+        ) WITH CLUSTERING ORDER BY ({} DESC, {} ASC)",
+                keyspace,
+                tables::accounts::AUDIT_EVENTS,
+                audit_events::EMAIL,
+                audit_events::EMAIL_TYPE,
+                audit_events::BUCKET,
+                audit_events::BUCKET_TYPE,
+                audit_events::EVENT_TIME,
+                audit_events::EVENT_TIME_TYPE,
+                audit_events::EVENT_ID,
+                audit_events::EVENT_ID_TYPE,
+                audit_events::EVENT_TYPE,
+                audit_events::EVENT_TYPE_TYPE,
+                audit_events::OUTCOME,
+                audit_events::OUTCOME_TYPE,
+                audit_events::HASHED_IP,
+                audit_events::HASHED_IP_TYPE,
+                audit_events::USER_AGENT,
+                audit_events::USER_AGENT_TYPE,
+                audit_events::PARTITION_KEY.0,
+                audit_events::PARTITION_KEY.1,
+                audit_events::CLUSTERING_KEY.0,
+                audit_events::CLUSTERING_KEY.1,
+                audit_events::CLUSTERING_KEY.0,
+                audit_events::CLUSTERING_KEY.1,
+            ),
+            &[],
+        )
+        .await?;
+
+    Ok(())
+}
+
+async fn add_missing_columns(
+    session: &Session,
+    keyspace: &str,
+    table: &str,
+    columns: &[(&str, &str)],
+) -> Result<(), AppError> {
+    let existing_columns: Vec<String> = session
+        .query_unpaged(
+            "SELECT column_name FROM system_schema.columns WHERE keyspace_name = ? AND table_name = ?",
+            (keyspace, table),
+        )
+        .await?
+        .into_rows_result()?
+        .rows::<(String,)>()?
+        .map(|row| row.map(|(column_name,)| column_name))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for (column, column_type) in columns {
+        if existing_columns.iter().any(|existing| existing == column) {
+            continue;
+        }
+
+        session
+            .query_unpaged(
+                format!(
+                    "ALTER TABLE {}.{} ADD {} {}",
+                    keyspace, table, column, column_type
+                ),
+                &[],
+            )
+            .await?;
+    }
+
+    Ok(())
+}
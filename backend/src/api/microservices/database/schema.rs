@@ -1,15 +1,23 @@
 pub const BOILER_SWAP_KEYSPACE: &str = "boiler_swap";
 
+pub const HOME_KEYSPACE: &str = "home";
+
 pub mod tables {
-    pub mod boiler_swap {
+    pub mod accounts {
         pub const USERS: &str = "users";
+        pub const PASSKEYS: &str = "passkeys";
+        pub const AUDIT_EVENTS: &str = "audit_events";
+    }
+
+    pub mod boiler_swap {
         pub const ITEMS: &str = "items";
+        pub const USER_ITEMS: &str = "user_items";
         pub const CDC: &str = "cdc";
     }
 }
 
 pub mod columns {
-    pub mod boiler_swap {
+    pub mod accounts {
         pub mod users {
             pub const EMAIL: &str = "email";
             pub const EMAIL_TYPE: &str = "text";
@@ -20,10 +28,77 @@ pub mod columns {
             pub const LOCKED: &str = "locked";
             pub const LOCKED_TYPE: &str = "boolean";
 
+            pub const TOTP_SECRET: &str = "totp_secret";
+            pub const TOTP_SECRET_TYPE: &str = "text";
+
+            pub const RECOVERY_CODES: &str = "recovery_codes";
+            pub const RECOVERY_CODES_TYPE: &str = "set<text>";
+
+            pub const ROLE: &str = "role";
+            pub const ROLE_TYPE: &str = "text";
+
             pub const PRIMARY_KEY: &str = EMAIL;
             pub const TTL: &str = "126144000";
         }
 
+        pub mod passkeys {
+            pub const EMAIL: &str = "email";
+            pub const EMAIL_TYPE: &str = "text";
+
+            pub const CREDENTIAL_ID: &str = "credential_id";
+            pub const CREDENTIAL_ID_TYPE: &str = "text";
+
+            pub const PASSKEY: &str = "passkey";
+            pub const PASSKEY_TYPE: &str = "text";
+
+            pub const PARTITION_KEY: &str = EMAIL;
+            pub const CLUSTERING_KEY: &str = CREDENTIAL_ID;
+            pub const TTL: &str = super::users::TTL;
+        }
+
+        pub mod audit_events {
+            pub const EMAIL: &str = "email";
+            pub const EMAIL_TYPE: &str = "text";
+
+            pub const BUCKET: &str = "bucket";
+            pub const BUCKET_TYPE: &str = "text";
+
+            pub const EVENT_TIME: &str = "event_time";
+            pub const EVENT_TIME_TYPE: &str = "timestamp";
+
+            pub const EVENT_ID: &str = "event_id";
+            pub const EVENT_ID_TYPE: &str = "uuid";
+
+            pub const EVENT_TYPE: &str = "event_type";
+            pub const EVENT_TYPE_TYPE: &str = "text";
+
+            pub const OUTCOME: &str = "outcome";
+            pub const OUTCOME_TYPE: &str = "text";
+
+            pub const HASHED_IP: &str = "hashed_ip";
+            pub const HASHED_IP_TYPE: &str = "text";
+
+            pub const USER_AGENT: &str = "user_agent";
+            pub const USER_AGENT_TYPE: &str = "text";
+
+            pub const PARTITION_KEY: (&str, &str) = (EMAIL, BUCKET);
+            pub const CLUSTERING_KEY: (&str, &str) = (EVENT_TIME, EVENT_ID);
+            pub const TTL: &str = "31536000";
+        }
+    }
+
+    pub mod boiler_swap {
+        pub mod user_items {
+            pub const EMAIL: &str = "email";
+            pub const EMAIL_TYPE: &str = "text";
+
+            pub const ITEM_ID: &str = "item_id";
+            pub const ITEM_ID_TYPE: &str = "uuid";
+
+            pub const PARTITION_KEY: &str = EMAIL;
+            pub const CLUSTERING_KEY: &str = ITEM_ID;
+        }
+
         pub mod items {
             pub const ITEM_ID: &str = "item_id";
             pub const ITEM_ID_TYPE: &str = "uuid";
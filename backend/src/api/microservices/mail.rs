@@ -0,0 +1,97 @@
+use crate::{
+    AppError,
+    config::{Config, MailTransportKind},
+};
+use async_trait::async_trait;
+use lettre::{
+    AsyncFileTransport, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+    transport::smtp::{PoolConfig, authentication::Credentials},
+};
+use std::{
+    fs::create_dir_all,
+    sync::{Arc, Mutex},
+};
+use tracing::info;
+
+#[async_trait]
+pub trait MailTransport: Send + Sync {
+    async fn send(&self, message: Message) -> Result<(), AppError>;
+}
+
+pub struct SmtpMailTransport {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+pub struct FileMailTransport {
+    transport: AsyncFileTransport<Tokio1Executor>,
+}
+
+#[derive(Default)]
+pub struct MemoryMailTransport {
+    messages: Mutex<Vec<Message>>,
+}
+
+#[async_trait]
+impl MailTransport for SmtpMailTransport {
+    async fn send(&self, message: Message) -> Result<(), AppError> {
+        self.transport.send(message).await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MailTransport for FileMailTransport {
+    async fn send(&self, message: Message) -> Result<(), AppError> {
+        let id = self.transport.send(message).await?;
+
+        info!("Wrote email {}.eml", id);
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MailTransport for MemoryMailTransport {
+    async fn send(&self, message: Message) -> Result<(), AppError> {
+        self.messages
+            .lock()
+            .expect("mail outbox poisoned")
+            .push(message);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl MemoryMailTransport {
+    pub fn messages(&self) -> Vec<Message> {
+        self.messages.lock().expect("mail outbox poisoned").clone()
+    }
+}
+
+pub fn init_mail_transport(config: &Config) -> Result<Arc<dyn MailTransport>, AppError> {
+    info!("Using {} mail transport", config.email.transport.as_ref());
+
+    Ok(match config.email.transport {
+        MailTransportKind::Smtp => Arc::new(SmtpMailTransport {
+            transport: AsyncSmtpTransport::<Tokio1Executor>::relay(
+                &config.email.from_email_server,
+            )?
+            .credentials(Credentials::new(
+                config.email.from_email.to_string(),
+                config.email.from_email_password.to_string(),
+            ))
+            .pool_config(PoolConfig::new().max_size(config.mail_queue.pool_size))
+            .build(),
+        }),
+        MailTransportKind::File => {
+            create_dir_all(&config.email.outbox_path)?;
+
+            Arc::new(FileMailTransport {
+                transport: AsyncFileTransport::<Tokio1Executor>::new(&config.email.outbox_path),
+            })
+        }
+        MailTransportKind::Memory => Arc::new(MemoryMailTransport::default()),
+    })
+}
@@ -1,4 +1,5 @@
 pub mod cdc;
 pub mod database;
+pub mod mail;
 pub mod meilisearch;
 pub mod redis;
@@ -1,10 +1,21 @@
 use crate::{AppError, AppState, config::try_load};
+use once_cell::sync::Lazy;
 use redis::{
-    AsyncTypedCommands, Client,
+    AsyncTypedCommands, Client, Script,
     aio::{ConnectionManager, ConnectionManagerConfig},
 };
 use std::{sync::Arc, time::Duration};
 
+static RENAME_SCRIPT: Lazy<Script> = Lazy::new(|| {
+    Script::new(
+        r#"
+        if redis.call("EXISTS", KEYS[1]) == 1 then
+            redis.call("RENAME", KEYS[1], KEYS[2])
+        end
+    "#,
+    )
+});
+
 pub async fn init_redis() -> Result<ConnectionManager, AppError> {
     let redis_url = try_load::<String>("RUST_REDIS_URL", "redis://redis:6379").unwrap();
 
@@ -40,6 +51,50 @@ pub async fn remove_id(state: Arc<AppState>, key: &str) -> Result<(), AppError>
     Ok(())
 }
 
+pub async fn rename_id(state: Arc<AppState>, key: &str, new_key: &str) -> Result<(), AppError> {
+    let _: () = RENAME_SCRIPT
+        .key(key)
+        .key(new_key)
+        .invoke_async(&mut state.redis_connection_manager.clone())
+        .await?;
+
+    Ok(())
+}
+
 pub async fn try_get(state: Arc<AppState>, key: &str) -> Result<Option<String>, AppError> {
     Ok(state.redis_connection_manager.clone().get(key).await?)
 }
+
+pub async fn scan_keys(state: Arc<AppState>, pattern: &str) -> Result<Vec<String>, AppError> {
+    let mut cursor: u64 = 0;
+    let mut keys = Vec::new();
+
+    loop {
+        let (next_cursor, batch): (u64, Vec<String>) = redis::cmd("SCAN")
+            .arg(cursor)
+            .arg("MATCH")
+            .arg(pattern)
+            .arg("COUNT")
+            .arg(1000)
+            .query_async(&mut state.redis_connection_manager.clone())
+            .await?;
+
+        keys.extend(batch);
+
+        if next_cursor == 0 {
+            return Ok(keys);
+        }
+
+        cursor = next_cursor;
+    }
+}
+
+pub fn escape_pattern(value: &str) -> String {
+    value
+        .chars()
+        .flat_map(|c| match c {
+            '*' | '?' | '[' | ']' | '\\' => vec!['\\', c],
+            _ => vec![c],
+        })
+        .collect()
+}
@@ -0,0 +1,492 @@
+use super::{
+    cookies::get_cookie,
+    database::{delete_user_data, get_user, migrate_audit_events, migrate_user},
+    locks::{check_redis_lock, increment_lock_key, invalidate_pending_logins, unfreeze_account},
+    models::{
+        Action, ActiveSession, IssuedCode, PasswordChange, RedisAccount, RedisAction, WebsitePath,
+    },
+    password::PasswordPolicy,
+    sessions::{
+        create_auth_redis_account, create_temporary_session, delete_all_sessions,
+        delete_other_sessions, send_code, try_get_redis_account,
+    },
+    swap::redis::reassign_items,
+    twofactor::{generate_code, hash_code},
+    utilities::{clear_all_keys, format_verified_result, get_key},
+    verify::{check_email, check_password, is_code_format, verify_password},
+};
+use crate::{
+    AppError, AppState,
+    api::microservices::redis::{insert_id, rename_id},
+};
+use axum::http::header::HeaderMap;
+use std::sync::Arc;
+use tokio::task::spawn_blocking;
+
+const PURGED_KEYS: [RedisAction; 6] = [
+    RedisAction::LockedItems,
+    RedisAction::LockedTime,
+    RedisAction::TotpPending,
+    RedisAction::TotpUsed,
+    RedisAction::PasskeyRegistration,
+    RedisAction::KnownDevices,
+];
+
+pub async fn start_account_deletion(
+    state: Arc<AppState>,
+    session: &ActiveSession,
+    hashed_ip: &str,
+    locale: String,
+    website_path: &WebsitePath,
+) -> Result<(), AppError> {
+    let code_key = get_key(RedisAction::LockedCode, hashed_ip);
+
+    check_redis_lock(
+        state.clone(),
+        website_path.as_ref(),
+        &code_key,
+        &session.record.email,
+        &state.config.authentication.max_codes,
+    )
+    .await?;
+
+    let issued_code = IssuedCode {
+        code: generate_code(),
+        link_token: None,
+    };
+
+    let redis_account = RedisAccount {
+        code_hash: hash_code(&state.config, &session.record.email, &issued_code.code),
+        locale: Some(locale),
+        ..create_auth_redis_account(session.record.email.clone(), Action::Delete, None)
+    };
+
+    send_code(
+        state.clone(),
+        &RedisAction::DeleteAccount,
+        &redis_account,
+        &issued_code,
+        &None,
+        &Some(code_key),
+        website_path,
+    )
+    .await?;
+
+    insert_id(
+        state.clone(),
+        &format!(
+            "{}:{}:{}",
+            website_path.as_ref(),
+            RedisAction::DeleteAccount.as_ref(),
+            &session.id
+        ),
+        &serde_json::to_string(&redis_account)?,
+        state
+            .config
+            .session
+            .temporary_session_duration_seconds
+            .into(),
+    )
+    .await?;
+
+    Ok(())
+}
+
+pub async fn finish_account_deletion(
+    state: Arc<AppState>,
+    session: &ActiveSession,
+    token: &str,
+    hashed_ip: &str,
+    website_path: &WebsitePath,
+) -> Result<(), AppError> {
+    if !is_code_format(token) {
+        return Err(AppError::Unauthorized("Unable to verify".to_string()));
+    }
+
+    let verified_result = format_verified_result(
+        state.clone(),
+        website_path,
+        RedisAction::DeleteAccount,
+        session.id.clone(),
+    )
+    .await?
+    .ok_or(AppError::Unauthorized("Unable to verify".to_string()))?;
+
+    let redis_account = try_get_redis_account(
+        state.clone(),
+        &verified_result,
+        token,
+        hashed_ip,
+        website_path,
+    )
+    .await?;
+
+    if redis_account.email != session.record.email {
+        return Err(AppError::Unauthorized("Unable to verify".to_string()));
+    }
+
+    purge_account(state.clone(), &redis_account.email, website_path).await
+}
+
+pub async fn purge_account(
+    state: Arc<AppState>,
+    email: &str,
+    website_path: &WebsitePath,
+) -> Result<(), AppError> {
+    delete_user_data(state.clone(), email, website_path).await?;
+
+    clear_all_keys(
+        state.clone(),
+        website_path.as_ref(),
+        &get_purged_keys(),
+        email,
+    )
+    .await?;
+
+    delete_all_sessions(
+        state.clone(),
+        website_path.as_ref(),
+        RedisAction::Session.as_ref(),
+        RedisAction::SessionStore.as_ref(),
+        email,
+    )
+    .await
+}
+
+pub async fn change_password(
+    state: Arc<AppState>,
+    session: &ActiveSession,
+    payload: &PasswordChange,
+    hashed_ip: &str,
+    website_path: &WebsitePath,
+) -> Result<(), AppError> {
+    check_password_change(&state.password_policy, &session.record.email, payload)?;
+
+    let email = &session.record.email;
+    let auth_key = get_key(RedisAction::LockedAuth, hashed_ip);
+
+    check_redis_lock(
+        state.clone(),
+        website_path.as_ref(),
+        &auth_key,
+        email,
+        &state.config.authentication.auth_max_attempts,
+    )
+    .await?;
+
+    let Some((password_hash, false)) = get_user(state.clone(), email, website_path).await? else {
+        return Err(AppError::Unauthorized("Unable to verify".to_string()));
+    };
+
+    if !spawn_blocking({
+        let plaintext = payload.current_password.to_owned();
+        move || verify_password(&plaintext, &password_hash)
+    })
+    .await?
+    {
+        increment_lock_key(
+            state.clone(),
+            website_path.as_ref(),
+            &auth_key,
+            email,
+            &state.config.authentication.auth_lock_duration_seconds,
+            &state.config.authentication.auth_max_attempts,
+        )
+        .await?;
+
+        return Err(AppError::Unauthorized("Unable to verify".to_string()));
+    }
+
+    unfreeze_account(state.clone(), email, &payload.new_password, website_path).await?;
+
+    invalidate_pending_logins(state.clone(), email, website_path).await?;
+
+    delete_other_sessions(state.clone(), website_path.as_ref(), email, &session.id).await
+}
+
+fn get_purged_keys() -> Vec<&'static str> {
+    PURGED_KEYS.iter().map(|key| key.as_ref()).collect()
+}
+
+fn check_password_change(
+    password_policy: &PasswordPolicy,
+    email: &str,
+    payload: &PasswordChange,
+) -> Result<(), AppError> {
+    check_password(password_policy, email, &payload.new_password)?;
+
+    if payload.new_password == payload.current_password {
+        return Err(AppError::BadRequest(
+            "New password must be different".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+pub async fn start_email_change(
+    state: Arc<AppState>,
+    session: &ActiveSession,
+    new_email: &str,
+    hashed_ip: &str,
+    locale: String,
+    website_path: &WebsitePath,
+) -> Result<HeaderMap, AppError> {
+    check_email(state.clone(), new_email, website_path)?;
+
+    if new_email == session.record.email {
+        return Err(AppError::BadRequest(
+            "New email must be different".to_string(),
+        ));
+    }
+
+    let code_key = get_key(RedisAction::LockedCode, hashed_ip);
+
+    check_redis_lock(
+        state.clone(),
+        website_path.as_ref(),
+        &code_key,
+        new_email,
+        &state.config.authentication.max_codes,
+    )
+    .await?;
+
+    if get_user(state.clone(), new_email, website_path)
+        .await?
+        .is_some()
+    {
+        return Err(AppError::BadRequest("Email unavailable".to_string()));
+    }
+
+    let redis_account = RedisAccount {
+        locale: Some(locale),
+        previous_email: Some(session.record.email.clone()),
+        ..create_auth_redis_account(new_email.to_string(), Action::ChangeEmail, None)
+    };
+
+    create_temporary_session(
+        state.clone(),
+        &None,
+        &redis_account,
+        &RedisAction::ChangeEmail,
+        &None,
+        &Some(code_key),
+        website_path,
+    )
+    .await
+}
+
+pub async fn finish_email_change(
+    state: Arc<AppState>,
+    session: &ActiveSession,
+    headers: &HeaderMap,
+    token: &str,
+    hashed_ip: &str,
+    website_path: &WebsitePath,
+) -> Result<String, AppError> {
+    if !is_code_format(token) {
+        return Err(AppError::Unauthorized("Unable to verify".to_string()));
+    }
+
+    let verified_result = format_verified_result(
+        state.clone(),
+        website_path,
+        RedisAction::ChangeEmail,
+        get_cookie(headers, RedisAction::ChangeEmail.as_ref())
+            .ok_or(AppError::Unauthorized("Unable to verify".to_string()))?,
+    )
+    .await?
+    .ok_or(AppError::Unauthorized("Unable to verify".to_string()))?;
+
+    let redis_account = try_get_redis_account(
+        state.clone(),
+        &verified_result,
+        token,
+        hashed_ip,
+        website_path,
+    )
+    .await?;
+
+    if redis_account.action != Action::ChangeEmail
+        || redis_account.previous_email.as_deref() != Some(session.record.email.as_str())
+    {
+        return Err(AppError::Unauthorized("Unable to verify".to_string()));
+    }
+
+    migrate_account(
+        state.clone(),
+        &session.record.email,
+        &redis_account.email,
+        website_path,
+    )
+    .await?;
+
+    Ok(redis_account.email)
+}
+
+pub async fn migrate_account(
+    state: Arc<AppState>,
+    email: &str,
+    new_email: &str,
+    website_path: &WebsitePath,
+) -> Result<(), AppError> {
+    if get_user(state.clone(), new_email, website_path)
+        .await?
+        .is_some()
+    {
+        return Err(AppError::BadRequest("Email unavailable".to_string()));
+    }
+
+    invalidate_pending_logins(state.clone(), email, website_path).await?;
+
+    let item_ids = migrate_user(state.clone(), email, new_email, website_path).await?;
+
+    migrate_audit_events(state.clone(), email, new_email, website_path).await?;
+
+    rename_id(
+        state.clone(),
+        &format!(
+            "{}:{}:{}",
+            website_path.as_ref(),
+            RedisAction::KnownDevices.as_ref(),
+            email
+        ),
+        &format!(
+            "{}:{}:{}",
+            website_path.as_ref(),
+            RedisAction::KnownDevices.as_ref(),
+            new_email
+        ),
+    )
+    .await?;
+
+    reassign_items(
+        state.clone(),
+        website_path.as_ref(),
+        email,
+        new_email,
+        &item_ids,
+    )
+    .await?;
+
+    clear_all_keys(
+        state.clone(),
+        website_path.as_ref(),
+        &[
+            RedisAction::TotpPending.as_ref(),
+            RedisAction::TotpUsed.as_ref(),
+            RedisAction::PasskeyRegistration.as_ref(),
+        ],
+        email,
+    )
+    .await?;
+
+    delete_all_sessions(
+        state.clone(),
+        website_path.as_ref(),
+        RedisAction::Session.as_ref(),
+        RedisAction::SessionStore.as_ref(),
+        email,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{api::web::utilities::get_clear_keys_pipe, config::Passwords};
+    use redis::{AsyncTypedCommands, aio::MultiplexedConnection};
+    use uuid::Uuid;
+
+    async fn redis_connection() -> MultiplexedConnection {
+        redis::Client::open(
+            std::env::var("REDIS_TEST_URL").unwrap_or("redis://127.0.0.1:6379".to_string()),
+        )
+        .unwrap()
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap()
+    }
+
+    fn policy() -> PasswordPolicy {
+        PasswordPolicy::load(&Passwords {
+            min_entropy_bits: 40.0,
+            blocklist_path: "data/common_passwords.txt".to_string(),
+        })
+        .unwrap()
+    }
+
+    fn change(current_password: &str, new_password: &str) -> PasswordChange {
+        PasswordChange {
+            current_password: current_password.to_string(),
+            new_password: new_password.to_string(),
+        }
+    }
+
+    #[test]
+    fn password_change_accepts_a_strong_new_password() {
+        assert!(
+            check_password_change(
+                &policy(),
+                "pete@purdue.edu",
+                &change("old password", "correct horse battery staple"),
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn password_change_rejects_the_same_password() {
+        assert!(matches!(
+            check_password_change(
+                &policy(),
+                "pete@purdue.edu",
+                &change(
+                    "correct horse battery staple",
+                    "correct horse battery staple"
+                ),
+            ),
+            Err(AppError::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn password_change_rejects_a_weak_new_password() {
+        for new_password in ["password123", "short", "pete12345678"] {
+            assert!(matches!(
+                check_password_change(
+                    &policy(),
+                    "pete@purdue.edu",
+                    &change("correct horse battery staple", new_password),
+                ),
+                Err(AppError::BadRequest(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn account_deletion_purges_known_devices() {
+        assert!(get_purged_keys().contains(&RedisAction::KnownDevices.as_ref()));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Redis at REDIS_TEST_URL"]
+    async fn account_deletion_clears_known_devices() {
+        let mut connection = redis_connection().await;
+        let site = format!("test:{}", Uuid::new_v4());
+        let known_devices_key = format!(
+            "{}:{}:pete@purdue.edu",
+            site,
+            RedisAction::KnownDevices.as_ref()
+        );
+
+        connection.sadd(&known_devices_key, "device").await.unwrap();
+
+        get_clear_keys_pipe(&site, &get_purged_keys(), "pete@purdue.edu")
+            .query_async::<()>(&mut connection)
+            .await
+            .unwrap();
+
+        assert!(!connection.exists(&known_devices_key).await.unwrap());
+    }
+}
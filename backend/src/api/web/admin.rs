@@ -0,0 +1,310 @@
+use super::{
+    database::{get_role, get_user, update_lock},
+    locks::freeze_account,
+    models::{ActiveSession, AdminUserPayload, LockPayload, RedisAction, Role, WebsitePath},
+    sessions::{check_session, list_sessions},
+    swap::database::remove_item,
+};
+use crate::{
+    AppError, AppState,
+    api::microservices::redis::{escape_pattern, remove_id, scan_keys, try_get},
+    config::Config,
+};
+use axum::http::header::HeaderMap;
+use redis::pipe;
+use std::sync::Arc;
+use uuid::Uuid;
+
+const COUNTED_LOCKS: [RedisAction; 4] = [
+    RedisAction::LockedAuth,
+    RedisAction::LockedVerify,
+    RedisAction::LockedCode,
+    RedisAction::LockedForgot,
+];
+
+pub async fn check_admin_session(
+    state: Arc<AppState>,
+    headers: &HeaderMap,
+    website_path: &WebsitePath,
+) -> Result<ActiveSession, AppError> {
+    let session = check_session(state.clone(), headers, website_path).await?;
+
+    if get_user_role(state.clone(), &session.record.email, website_path).await? != Role::Admin {
+        return Err(AppError::Forbidden("Admin access required".to_string()));
+    }
+
+    Ok(session)
+}
+
+pub async fn get_user_overview(
+    state: Arc<AppState>,
+    email: &str,
+    website_path: &WebsitePath,
+) -> Result<AdminUserPayload, AppError> {
+    let locked = get_user_lock(state.clone(), email, website_path).await?;
+
+    Ok(AdminUserPayload {
+        email: email.to_string(),
+        role: get_user_role(state.clone(), email, website_path)
+            .await?
+            .as_ref()
+            .to_string(),
+        locked,
+        items_posted: try_get(
+            state.clone(),
+            &format!(
+                "{}:{}:{}",
+                website_path.as_ref(),
+                RedisAction::LockedItems.as_ref(),
+                email
+            ),
+        )
+        .await?
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(0),
+        locks: get_lock_counters(state.clone(), email, website_path).await?,
+        sessions: list_sessions(state.clone(), "", email, website_path).await?,
+    })
+}
+
+pub async fn force_unlock(
+    state: Arc<AppState>,
+    email: &str,
+    website_path: &WebsitePath,
+) -> Result<(), AppError> {
+    get_user_lock(state.clone(), email, website_path).await?;
+
+    update_lock(state.clone(), email, false, website_path).await?;
+
+    clear_lock_counters(state.clone(), email, website_path).await
+}
+
+pub async fn force_freeze(
+    state: Arc<AppState>,
+    email: &str,
+    website_path: &WebsitePath,
+) -> Result<(), AppError> {
+    get_user_lock(state.clone(), email, website_path).await?;
+
+    freeze_account(state.clone(), email, website_path).await
+}
+
+pub async fn reset_item_quota(
+    state: Arc<AppState>,
+    email: &str,
+    website_path: &WebsitePath,
+) -> Result<(), AppError> {
+    get_user_lock(state.clone(), email, website_path).await?;
+
+    remove_id(
+        state.clone(),
+        &format!(
+            "{}:{}:{}",
+            website_path.as_ref(),
+            RedisAction::LockedItems.as_ref(),
+            email
+        ),
+    )
+    .await
+}
+
+pub async fn delete_item(
+    state: Arc<AppState>,
+    item_id: Uuid,
+    website_path: &WebsitePath,
+) -> Result<(), AppError> {
+    let email = try_get(
+        state.clone(),
+        &format!(
+            "{}:{}:{}",
+            website_path.as_ref(),
+            RedisAction::DeletedItem.as_ref(),
+            item_id
+        ),
+    )
+    .await?
+    .ok_or(AppError::BadRequest("Item not found".to_string()))?;
+
+    remove_item(state.clone(), item_id, &email).await
+}
+
+pub async fn clear_lock_counters(
+    state: Arc<AppState>,
+    email: &str,
+    website_path: &WebsitePath,
+) -> Result<(), AppError> {
+    let lock_keys = find_lock_keys(state.clone(), email, website_path).await?;
+
+    if lock_keys.is_empty() {
+        return Ok(());
+    }
+
+    let mut pipe = pipe();
+
+    for (_, key) in &lock_keys {
+        pipe.del(key).ignore();
+    }
+
+    pipe.query_async::<()>(&mut state.redis_connection_manager.clone())
+        .await?;
+
+    Ok(())
+}
+
+async fn get_user_role(
+    state: Arc<AppState>,
+    email: &str,
+    website_path: &WebsitePath,
+) -> Result<Role, AppError> {
+    Ok(get_effective_role(
+        &state.config,
+        email,
+        get_role(state.clone(), email, website_path).await?,
+        website_path,
+    ))
+}
+
+fn get_effective_role(
+    config: &Config,
+    email: &str,
+    role: Role,
+    website_path: &WebsitePath,
+) -> Role {
+    let admins = match website_path {
+        WebsitePath::BoilerSwap => &config.admins.swap,
+        WebsitePath::Home => &config.admins.home,
+        WebsitePath::Photos => return role,
+    };
+
+    if admins.iter().any(|admin| admin == email) {
+        Role::Admin
+    } else {
+        role
+    }
+}
+
+async fn get_user_lock(
+    state: Arc<AppState>,
+    email: &str,
+    website_path: &WebsitePath,
+) -> Result<bool, AppError> {
+    get_user(state.clone(), email, website_path)
+        .await?
+        .map(|(_, locked)| locked)
+        .ok_or(AppError::BadRequest("User not found".to_string()))
+}
+
+async fn get_lock_counters(
+    state: Arc<AppState>,
+    email: &str,
+    website_path: &WebsitePath,
+) -> Result<Vec<LockPayload>, AppError> {
+    let mut counters = Vec::new();
+
+    for (lock, key) in find_lock_keys(state.clone(), email, website_path).await? {
+        let (attempts, expires_in): (Option<String>, i64) = pipe()
+            .get(&key)
+            .ttl(&key)
+            .query_async(&mut state.redis_connection_manager.clone())
+            .await?;
+
+        let Some(attempts) = attempts else {
+            continue;
+        };
+
+        counters.push(LockPayload {
+            hashed_ip: key
+                .strip_prefix(&format!("{}:{}:", website_path.as_ref(), lock))
+                .and_then(|rest| rest.strip_suffix(&format!(":{}", email)))
+                .unwrap_or_default()
+                .to_string(),
+            lock,
+            attempts: attempts.parse().unwrap_or(0),
+            expires_in,
+        });
+    }
+
+    Ok(counters)
+}
+
+async fn find_lock_keys(
+    state: Arc<AppState>,
+    email: &str,
+    website_path: &WebsitePath,
+) -> Result<Vec<(String, String)>, AppError> {
+    let mut lock_keys = Vec::new();
+
+    for lock in &COUNTED_LOCKS {
+        for key in scan_keys(
+            state.clone(),
+            &format!(
+                "{}:{}:*:{}",
+                website_path.as_ref(),
+                lock.as_ref(),
+                escape_pattern(email)
+            ),
+        )
+        .await?
+        {
+            lock_keys.push((lock.as_ref().to_string(), key));
+        }
+    }
+
+    Ok(lock_keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Admins;
+
+    fn config() -> Config {
+        Config {
+            admins: Admins {
+                swap: vec!["pete@purdue.edu".to_string()],
+                home: Vec::new(),
+            },
+            ..Config::for_tests()
+        }
+    }
+
+    #[test]
+    fn configured_emails_are_admins_on_their_site() {
+        assert!(
+            get_effective_role(
+                &config(),
+                "pete@purdue.edu",
+                Role::User,
+                &WebsitePath::BoilerSwap
+            ) == Role::Admin
+        );
+    }
+
+    #[test]
+    fn configured_emails_are_not_admins_on_other_sites() {
+        assert!(
+            get_effective_role(&config(), "pete@purdue.edu", Role::User, &WebsitePath::Home)
+                == Role::User
+        );
+    }
+
+    #[test]
+    fn other_emails_keep_their_stored_role() {
+        assert!(
+            get_effective_role(
+                &config(),
+                "purdue.pete@purdue.edu",
+                Role::User,
+                &WebsitePath::BoilerSwap
+            ) == Role::User
+        );
+        assert!(
+            get_effective_role(
+                &config(),
+                "purdue.pete@purdue.edu",
+                Role::Admin,
+                &WebsitePath::Home
+            ) == Role::Admin
+        );
+    }
+}
@@ -0,0 +1,256 @@
+use super::{
+    database::{get_audit_events, get_user, insert_audit_event},
+    models::{AuditEventPayload, WebsitePath},
+    utilities::get_user_agent,
+};
+use crate::{
+    AppError, AppState, api::microservices::database::schema::columns::accounts::audit_events,
+};
+use axum::http::header::HeaderMap;
+use chrono::{DateTime, Months, Utc};
+use std::sync::Arc;
+use strum_macros::AsRefStr;
+use tracing::warn;
+
+const BUCKET_FORMAT: &str = "%Y-%m";
+const MAX_BUCKETS: u32 = 3;
+const MAX_EVENTS: usize = 50;
+const SECONDS_PER_SHORTEST_MONTH: u32 = 28 * 24 * 60 * 60;
+
+#[derive(AsRefStr, Clone, Copy)]
+#[strum(serialize_all = "snake_case")]
+pub enum AuditEventType {
+    Login,
+    Signup,
+    CodeVerification,
+    PasswordResetRequested,
+    AccountFrozen,
+    AccountUnlocked,
+    LocksCleared,
+    ItemQuotaReset,
+    PasswordReset,
+    PasswordChanged,
+    Logout,
+    SessionRevoked,
+    AllSessionsRevoked,
+    TotpEnabled,
+    TotpDisabled,
+    PasskeyRegistered,
+    PasskeyLogin,
+    OidcLogin,
+    AccountDeletionRequested,
+    AccountDeleted,
+    EmailChangeRequested,
+    EmailChanged,
+    LockTripped,
+}
+
+#[derive(AsRefStr, Clone, Copy)]
+#[strum(serialize_all = "snake_case")]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+    Blocked,
+}
+
+pub struct AuditEvent<'a> {
+    pub email: &'a str,
+    pub event_type: AuditEventType,
+    pub outcome: AuditOutcome,
+    pub hashed_ip: &'a str,
+    pub user_agent: &'a str,
+}
+
+pub struct AuditContext {
+    pub hashed_ip: String,
+    pub user_agent: String,
+    pub website_path: WebsitePath,
+    pub known_user: bool,
+}
+
+impl AuditContext {
+    pub fn new(headers: &HeaderMap, hashed_ip: &str, website_path: &WebsitePath) -> Self {
+        Self {
+            hashed_ip: hashed_ip.to_string(),
+            user_agent: get_user_agent(headers),
+            website_path: website_path.clone(),
+            known_user: false,
+        }
+    }
+
+    pub fn for_session(headers: &HeaderMap, hashed_ip: &str, website_path: &WebsitePath) -> Self {
+        Self {
+            known_user: true,
+            ..Self::new(headers, hashed_ip, website_path)
+        }
+    }
+}
+
+pub async fn record_event(
+    state: Arc<AppState>,
+    context: &AuditContext,
+    email: &str,
+    event_type: AuditEventType,
+    outcome: AuditOutcome,
+) {
+    if email.is_empty() {
+        return;
+    }
+
+    if let Err(error) =
+        insert_account_event(state.clone(), context, email, event_type, outcome).await
+    {
+        warn!(
+            "Failed to record {} audit event: {:?}",
+            event_type.as_ref(),
+            error
+        );
+    }
+}
+
+async fn insert_account_event(
+    state: Arc<AppState>,
+    context: &AuditContext,
+    email: &str,
+    event_type: AuditEventType,
+    outcome: AuditOutcome,
+) -> Result<(), AppError> {
+    if !context.known_user
+        && get_user(state.clone(), email, &context.website_path)
+            .await?
+            .is_none()
+    {
+        return Ok(());
+    }
+
+    let now = Utc::now();
+
+    insert_audit_event(
+        state.clone(),
+        &get_bucket(now),
+        now,
+        &AuditEvent {
+            email,
+            event_type,
+            outcome,
+            hashed_ip: &context.hashed_ip,
+            user_agent: &context.user_agent,
+        },
+        &context.website_path,
+    )
+    .await
+}
+
+pub async fn record_result<T>(
+    state: Arc<AppState>,
+    context: &AuditContext,
+    email: &str,
+    event_type: AuditEventType,
+    result: Result<T, AppError>,
+) -> Result<T, AppError> {
+    match &result {
+        Ok(_) => {
+            record_event(state, context, email, event_type, AuditOutcome::Success).await;
+        }
+        Err(error) if is_rejection(error) => {
+            record_event(state, context, email, event_type, AuditOutcome::Failure).await;
+        }
+        Err(_) => {}
+    }
+
+    result
+}
+
+pub async fn record_failure<T>(
+    state: Arc<AppState>,
+    context: &AuditContext,
+    email: &str,
+    event_type: AuditEventType,
+    result: Result<T, AppError>,
+) -> Result<T, AppError> {
+    if let Err(error) = &result
+        && is_rejection(error)
+    {
+        record_event(state, context, email, event_type, AuditOutcome::Failure).await;
+    }
+
+    result
+}
+
+pub async fn record_lock<T>(
+    state: Arc<AppState>,
+    context: &AuditContext,
+    email: &str,
+    result: Result<T, AppError>,
+) -> Result<T, AppError> {
+    if let Err(error) = &result
+        && is_rejection(error)
+    {
+        record_event(
+            state,
+            context,
+            email,
+            AuditEventType::LockTripped,
+            AuditOutcome::Blocked,
+        )
+        .await;
+    }
+
+    result
+}
+
+pub async fn get_recent_events(
+    state: Arc<AppState>,
+    email: &str,
+    website_path: &WebsitePath,
+) -> Result<Vec<AuditEventPayload>, AppError> {
+    let now = Utc::now();
+    let mut events = Vec::new();
+
+    for offset in 0..MAX_BUCKETS {
+        let remaining = MAX_EVENTS - events.len();
+
+        if remaining == 0 {
+            break;
+        }
+
+        let Some(bucket_time) = now.checked_sub_months(Months::new(offset)) else {
+            break;
+        };
+
+        events.extend(
+            get_audit_events(
+                state.clone(),
+                email,
+                &get_bucket(bucket_time),
+                remaining as i32,
+                website_path,
+            )
+            .await?,
+        );
+    }
+
+    Ok(events)
+}
+
+pub fn get_retained_buckets() -> Vec<String> {
+    let now = Utc::now();
+    let retained_months =
+        audit_events::TTL.parse::<u32>().unwrap_or(0) / SECONDS_PER_SHORTEST_MONTH + 1;
+
+    (0..=retained_months)
+        .filter_map(|offset| now.checked_sub_months(Months::new(offset)))
+        .map(get_bucket)
+        .collect()
+}
+
+fn get_bucket(time: DateTime<Utc>) -> String {
+    time.format(BUCKET_FORMAT).to_string()
+}
+
+fn is_rejection(error: &AppError) -> bool {
+    matches!(
+        error,
+        AppError::Unauthorized(_) | AppError::BadRequest(_) | AppError::TooManyRequests(_)
+    )
+}
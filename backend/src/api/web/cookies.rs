@@ -1,8 +1,5 @@
-use super::{
-    models::{RedisAction, WebsitePath},
-    utilities::get_website_path,
-};
-use crate::{AppError, AppState, api::microservices::redis::remove_id};
+use super::models::{RedisAction, WebsitePath};
+use crate::{AppError, config::Config};
 use axum::http::{
     HeaderValue,
     header::{HeaderMap, SET_COOKIE},
@@ -11,8 +8,19 @@ use axum_extra::extract::CookieJar;
 use cookie::{
     Cookie, CookieBuilder, CookieJar as cookieCookieJar, SameSite::Strict, time::Duration,
 };
+use data_encoding::HEXLOWER;
+use hmac::{Hmac, Mac};
 use once_cell::sync::Lazy;
-use std::sync::Arc;
+use sha2::Sha256;
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const CSRF_COOKIE: &str = "csrf_token";
+
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+pub const PRE_SESSION_COOKIE: &str = "pre_session_id";
 
 static COOKIES_TO_CLEAR: Lazy<Vec<&'static str>> = Lazy::new(|| {
     vec![
@@ -20,12 +28,17 @@ static COOKIES_TO_CLEAR: Lazy<Vec<&'static str>> = Lazy::new(|| {
         RedisAction::Forgot.as_ref(),
         RedisAction::Update.as_ref(),
         RedisAction::Auth.as_ref(),
+        RedisAction::Passkey.as_ref(),
+        RedisAction::Oidc.as_ref(),
     ]
 });
 
 pub static CLEARED_COOKIES_SWAP: Lazy<cookieCookieJar> =
     Lazy::new(|| cleared_cookies_for(WebsitePath::BoilerSwap));
 
+pub static CLEARED_COOKIES_HOME: Lazy<cookieCookieJar> =
+    Lazy::new(|| cleared_cookies_for(WebsitePath::Home));
+
 pub fn cleared_cookies_for(website_path: WebsitePath) -> cookieCookieJar {
     let mut jar = cookieCookieJar::new();
 
@@ -38,29 +51,12 @@ pub fn cleared_cookies_for(website_path: WebsitePath) -> cookieCookieJar {
     jar
 }
 
-pub fn clear_cookies(label: &str) -> HeaderMap {
-    generate_cookie("", "", 0, &get_website_path(label))
-}
-
-pub async fn remove_cookie(
-    state: Arc<AppState>,
-    headers: &HeaderMap,
-    redis_action: RedisAction,
-) -> Result<(), AppError> {
-    let id = get_cookie(headers, redis_action.as_ref());
-
-    if id.is_some() {
-        remove_id(
-            state.clone(),
-            &format!("{}:{}", redis_action.as_ref(), id.expect("is_none failed")),
-        )
-        .await?;
-    }
-
-    Ok(())
+pub fn clear_cookies(config: &Config, website_path: &WebsitePath) -> HeaderMap {
+    generate_cookie(config, "", "", 0, website_path)
 }
 
 pub fn generate_cookie(
+    config: &Config,
     key: &str,
     value: &str,
     ttl_seconds: i64,
@@ -72,6 +68,26 @@ pub fn generate_cookie(
 
     jar.add(new_cookie);
 
+    let bound_value = match value.is_empty() {
+        true => {
+            let pre_session_id = generate_pre_session_id();
+
+            jar.add(build_pre_session_cookie(
+                &pre_session_id,
+                website_path.as_ref(),
+            ));
+
+            pre_session_id
+        }
+        false => value.to_string(),
+    };
+
+    jar.add(build_csrf_cookie(
+        config,
+        &bound_value,
+        website_path.as_ref(),
+    ));
+
     let mut headers = HeaderMap::new();
 
     for cookie in jar.delta() {
@@ -84,6 +100,99 @@ pub fn generate_cookie(
     headers
 }
 
+pub fn generate_secondary_cookie(
+    key: &str,
+    value: &str,
+    ttl_seconds: i64,
+    website_path: &WebsitePath,
+) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+
+    headers.append(
+        SET_COOKIE,
+        HeaderValue::from_str(
+            &build_cookie(key, value, website_path.as_ref(), ttl_seconds)
+                .build()
+                .to_string(),
+        )
+        .unwrap(),
+    );
+
+    headers
+}
+
+pub fn append_cookies(headers: &mut HeaderMap, cookies: HeaderMap) {
+    for cookie in cookies.get_all(SET_COOKIE) {
+        headers.append(SET_COOKIE, cookie.clone());
+    }
+}
+
+pub fn generate_csrf_cookie(
+    config: &Config,
+    headers: &HeaderMap,
+    website_path: &WebsitePath,
+) -> HeaderMap {
+    let mut csrf_headers = HeaderMap::new();
+
+    let bound_value = match get_bound_values(headers).into_iter().next() {
+        Some(bound_value) => bound_value,
+        None => {
+            let pre_session_id = generate_pre_session_id();
+
+            csrf_headers.append(
+                SET_COOKIE,
+                HeaderValue::from_str(
+                    &build_pre_session_cookie(&pre_session_id, website_path.as_ref())
+                        .build()
+                        .to_string(),
+                )
+                .unwrap(),
+            );
+
+            pre_session_id
+        }
+    };
+
+    csrf_headers.append(
+        SET_COOKIE,
+        HeaderValue::from_str(
+            &build_csrf_cookie(config, &bound_value, website_path.as_ref())
+                .build()
+                .to_string(),
+        )
+        .unwrap(),
+    );
+
+    csrf_headers
+}
+
+pub fn verify_csrf(config: &Config, headers: &HeaderMap) -> Result<(), AppError> {
+    let token = headers
+        .get(CSRF_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .ok_or(AppError::Csrf)?;
+
+    if get_cookie(headers, CSRF_COOKIE).as_deref() != Some(token) {
+        return Err(AppError::Csrf);
+    }
+
+    let (nonce, signature) = token.split_once('.').ok_or(AppError::Csrf)?;
+
+    let signature = HEXLOWER
+        .decode(signature.as_bytes())
+        .map_err(|_| AppError::Csrf)?;
+
+    if get_bound_values(headers).iter().any(|bound_value| {
+        get_csrf_mac(config, nonce, bound_value)
+            .verify_slice(&signature)
+            .is_ok()
+    }) {
+        return Ok(());
+    }
+
+    Err(AppError::Csrf)
+}
+
 pub fn get_cookie(headers: &HeaderMap, key: &str) -> Option<String> {
     CookieJar::from_headers(headers)
         .get(key)
@@ -104,14 +213,265 @@ fn build_cookie(
         .max_age(Duration::seconds(ttl_seconds))
 }
 
+fn build_pre_session_cookie(pre_session_id: &str, website_path: &str) -> CookieBuilder<'static> {
+    Cookie::build((PRE_SESSION_COOKIE, pre_session_id.to_owned()))
+        .path(format!("/{}", website_path))
+        .http_only(true)
+        .secure(true)
+        .same_site(Strict)
+}
+
+fn build_csrf_cookie(
+    config: &Config,
+    bound_value: &str,
+    website_path: &str,
+) -> CookieBuilder<'static> {
+    let nonce = Uuid::new_v4().simple().to_string();
+    let signature = HEXLOWER.encode(
+        &get_csrf_mac(config, &nonce, bound_value)
+            .finalize()
+            .into_bytes(),
+    );
+
+    Cookie::build((CSRF_COOKIE, format!("{}.{}", nonce, signature)))
+        .path(format!("/{}", website_path))
+        .http_only(false)
+        .secure(true)
+        .same_site(Strict)
+}
+
+fn get_csrf_mac(config: &Config, nonce: &str, bound_value: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(config.secrets.csrf_secret.as_bytes())
+        .expect("HMAC accepts keys of any length");
+
+    mac.update(nonce.as_bytes());
+    mac.update(b":");
+    mac.update(bound_value.as_bytes());
+
+    mac
+}
+
+fn get_bound_values(headers: &HeaderMap) -> Vec<String> {
+    COOKIES_TO_CLEAR
+        .iter()
+        .chain([&PRE_SESSION_COOKIE])
+        .filter_map(|key| get_cookie(headers, key))
+        .filter(|bound_value| !bound_value.is_empty())
+        .collect()
+}
+
+fn generate_pre_session_id() -> String {
+    Uuid::new_v4().simple().to_string()
+}
+
 fn get_cleared_cookies(website_path: &WebsitePath) -> cookieCookieJar {
     match website_path {
         WebsitePath::BoilerSwap => CLEARED_COOKIES_SWAP.clone(),
-        WebsitePath::Photos => {
-            panic!("Photos has no cookies")
-        }
-        WebsitePath::Home => {
-            panic!("Home has no cookies")
+        WebsitePath::Home => CLEARED_COOKIES_HOME.clone(),
+        WebsitePath::Photos => cookieCookieJar::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::header::COOKIE;
+
+    fn request_headers(set_cookies: &HeaderMap, csrf_header: Option<&str>) -> HeaderMap {
+        let cookies = set_cookies
+            .get_all(SET_COOKIE)
+            .iter()
+            .filter_map(|value| Cookie::parse(value.to_str().ok()?.to_string()).ok())
+            .filter(|cookie| !cookie.value().is_empty() && cookie.name() != "")
+            .map(|cookie| format!("{}={}", cookie.name(), cookie.value()))
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, HeaderValue::from_str(&cookies).unwrap());
+
+        let token = get_cookie(&headers, CSRF_COOKIE).unwrap();
+        headers.insert(
+            CSRF_HEADER,
+            HeaderValue::from_str(csrf_header.unwrap_or(&token)).unwrap(),
+        );
+
+        headers
+    }
+
+    fn replace_cookie(headers: &mut HeaderMap, key: &str, value: &str) {
+        let cookies = headers
+            .get(COOKIE)
+            .and_then(|cookies| cookies.to_str().ok())
+            .unwrap_or_default()
+            .split("; ")
+            .map(|cookie| match cookie.split_once('=') {
+                Some((name, _)) if name == key => format!("{}={}", key, value),
+                _ => cookie.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        headers.insert(COOKIE, HeaderValue::from_str(&cookies).unwrap());
+    }
+
+    #[test]
+    fn session_bound_token_verifies() {
+        let config = Config::for_tests();
+        let set_cookies = generate_cookie(
+            &config,
+            RedisAction::Session.as_ref(),
+            "session",
+            60,
+            &WebsitePath::BoilerSwap,
+        );
+
+        assert!(verify_csrf(&config, &request_headers(&set_cookies, None)).is_ok());
+    }
+
+    #[test]
+    fn token_is_rejected_for_another_session() {
+        let config = Config::for_tests();
+        let set_cookies = generate_cookie(
+            &config,
+            RedisAction::Session.as_ref(),
+            "session",
+            60,
+            &WebsitePath::BoilerSwap,
+        );
+
+        let mut headers = request_headers(&set_cookies, None);
+        replace_cookie(&mut headers, RedisAction::Session.as_ref(), "other");
+
+        assert!(matches!(
+            verify_csrf(&config, &headers),
+            Err(AppError::Csrf)
+        ));
+    }
+
+    #[test]
+    fn anonymous_token_is_bound_to_pre_session_cookie() {
+        let config = Config::for_tests();
+        let set_cookies =
+            generate_csrf_cookie(&config, &HeaderMap::new(), &WebsitePath::BoilerSwap);
+
+        let mut headers = request_headers(&set_cookies, None);
+        assert!(get_cookie(&headers, PRE_SESSION_COOKIE).is_some());
+        assert!(verify_csrf(&config, &headers).is_ok());
+
+        replace_cookie(&mut headers, PRE_SESSION_COOKIE, "other");
+        assert!(matches!(
+            verify_csrf(&config, &headers),
+            Err(AppError::Csrf)
+        ));
+    }
+
+    #[test]
+    fn token_without_bound_cookie_is_rejected() {
+        let config = Config::for_tests();
+        let set_cookies =
+            generate_csrf_cookie(&config, &HeaderMap::new(), &WebsitePath::BoilerSwap);
+        let token = get_cookie(&request_headers(&set_cookies, None), CSRF_COOKIE).unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            COOKIE,
+            HeaderValue::from_str(&format!("{}={}", CSRF_COOKIE, token)).unwrap(),
+        );
+        headers.insert(CSRF_HEADER, HeaderValue::from_str(&token).unwrap());
+
+        assert!(matches!(
+            verify_csrf(&config, &headers),
+            Err(AppError::Csrf)
+        ));
+    }
+
+    #[test]
+    fn mismatched_or_forged_header_is_rejected() {
+        let config = Config::for_tests();
+        let set_cookies = generate_cookie(
+            &config,
+            RedisAction::Session.as_ref(),
+            "session",
+            60,
+            &WebsitePath::BoilerSwap,
+        );
+
+        let headers = request_headers(&set_cookies, Some("nonce.00"));
+        assert!(matches!(
+            verify_csrf(&config, &headers),
+            Err(AppError::Csrf)
+        ));
+
+        let mut headers = request_headers(&set_cookies, None);
+        headers.remove(CSRF_HEADER);
+        assert!(matches!(
+            verify_csrf(&config, &headers),
+            Err(AppError::Csrf)
+        ));
+
+        let mut forged = request_headers(&set_cookies, None);
+        let forged_token = format!("{}.{}", Uuid::new_v4().simple(), "ab".repeat(32));
+        replace_cookie(&mut forged, CSRF_COOKIE, &forged_token);
+        forged.insert(CSRF_HEADER, HeaderValue::from_str(&forged_token).unwrap());
+        assert!(matches!(verify_csrf(&config, &forged), Err(AppError::Csrf)));
+    }
+
+    #[test]
+    fn token_signed_with_another_secret_is_rejected() {
+        let config = Config::for_tests();
+        let set_cookies = generate_cookie(
+            &config,
+            RedisAction::Session.as_ref(),
+            "session",
+            60,
+            &WebsitePath::BoilerSwap,
+        );
+
+        let mut other_config = Config::for_tests();
+        other_config.secrets.csrf_secret = "another-secret".to_string();
+
+        assert!(matches!(
+            verify_csrf(&other_config, &request_headers(&set_cookies, None)),
+            Err(AppError::Csrf)
+        ));
+    }
+
+    #[test]
+    fn cookies_are_scoped_to_their_site() {
+        let config = Config::for_tests();
+
+        for website_path in [WebsitePath::BoilerSwap, WebsitePath::Home] {
+            let set_cookies = generate_cookie(
+                &config,
+                RedisAction::Session.as_ref(),
+                "session",
+                60,
+                &website_path,
+            );
+
+            assert!(set_cookies.get_all(SET_COOKIE).iter().all(|value| {
+                Cookie::parse(value.to_str().unwrap().to_string())
+                    .unwrap()
+                    .path()
+                    == Some(format!("/{}", website_path.as_ref()).as_str())
+            }));
         }
     }
+
+    #[test]
+    fn home_cookies_clear_without_touching_swap() {
+        let cleared = clear_cookies(&Config::for_tests(), &WebsitePath::Home)
+            .get_all(SET_COOKIE)
+            .iter()
+            .filter_map(|value| Cookie::parse(value.to_str().ok()?.to_string()).ok())
+            .collect::<Vec<_>>();
+
+        assert!(cleared.iter().any(|cookie| {
+            cookie.name() == RedisAction::Session.as_ref()
+                && cookie.max_age() == Some(Duration::ZERO)
+                && cookie.path() == Some("/home")
+        }));
+        assert!(cleared.iter().all(|cookie| cookie.path() == Some("/home")));
+    }
 }
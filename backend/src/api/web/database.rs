@@ -0,0 +1,575 @@
+use super::{
+    audit::{AuditEvent, get_retained_buckets},
+    models::{AuditEventPayload, AuditEventRow, RedisAccount, Role, WebsitePath},
+    swap::database::{append_user_items_migration, delete_user_items},
+};
+use crate::{AppError, AppState, api::microservices::database::init::Accounts};
+use chrono::{DateTime, Utc};
+use scylla::{
+    response::{PagingState, query_result::FirstRowError::RowsEmpty},
+    statement::batch::Batch,
+    value::{CqlValue, Row},
+};
+use std::{collections::HashSet, ops::ControlFlow, str::FromStr, sync::Arc};
+use uuid::Uuid;
+
+pub fn get_account_queries<'a>(
+    state: &'a AppState,
+    website_path: &WebsitePath,
+) -> Result<&'a Accounts, AppError> {
+    match website_path {
+        WebsitePath::BoilerSwap => Ok(&state.database_queries.swap_accounts),
+        WebsitePath::Home => Ok(&state.database_queries.home_accounts),
+        WebsitePath::Photos => Err(AppError::Config(format!(
+            "{} has no accounts",
+            website_path.as_ref()
+        ))),
+    }
+}
+
+const AUDIT_MIGRATION_BATCH_SIZE: usize = 100;
+
+fn is_applied(row: &Row) -> bool {
+    matches!(row.columns.first(), Some(Some(CqlValue::Boolean(true))))
+}
+
+pub async fn get_user(
+    state: Arc<AppState>,
+    email: &str,
+    website_path: &WebsitePath,
+) -> Result<Option<(String, bool)>, AppError> {
+    let queries = get_account_queries(&state, website_path)?;
+    let fallback_page_state = PagingState::start();
+
+    let (returned_rows, _) = state
+        .database_session
+        .execute_single_page(&queries.get_user, (email,), fallback_page_state)
+        .await?;
+
+    match returned_rows
+        .into_rows_result()?
+        .first_row::<(String, bool)>()
+    {
+        Ok((password_hash, locked)) => Ok(Some((password_hash, locked))),
+        Err(RowsEmpty) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub async fn insert_user(
+    state: Arc<AppState>,
+    account: &RedisAccount,
+    website_path: &WebsitePath,
+) -> Result<(), AppError> {
+    let queries = get_account_queries(&state, website_path)?;
+    let fallback_page_state = PagingState::start();
+
+    state
+        .database_session
+        .execute_single_page(
+            &queries.insert_user,
+            (account.email.clone(), account.password_hash.clone(), false),
+            fallback_page_state,
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn check_lock(
+    state: Arc<AppState>,
+    email: &str,
+    website_path: &WebsitePath,
+) -> Result<Option<bool>, AppError> {
+    let queries = get_account_queries(&state, website_path)?;
+    let fallback_page_state = PagingState::start();
+
+    let (returned_rows, _) = state
+        .database_session
+        .execute_single_page(&queries.check_lock, (email,), fallback_page_state)
+        .await?;
+
+    match returned_rows.into_rows_result()?.first_row::<(bool,)>() {
+        Ok((locked,)) => Ok(Some(locked)),
+        Err(RowsEmpty) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub async fn get_role(
+    state: Arc<AppState>,
+    email: &str,
+    website_path: &WebsitePath,
+) -> Result<Role, AppError> {
+    let queries = get_account_queries(&state, website_path)?;
+    let fallback_page_state = PagingState::start();
+
+    let (returned_rows, _) = state
+        .database_session
+        .execute_single_page(&queries.get_role, (email,), fallback_page_state)
+        .await?;
+
+    match returned_rows
+        .into_rows_result()?
+        .first_row::<(Option<String>,)>()
+    {
+        Ok((Some(role),)) => Ok(Role::from_str(&role).unwrap_or(Role::User)),
+        Ok((None,)) | Err(RowsEmpty) => Ok(Role::User),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub async fn update_lock(
+    state: Arc<AppState>,
+    email: &str,
+    lock: bool,
+    website_path: &WebsitePath,
+) -> Result<(), AppError> {
+    let queries = get_account_queries(&state, website_path)?;
+    let fallback_page_state = PagingState::start();
+
+    state
+        .database_session
+        .execute_single_page(&queries.update_lock, (lock, email), fallback_page_state)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn unlock_account(
+    state: Arc<AppState>,
+    email: &str,
+    password_hash: &str,
+    website_path: &WebsitePath,
+) -> Result<(), AppError> {
+    let queries = get_account_queries(&state, website_path)?;
+    let fallback_page_state = PagingState::start();
+
+    state
+        .database_session
+        .execute_single_page(
+            &queries.unlock_account,
+            (password_hash, email),
+            fallback_page_state,
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn update_password(
+    state: Arc<AppState>,
+    email: &str,
+    password_hash: &str,
+    website_path: &WebsitePath,
+) -> Result<(), AppError> {
+    let queries = get_account_queries(&state, website_path)?;
+    let fallback_page_state = PagingState::start();
+
+    state
+        .database_session
+        .execute_single_page(
+            &queries.update_password,
+            (password_hash, email),
+            fallback_page_state,
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn get_totp(
+    state: Arc<AppState>,
+    email: &str,
+    website_path: &WebsitePath,
+) -> Result<Option<(String, HashSet<String>)>, AppError> {
+    let queries = get_account_queries(&state, website_path)?;
+    let fallback_page_state = PagingState::start();
+
+    let (returned_rows, _) = state
+        .database_session
+        .execute_single_page(&queries.get_totp, (email,), fallback_page_state)
+        .await?;
+
+    match returned_rows
+        .into_rows_result()?
+        .first_row::<(Option<String>, Option<HashSet<String>>)>()
+    {
+        Ok((Some(secret), recovery_codes)) => {
+            Ok(Some((secret, recovery_codes.unwrap_or_default())))
+        }
+        Ok((None, _)) | Err(RowsEmpty) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub async fn update_totp(
+    state: Arc<AppState>,
+    email: &str,
+    secret: Option<&str>,
+    recovery_codes: Option<HashSet<String>>,
+    website_path: &WebsitePath,
+) -> Result<(), AppError> {
+    let queries = get_account_queries(&state, website_path)?;
+    let fallback_page_state = PagingState::start();
+
+    state
+        .database_session
+        .execute_single_page(
+            &queries.update_totp,
+            (secret, recovery_codes, email),
+            fallback_page_state,
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn remove_recovery_code(
+    state: Arc<AppState>,
+    email: &str,
+    recovery_codes: &HashSet<String>,
+    recovery_code_hash: &str,
+    website_path: &WebsitePath,
+) -> Result<bool, AppError> {
+    let queries = get_account_queries(&state, website_path)?;
+    let fallback_page_state = PagingState::start();
+
+    let mut remaining_codes = recovery_codes.clone();
+
+    if !remaining_codes.remove(recovery_code_hash) {
+        return Ok(false);
+    }
+
+    let (returned_rows, _) = state
+        .database_session
+        .execute_single_page(
+            &queries.remove_recovery_code,
+            (remaining_codes, email, recovery_codes),
+            fallback_page_state,
+        )
+        .await?;
+
+    Ok(is_applied(
+        &returned_rows.into_rows_result()?.first_row::<Row>()?,
+    ))
+}
+
+pub async fn get_passkeys(
+    state: Arc<AppState>,
+    email: &str,
+    website_path: &WebsitePath,
+) -> Result<Vec<String>, AppError> {
+    let queries = get_account_queries(&state, website_path)?;
+    let fallback_page_state = PagingState::start();
+
+    let (returned_rows, _) = state
+        .database_session
+        .execute_single_page(&queries.get_passkeys, (email,), fallback_page_state)
+        .await?;
+
+    Ok(returned_rows
+        .into_rows_result()?
+        .rows::<(String,)>()?
+        .map(|row| row.map(|(passkey,)| passkey))
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+pub async fn insert_passkey(
+    state: Arc<AppState>,
+    email: &str,
+    credential_id: &str,
+    passkey: &str,
+    website_path: &WebsitePath,
+) -> Result<(), AppError> {
+    let queries = get_account_queries(&state, website_path)?;
+    let fallback_page_state = PagingState::start();
+
+    state
+        .database_session
+        .execute_single_page(
+            &queries.insert_passkey,
+            (email, credential_id, passkey),
+            fallback_page_state,
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn delete_user_data(
+    state: Arc<AppState>,
+    email: &str,
+    website_path: &WebsitePath,
+) -> Result<(), AppError> {
+    let queries = get_account_queries(&state, website_path)?;
+    let fallback_page_state = PagingState::start();
+
+    if *website_path == WebsitePath::BoilerSwap {
+        delete_user_items(state.clone(), email).await?;
+    }
+
+    for statement in [&queries.delete_passkeys, &queries.delete_user] {
+        state
+            .database_session
+            .execute_single_page(statement, (email,), fallback_page_state.clone())
+            .await?;
+    }
+
+    delete_audit_events(state.clone(), email, get_retained_buckets(), website_path).await
+}
+
+pub async fn migrate_user(
+    state: Arc<AppState>,
+    email: &str,
+    new_email: &str,
+    website_path: &WebsitePath,
+) -> Result<Vec<Uuid>, AppError> {
+    let queries = get_account_queries(&state, website_path)?;
+    let fallback_page_state = PagingState::start();
+
+    let (returned_rows, _) = state
+        .database_session
+        .execute_single_page(&queries.get_account, (email,), fallback_page_state.clone())
+        .await?;
+
+    let (password_hash, locked, totp_secret, recovery_codes, role) =
+        match returned_rows.into_rows_result()?.first_row::<(
+            Option<String>,
+            Option<bool>,
+            Option<String>,
+            Option<HashSet<String>>,
+            Option<String>,
+        )>() {
+            Ok(account) => account,
+            Err(RowsEmpty) => return Err(AppError::Unauthorized("Unable to verify".to_string())),
+            Err(e) => return Err(e.into()),
+        };
+
+    let (returned_rows, _) = state
+        .database_session
+        .execute_single_page(
+            &queries.insert_account,
+            (
+                new_email,
+                password_hash,
+                locked,
+                totp_secret,
+                recovery_codes,
+                role,
+            ),
+            fallback_page_state.clone(),
+        )
+        .await?;
+
+    if !is_applied(&returned_rows.into_rows_result()?.first_row::<Row>()?) {
+        return Err(AppError::BadRequest("Email unavailable".to_string()));
+    }
+
+    let mut batch: Batch = Default::default();
+    let mut batch_values: Vec<Vec<Option<CqlValue>>> = Vec::new();
+
+    let (returned_rows, _) = state
+        .database_session
+        .execute_single_page(
+            &queries.get_passkey_records,
+            (email,),
+            fallback_page_state.clone(),
+        )
+        .await?;
+
+    for row in returned_rows
+        .into_rows_result()?
+        .rows::<(String, String)>()?
+    {
+        let (credential_id, passkey) = row?;
+
+        batch.append_statement(queries.insert_passkey.clone());
+        batch_values.push(vec![
+            Some(CqlValue::Text(new_email.to_string())),
+            Some(CqlValue::Text(credential_id)),
+            Some(CqlValue::Text(passkey)),
+        ]);
+    }
+
+    let item_ids = match website_path {
+        WebsitePath::BoilerSwap => {
+            append_user_items_migration(
+                state.clone(),
+                &mut batch,
+                &mut batch_values,
+                email,
+                new_email,
+            )
+            .await?
+        }
+        _ => Vec::new(),
+    };
+
+    for statement in [&queries.delete_passkeys, &queries.delete_user] {
+        batch.append_statement(statement.clone());
+        batch_values.push(vec![Some(CqlValue::Text(email.to_string()))]);
+    }
+
+    if let Err(e) = state.database_session.batch(&batch, &batch_values).await {
+        state
+            .database_session
+            .execute_single_page(&queries.delete_user, (new_email,), fallback_page_state)
+            .await?;
+
+        return Err(e.into());
+    }
+
+    Ok(item_ids)
+}
+
+// Audit events are only ever inserted, except that the email partition key forces an email
+// change to move them to the new address and an account deletion to drop them
+async fn delete_audit_events(
+    state: Arc<AppState>,
+    email: &str,
+    buckets: Vec<String>,
+    website_path: &WebsitePath,
+) -> Result<(), AppError> {
+    let queries = get_account_queries(&state, website_path)?;
+
+    state
+        .database_session
+        .execute_single_page(
+            &queries.delete_audit_events,
+            (email, buckets),
+            PagingState::start(),
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn migrate_audit_events(
+    state: Arc<AppState>,
+    email: &str,
+    new_email: &str,
+    website_path: &WebsitePath,
+) -> Result<(), AppError> {
+    let queries = get_account_queries(&state, website_path)?;
+    let buckets = get_retained_buckets();
+    let mut events = Vec::new();
+
+    for bucket in &buckets {
+        let mut paging_state = PagingState::start();
+
+        loop {
+            let (returned_rows, paging_state_response) = state
+                .database_session
+                .execute_single_page(
+                    &queries.get_audit_events,
+                    (email, bucket, i32::MAX),
+                    paging_state,
+                )
+                .await?;
+
+            events.extend(
+                returned_rows
+                    .into_rows_result()?
+                    .rows::<AuditEventRow>()?
+                    .map(|row| row.map(|row| (bucket.clone(), row)))
+                    .collect::<Result<Vec<_>, _>>()?,
+            );
+
+            match paging_state_response.into_paging_control_flow() {
+                ControlFlow::Break(()) => break,
+                ControlFlow::Continue(new_paging_state) => paging_state = new_paging_state,
+            }
+        }
+    }
+
+    for chunk in events.chunks(AUDIT_MIGRATION_BATCH_SIZE) {
+        let mut batch: Batch = Default::default();
+        let mut batch_values = Vec::new();
+
+        for (bucket, (event_time, event_type, outcome, hashed_ip, user_agent)) in chunk {
+            batch.append_statement(queries.insert_audit_event.clone());
+            batch_values.push((
+                new_email,
+                bucket,
+                event_time,
+                Uuid::new_v4(),
+                event_type,
+                outcome,
+                hashed_ip,
+                user_agent,
+            ));
+        }
+
+        state.database_session.batch(&batch, &batch_values).await?;
+    }
+
+    delete_audit_events(state.clone(), email, buckets, website_path).await
+}
+
+pub async fn insert_audit_event(
+    state: Arc<AppState>,
+    bucket: &str,
+    event_time: DateTime<Utc>,
+    event: &AuditEvent<'_>,
+    website_path: &WebsitePath,
+) -> Result<(), AppError> {
+    let queries = get_account_queries(&state, website_path)?;
+    let fallback_page_state = PagingState::start();
+
+    state
+        .database_session
+        .execute_single_page(
+            &queries.insert_audit_event,
+            (
+                event.email,
+                bucket,
+                event_time,
+                Uuid::new_v4(),
+                event.event_type.as_ref(),
+                event.outcome.as_ref(),
+                event.hashed_ip,
+                event.user_agent,
+            ),
+            fallback_page_state,
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn get_audit_events(
+    state: Arc<AppState>,
+    email: &str,
+    bucket: &str,
+    limit: i32,
+    website_path: &WebsitePath,
+) -> Result<Vec<AuditEventPayload>, AppError> {
+    let queries = get_account_queries(&state, website_path)?;
+    let fallback_page_state = PagingState::start();
+
+    let (returned_rows, _) = state
+        .database_session
+        .execute_single_page(
+            &queries.get_audit_events,
+            (email, bucket, limit),
+            fallback_page_state,
+        )
+        .await?;
+
+    Ok(returned_rows
+        .into_rows_result()?
+        .rows::<AuditEventRow>()?
+        .map(|row| {
+            row.map(
+                |(event_time, event_type, outcome, hashed_ip, user_agent)| AuditEventPayload {
+                    created_at: event_time.timestamp_millis(),
+                    event_type,
+                    outcome,
+                    hashed_ip,
+                    user_agent,
+                },
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?)
+}
@@ -1,18 +1,43 @@
 use super::{
-    cookies::{clear_cookies, remove_cookie},
+    account::{
+        change_password, finish_account_deletion, finish_email_change, start_account_deletion,
+        start_email_change,
+    },
+    admin::{
+        check_admin_session, clear_lock_counters, delete_item, force_freeze, force_unlock,
+        get_user_overview, reset_item_quota,
+    },
+    audit::{
+        AuditContext, AuditEventType, AuditOutcome, get_recent_events, record_event,
+        record_failure, record_lock, record_result,
+    },
+    cookies::{append_cookies, clear_cookies, generate_csrf_cookie, generate_secondary_cookie},
     locks::{
-        check_auth_locks, check_forgot_locks, freeze_account, is_home_locked,
-        prepare_resend_and_check_locks, unfreeze_account,
+        check_auth_locks, check_forgot_locks, freeze_account, prepare_resend_and_check_locks,
+        redeem_freeze_link, unfreeze_account,
+    },
+    models::{
+        Account, Action, METRICS_ROUTE, OidcCallback, PHOTOS_PREFIX, PasswordChange, RedisAccount,
+        RedisAction, Token, WebsitePath,
+    },
+    oidc::{finish_oidc_login, start_oidc_login, take_oidc_challenge, verify_oidc_callback},
+    passkeys::{
+        finish_passkey_login, finish_passkey_registration, start_passkey_login,
+        start_passkey_registration, take_passkey_challenge,
     },
-    models::{Account, METRICS_ROUTE, PHOTOS_PREFIX, RedisAction, Token, WebsitePath},
     sessions::{
-        create_forgot_redis_account, create_session, create_temporary_session,
-        try_create_redis_account, try_get_redis_account,
+        check_session, create_forgot_redis_account, create_session, create_temporary_session,
+        delete_all_sessions, end_session, list_sessions, revoke_session, try_create_redis_account,
+        try_get_redis_account,
+    },
+    totp::{confirm_totp_enrollment, remove_totp_enrollment, start_totp_enrollment},
+    utilities::{
+        get_hashed_ip, get_key, get_network_hash, get_pending_account, get_user_agent,
+        get_website_path,
     },
-    utilities::{get_hashed_ip, get_key, get_website_path},
     verify::{
-        check_account, check_email, check_resend, check_token, check_token_content,
-        is_request_authorized,
+        check_account, check_email, check_resend, check_token, check_token_content, is_code_format,
+        is_request_authorized, is_second_factor_format, normalize_email,
     },
 };
 use crate::{
@@ -21,13 +46,15 @@ use crate::{
 };
 use axum::{
     Extension, Json,
-    extract::{ConnectInfo, Request, State},
+    extract::{ConnectInfo, Path, Request, State},
     http::{StatusCode, header::HeaderMap},
     middleware::Next,
     response::IntoResponse,
 };
 use std::{net::SocketAddr, sync::Arc};
 use tracing::info;
+use uuid::Uuid;
+use webauthn_rs::prelude::{PublicKeyCredential, RegisterPublicKeyCredential};
 
 pub async fn api_token_check(
     headers: HeaderMap,
@@ -57,47 +84,83 @@ pub async fn forgot_handler(
     headers: HeaderMap,
     ConnectInfo(address): ConnectInfo<SocketAddr>,
     State(state): State<Arc<AppState>>,
-    Json(payload): Json<Token>,
+    Json(mut payload): Json<Token>,
 ) -> Result<impl IntoResponse, AppError> {
-    check_email(&payload.token)?;
+    payload.token = normalize_email(&payload.token);
+
+    let website_path = get_website_path(&label)?;
+    let hashed_ip = get_hashed_ip(state.clone(), &headers, address.ip(), &website_path).await?;
 
-    let website_path = get_website_path(&label);
-    let hashed_ip = get_hashed_ip(&headers, address.ip());
+    let audit_context = AuditContext::new(&headers, &hashed_ip, &website_path);
 
-    check_forgot_locks(
+    check_email(state.clone(), &payload.token, &website_path)?;
+
+    record_lock(
         state.clone(),
-        &hashed_ip,
-        website_path.as_ref(),
+        &audit_context,
         &payload.token,
+        check_forgot_locks(
+            state.clone(),
+            &hashed_ip,
+            website_path.as_ref(),
+            &payload.token,
+        )
+        .await,
     )
     .await?;
 
-    let redis_account = create_forgot_redis_account(payload.token);
+    let redis_account = RedisAccount {
+        locale: Some(state.email_templates.negotiate_locale(&headers)),
+        ..create_forgot_redis_account(payload.token)
+    };
 
-    Ok((
-        StatusCode::OK,
-        create_temporary_session(
-            state.clone(),
-            &None,
-            &redis_account,
-            &RedisAction::Forgot,
-            &Some(get_key(RedisAction::LockedForgot, &hashed_ip)),
-            &Some(get_key(RedisAction::LockedCode, &hashed_ip)),
-            &website_path,
-        )
-        .await?,
+    let session_headers = create_temporary_session(
+        state.clone(),
+        &None,
+        &redis_account,
+        &RedisAction::Forgot,
+        &Some(get_key(RedisAction::LockedForgot, &hashed_ip)),
+        &Some(get_key(RedisAction::LockedCode, &hashed_ip)),
+        &website_path,
     )
-        .into_response())
+    .await?;
+
+    record_event(
+        state.clone(),
+        &audit_context,
+        &redis_account.email,
+        AuditEventType::PasswordResetRequested,
+        AuditOutcome::Success,
+    )
+    .await;
+
+    Ok((StatusCode::OK, session_headers).into_response())
 }
 
 pub async fn delete_handler(
     Extension(label): Extension<String>,
     headers: HeaderMap,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
     State(state): State<Arc<AppState>>,
 ) -> Result<impl IntoResponse, AppError> {
-    remove_cookie(state.clone(), &headers, RedisAction::Session).await?;
+    let website_path = get_website_path(&label)?;
+
+    if let Some(email) = end_session(state.clone(), &headers, &website_path).await? {
+        record_event(
+            state.clone(),
+            &AuditContext::new(
+                &headers,
+                &get_hashed_ip(state.clone(), &headers, address.ip(), &website_path).await?,
+                &website_path,
+            ),
+            &email,
+            AuditEventType::Logout,
+            AuditOutcome::Success,
+        )
+        .await;
+    }
 
-    Ok((StatusCode::OK, clear_cookies(&label)).into_response())
+    Ok((StatusCode::OK, clear_cookies(&state.config, &website_path)).into_response())
 }
 
 pub async fn verify_handler(
@@ -107,7 +170,7 @@ pub async fn verify_handler(
     State(state): State<Arc<AppState>>,
     Json(payload): Json<Token>,
 ) -> Result<impl IntoResponse, AppError> {
-    let website_path = get_website_path(&label);
+    let website_path = get_website_path(&label)?;
 
     let verified_result = check_token(
         state.clone(),
@@ -116,14 +179,34 @@ pub async fn verify_handler(
         &website_path,
     )
     .await?;
-    check_token_content(&verified_result.redis_action, &payload.token)?;
 
-    let redis_account = try_get_redis_account(
+    let hashed_ip = get_hashed_ip(state.clone(), &headers, address.ip(), &website_path).await?;
+    let audit_context = AuditContext::new(&headers, &hashed_ip, &website_path);
+    let pending_email = get_pending_account(&verified_result)
+        .map(|account| account.email)
+        .unwrap_or_default();
+
+    let redis_account = record_failure(
         state.clone(),
-        &verified_result,
-        &payload.token,
-        &get_hashed_ip(&headers, address.ip()),
-        &website_path,
+        &audit_context,
+        &pending_email,
+        match verified_result.redis_action {
+            RedisAction::Update => AuditEventType::PasswordReset,
+            _ => AuditEventType::CodeVerification,
+        },
+        match check_token_content(state.clone(), &verified_result, &payload.token) {
+            Ok(()) => {
+                try_get_redis_account(
+                    state.clone(),
+                    &verified_result,
+                    &payload.token,
+                    &hashed_ip,
+                    &website_path,
+                )
+                .await
+            }
+            Err(error) => Err(error),
+        },
     )
     .await?;
 
@@ -131,6 +214,15 @@ pub async fn verify_handler(
         RedisAction::Forgot => {
             freeze_account(state.clone(), &redis_account.email, &website_path).await?;
 
+            record_event(
+                state.clone(),
+                &audit_context,
+                &redis_account.email,
+                AuditEventType::AccountFrozen,
+                AuditOutcome::Success,
+            )
+            .await;
+
             return Ok((
                 StatusCode::OK,
                 create_temporary_session(
@@ -147,22 +239,49 @@ pub async fn verify_handler(
                 .into_response());
         }
         RedisAction::Update => {
-            unfreeze_account(
+            record_result(
                 state.clone(),
+                &audit_context,
                 &redis_account.email,
-                &payload.token,
-                &website_path,
+                AuditEventType::PasswordReset,
+                unfreeze_account(
+                    state.clone(),
+                    &redis_account.email,
+                    &payload.token,
+                    &website_path,
+                )
+                .await,
             )
             .await?;
         }
         _ => {}
     }
 
-    Ok((
-        StatusCode::OK,
-        create_session(state.clone(), &redis_account, &website_path).await?,
+    let session_headers = create_session(
+        state.clone(),
+        &redis_account,
+        &hashed_ip,
+        &get_network_hash(&headers, address.ip(), &state.config),
+        &get_user_agent(&headers),
+        &website_path,
     )
-        .into_response())
+    .await?;
+
+    if verified_result.redis_action == RedisAction::Auth {
+        record_event(
+            state.clone(),
+            &audit_context,
+            &redis_account.email,
+            match redis_account.action {
+                Action::Signup => AuditEventType::Signup,
+                _ => AuditEventType::Login,
+            },
+            AuditOutcome::Success,
+        )
+        .await;
+    }
+
+    Ok((StatusCode::OK, session_headers).into_response())
 }
 
 pub async fn authenticate_handler(
@@ -170,18 +289,39 @@ pub async fn authenticate_handler(
     headers: HeaderMap,
     ConnectInfo(address): ConnectInfo<SocketAddr>,
     State(state): State<Arc<AppState>>,
-    Json(payload): Json<Account>,
+    Json(mut payload): Json<Account>,
 ) -> Result<impl IntoResponse, AppError> {
-    let website_path = get_website_path(&label);
-    let hashed_ip = get_hashed_ip(&headers, address.ip());
+    payload.email = normalize_email(&payload.email);
+
+    let website_path = get_website_path(&label)?;
+    let hashed_ip = get_hashed_ip(state.clone(), &headers, address.ip(), &website_path).await?;
+    let audit_context = AuditContext::new(&headers, &hashed_ip, &website_path);
     info!("1");
-    check_auth_locks(state.clone(), &hashed_ip, website_path.as_ref(), &payload).await?;
+    record_lock(
+        state.clone(),
+        &audit_context,
+        &payload.email,
+        check_auth_locks(state.clone(), &hashed_ip, website_path.as_ref(), &payload).await,
+    )
+    .await?;
     info!("2");
-    check_account(&payload)?;
+    check_account(state.clone(), &payload, &website_path)?;
     info!("3");
 
-    let redis_account =
-        try_create_redis_account(state.clone(), &hashed_ip, &website_path, &payload).await?;
+    let redis_account = RedisAccount {
+        locale: Some(state.email_templates.negotiate_locale(&headers)),
+        ..record_failure(
+            state.clone(),
+            &audit_context,
+            &payload.email,
+            match payload.action {
+                Action::Signup => AuditEventType::Signup,
+                _ => AuditEventType::Login,
+            },
+            try_create_redis_account(state.clone(), &hashed_ip, &website_path, &payload).await,
+        )
+        .await?
+    };
     info!("4");
 
     Ok((
@@ -206,7 +346,7 @@ pub async fn resend_handler(
     ConnectInfo(address): ConnectInfo<SocketAddr>,
     State(state): State<Arc<AppState>>,
 ) -> Result<impl IntoResponse, AppError> {
-    let website_path = get_website_path(&label);
+    let website_path = get_website_path(&label)?;
 
     let verified_result = check_token(
         state.clone(),
@@ -216,15 +356,23 @@ pub async fn resend_handler(
     )
     .await?;
 
-    let hashed_ip = get_hashed_ip(&headers, address.ip());
+    let hashed_ip = get_hashed_ip(state.clone(), &headers, address.ip(), &website_path).await?;
 
     check_resend(&verified_result)?;
 
-    let redis_account = prepare_resend_and_check_locks(
+    let redis_account = record_lock(
         state.clone(),
-        &hashed_ip,
-        website_path.as_ref(),
-        &verified_result,
+        &AuditContext::new(&headers, &hashed_ip, &website_path),
+        &get_pending_account(&verified_result)
+            .map(|account| account.email)
+            .unwrap_or_default(),
+        prepare_resend_and_check_locks(
+            state.clone(),
+            &hashed_ip,
+            website_path.as_ref(),
+            &verified_result,
+        )
+        .await,
     )
     .await?;
 
@@ -244,12 +392,751 @@ pub async fn resend_handler(
         .into_response())
 }
 
-pub async fn visitors_handler(
+pub async fn freeze_handler(
+    Extension(label): Extension<String>,
     headers: HeaderMap,
     ConnectInfo(address): ConnectInfo<SocketAddr>,
     State(state): State<Arc<AppState>>,
+    Json(payload): Json<Token>,
 ) -> Result<impl IntoResponse, AppError> {
-    is_home_locked(state.clone(), &get_hashed_ip(&headers, address.ip())).await?;
+    let website_path = get_website_path(&label)?;
+    let hashed_ip = get_hashed_ip(state.clone(), &headers, address.ip(), &website_path).await?;
+
+    let email = redeem_freeze_link(state.clone(), &payload.token, &website_path)
+        .await?
+        .ok_or(AppError::Unauthorized("Unable to verify".to_string()))?;
+
+    freeze_account(state.clone(), &email, &website_path).await?;
+
+    record_event(
+        state.clone(),
+        &AuditContext::new(&headers, &hashed_ip, &website_path),
+        &email,
+        AuditEventType::AccountFrozen,
+        AuditOutcome::Success,
+    )
+    .await;
+
+    Ok((StatusCode::OK, clear_cookies(&state.config, &website_path)).into_response())
+}
 
+pub async fn csrf_handler(
+    Extension(label): Extension<String>,
+    headers: HeaderMap,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok((
+        StatusCode::OK,
+        generate_csrf_cookie(&state.config, &headers, &get_website_path(&label)?),
+    )
+        .into_response())
+}
+
+pub async fn visitors_handler(
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
     Ok((StatusCode::OK, get_visitors_payload(state.clone()).await?).into_response())
 }
+
+pub async fn sessions_handler(
+    Extension(label): Extension<String>,
+    headers: HeaderMap,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let website_path = get_website_path(&label)?;
+
+    let session = check_session(state.clone(), &headers, &website_path).await?;
+
+    Ok((
+        StatusCode::OK,
+        session.headers,
+        Json(
+            list_sessions(
+                state.clone(),
+                &session.id,
+                &session.record.email,
+                &website_path,
+            )
+            .await?,
+        ),
+    )
+        .into_response())
+}
+
+pub async fn revoke_session_handler(
+    Extension(label): Extension<String>,
+    headers: HeaderMap,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Path(session_handle): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let website_path = get_website_path(&label)?;
+
+    let session = check_session(state.clone(), &headers, &website_path).await?;
+
+    let revoked_id = revoke_session(
+        state.clone(),
+        &session.record.email,
+        &session_handle,
+        &website_path,
+    )
+    .await?;
+
+    if revoked_id.is_some() {
+        record_event(
+            state.clone(),
+            &AuditContext::for_session(
+                &headers,
+                &get_hashed_ip(state.clone(), &headers, address.ip(), &website_path).await?,
+                &website_path,
+            ),
+            &session.record.email,
+            AuditEventType::SessionRevoked,
+            AuditOutcome::Success,
+        )
+        .await;
+    }
+
+    match revoked_id {
+        Some(revoked_id) if revoked_id == session.id => {
+            Ok((StatusCode::OK, clear_cookies(&state.config, &website_path)).into_response())
+        }
+        Some(_) => Ok((StatusCode::OK, session.headers).into_response()),
+        None => Err(AppError::BadRequest("Session not found".to_string())),
+    }
+}
+
+pub async fn revoke_all_sessions_handler(
+    Extension(label): Extension<String>,
+    headers: HeaderMap,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let website_path = get_website_path(&label)?;
+
+    let session = check_session(state.clone(), &headers, &website_path).await?;
+
+    delete_all_sessions(
+        state.clone(),
+        website_path.as_ref(),
+        RedisAction::Session.as_ref(),
+        RedisAction::SessionStore.as_ref(),
+        &session.record.email,
+    )
+    .await?;
+
+    record_event(
+        state.clone(),
+        &AuditContext::for_session(
+            &headers,
+            &get_hashed_ip(state.clone(), &headers, address.ip(), &website_path).await?,
+            &website_path,
+        ),
+        &session.record.email,
+        AuditEventType::AllSessionsRevoked,
+        AuditOutcome::Success,
+    )
+    .await;
+
+    Ok((StatusCode::OK, clear_cookies(&state.config, &website_path)).into_response())
+}
+
+pub async fn totp_enroll_handler(
+    Extension(label): Extension<String>,
+    headers: HeaderMap,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let website_path = get_website_path(&label)?;
+
+    let session = check_session(state.clone(), &headers, &website_path).await?;
+
+    Ok((
+        StatusCode::OK,
+        session.headers,
+        Json(start_totp_enrollment(state.clone(), &session.record.email, &website_path).await?),
+    )
+        .into_response())
+}
+
+pub async fn totp_confirm_handler(
+    Extension(label): Extension<String>,
+    headers: HeaderMap,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<Token>,
+) -> Result<impl IntoResponse, AppError> {
+    let website_path = get_website_path(&label)?;
+
+    let session = check_session(state.clone(), &headers, &website_path).await?;
+
+    if !is_code_format(&payload.token) {
+        return Err(AppError::Unauthorized("Unable to verify".to_string()));
+    }
+
+    let hashed_ip = get_hashed_ip(state.clone(), &headers, address.ip(), &website_path).await?;
+
+    Ok((
+        StatusCode::OK,
+        session.headers,
+        Json(
+            record_result(
+                state.clone(),
+                &AuditContext::for_session(&headers, &hashed_ip, &website_path),
+                &session.record.email,
+                AuditEventType::TotpEnabled,
+                confirm_totp_enrollment(
+                    state.clone(),
+                    &session.record.email,
+                    &payload.token,
+                    &hashed_ip,
+                    &website_path,
+                )
+                .await,
+            )
+            .await?,
+        ),
+    )
+        .into_response())
+}
+
+pub async fn totp_disenroll_handler(
+    Extension(label): Extension<String>,
+    headers: HeaderMap,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<Token>,
+) -> Result<impl IntoResponse, AppError> {
+    let website_path = get_website_path(&label)?;
+
+    let session = check_session(state.clone(), &headers, &website_path).await?;
+
+    if !is_second_factor_format(&payload.token) {
+        return Err(AppError::Unauthorized("Unable to verify".to_string()));
+    }
+
+    let hashed_ip = get_hashed_ip(state.clone(), &headers, address.ip(), &website_path).await?;
+
+    record_result(
+        state.clone(),
+        &AuditContext::for_session(&headers, &hashed_ip, &website_path),
+        &session.record.email,
+        AuditEventType::TotpDisabled,
+        remove_totp_enrollment(
+            state.clone(),
+            &session.record.email,
+            &payload.token,
+            &hashed_ip,
+            &website_path,
+        )
+        .await,
+    )
+    .await?;
+
+    Ok((StatusCode::OK, session.headers).into_response())
+}
+
+pub async fn passkey_register_start_handler(
+    Extension(label): Extension<String>,
+    headers: HeaderMap,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let website_path = get_website_path(&label)?;
+
+    let session = check_session(state.clone(), &headers, &website_path).await?;
+
+    Ok((
+        StatusCode::OK,
+        session.headers,
+        Json(
+            start_passkey_registration(state.clone(), &session.record.email, &website_path).await?,
+        ),
+    )
+        .into_response())
+}
+
+pub async fn passkey_register_finish_handler(
+    Extension(label): Extension<String>,
+    headers: HeaderMap,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<RegisterPublicKeyCredential>,
+) -> Result<impl IntoResponse, AppError> {
+    let website_path = get_website_path(&label)?;
+
+    let session = check_session(state.clone(), &headers, &website_path).await?;
+
+    record_result(
+        state.clone(),
+        &AuditContext::for_session(
+            &headers,
+            &get_hashed_ip(state.clone(), &headers, address.ip(), &website_path).await?,
+            &website_path,
+        ),
+        &session.record.email,
+        AuditEventType::PasskeyRegistered,
+        finish_passkey_registration(
+            state.clone(),
+            &session.record.email,
+            &payload,
+            &website_path,
+        )
+        .await,
+    )
+    .await?;
+
+    Ok((StatusCode::OK, session.headers).into_response())
+}
+
+pub async fn passkey_login_start_handler(
+    Extension(label): Extension<String>,
+    headers: HeaderMap,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    Json(mut payload): Json<Token>,
+) -> Result<impl IntoResponse, AppError> {
+    payload.token = normalize_email(&payload.token);
+
+    let website_path = get_website_path(&label)?;
+
+    check_email(state.clone(), &payload.token, &website_path)?;
+
+    let (cookie, challenge) = start_passkey_login(
+        state.clone(),
+        &payload.token,
+        &get_hashed_ip(state.clone(), &headers, address.ip(), &website_path).await?,
+        &website_path,
+    )
+    .await?;
+
+    Ok((StatusCode::OK, cookie, Json(challenge)).into_response())
+}
+
+pub async fn passkey_login_finish_handler(
+    Extension(label): Extension<String>,
+    headers: HeaderMap,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<PublicKeyCredential>,
+) -> Result<impl IntoResponse, AppError> {
+    let website_path = get_website_path(&label)?;
+    let hashed_ip = get_hashed_ip(state.clone(), &headers, address.ip(), &website_path).await?;
+
+    let challenge = take_passkey_challenge(state.clone(), &headers, &website_path).await?;
+    let email = challenge.email.clone();
+
+    Ok((
+        StatusCode::OK,
+        record_result(
+            state.clone(),
+            &AuditContext::new(&headers, &hashed_ip, &website_path),
+            &email,
+            AuditEventType::PasskeyLogin,
+            finish_passkey_login(
+                state.clone(),
+                challenge,
+                &payload,
+                &hashed_ip,
+                &get_network_hash(&headers, address.ip(), &state.config),
+                &get_user_agent(&headers),
+                &website_path,
+            )
+            .await,
+        )
+        .await?,
+    )
+        .into_response())
+}
+
+pub async fn oidc_start_handler(
+    Extension(label): Extension<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let (cookie, redirect) = start_oidc_login(state.clone(), &get_website_path(&label)?).await?;
+
+    Ok((StatusCode::OK, cookie, Json(redirect)).into_response())
+}
+
+pub async fn oidc_callback_handler(
+    Extension(label): Extension<String>,
+    headers: HeaderMap,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<OidcCallback>,
+) -> Result<impl IntoResponse, AppError> {
+    let website_path = get_website_path(&label)?;
+    let hashed_ip = get_hashed_ip(state.clone(), &headers, address.ip(), &website_path).await?;
+
+    let challenge = take_oidc_challenge(state.clone(), &headers, &website_path).await?;
+    let email = verify_oidc_callback(&state.config, &challenge, &payload, &website_path).await?;
+
+    let (session_headers, result) = record_result(
+        state.clone(),
+        &AuditContext::new(&headers, &hashed_ip, &website_path),
+        &email,
+        AuditEventType::OidcLogin,
+        finish_oidc_login(
+            state.clone(),
+            &email,
+            state.email_templates.negotiate_locale(&headers),
+            &hashed_ip,
+            &get_network_hash(&headers, address.ip(), &state.config),
+            &get_user_agent(&headers),
+            &website_path,
+        )
+        .await,
+    )
+    .await?;
+
+    Ok((StatusCode::OK, session_headers, Json(result)).into_response())
+}
+
+pub async fn delete_account_handler(
+    Extension(label): Extension<String>,
+    headers: HeaderMap,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let website_path = get_website_path(&label)?;
+
+    let session = check_session(state.clone(), &headers, &website_path).await?;
+
+    let hashed_ip = get_hashed_ip(state.clone(), &headers, address.ip(), &website_path).await?;
+
+    record_result(
+        state.clone(),
+        &AuditContext::for_session(&headers, &hashed_ip, &website_path),
+        &session.record.email,
+        AuditEventType::AccountDeletionRequested,
+        start_account_deletion(
+            state.clone(),
+            &session,
+            &hashed_ip,
+            state.email_templates.negotiate_locale(&headers),
+            &website_path,
+        )
+        .await,
+    )
+    .await?;
+
+    Ok((StatusCode::OK, session.headers).into_response())
+}
+
+pub async fn delete_account_confirm_handler(
+    Extension(label): Extension<String>,
+    headers: HeaderMap,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<Token>,
+) -> Result<impl IntoResponse, AppError> {
+    let website_path = get_website_path(&label)?;
+
+    let session = check_session(state.clone(), &headers, &website_path).await?;
+
+    let hashed_ip = get_hashed_ip(state.clone(), &headers, address.ip(), &website_path).await?;
+
+    record_result(
+        state.clone(),
+        &AuditContext::new(&headers, &hashed_ip, &website_path),
+        &session.record.email,
+        AuditEventType::AccountDeleted,
+        finish_account_deletion(
+            state.clone(),
+            &session,
+            &payload.token,
+            &hashed_ip,
+            &website_path,
+        )
+        .await,
+    )
+    .await?;
+
+    Ok((StatusCode::OK, clear_cookies(&state.config, &website_path)).into_response())
+}
+
+pub async fn change_password_handler(
+    Extension(label): Extension<String>,
+    headers: HeaderMap,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<PasswordChange>,
+) -> Result<impl IntoResponse, AppError> {
+    let website_path = get_website_path(&label)?;
+
+    let session = check_session(state.clone(), &headers, &website_path).await?;
+
+    let hashed_ip = get_hashed_ip(state.clone(), &headers, address.ip(), &website_path).await?;
+
+    record_result(
+        state.clone(),
+        &AuditContext::for_session(&headers, &hashed_ip, &website_path),
+        &session.record.email,
+        AuditEventType::PasswordChanged,
+        change_password(state.clone(), &session, &payload, &hashed_ip, &website_path).await,
+    )
+    .await?;
+
+    Ok((StatusCode::OK, session.headers).into_response())
+}
+
+pub async fn change_email_handler(
+    Extension(label): Extension<String>,
+    headers: HeaderMap,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    Json(mut payload): Json<Token>,
+) -> Result<impl IntoResponse, AppError> {
+    payload.token = normalize_email(&payload.token);
+
+    let website_path = get_website_path(&label)?;
+
+    let session = check_session(state.clone(), &headers, &website_path).await?;
+
+    let hashed_ip = get_hashed_ip(state.clone(), &headers, address.ip(), &website_path).await?;
+
+    let change_headers = record_result(
+        state.clone(),
+        &AuditContext::for_session(&headers, &hashed_ip, &website_path),
+        &session.record.email,
+        AuditEventType::EmailChangeRequested,
+        start_email_change(
+            state.clone(),
+            &session,
+            &payload.token,
+            &hashed_ip,
+            state.email_templates.negotiate_locale(&headers),
+            &website_path,
+        )
+        .await,
+    )
+    .await?;
+
+    let mut response_headers = session.headers;
+    append_cookies(&mut response_headers, change_headers);
+
+    Ok((StatusCode::OK, response_headers).into_response())
+}
+
+pub async fn change_email_confirm_handler(
+    Extension(label): Extension<String>,
+    headers: HeaderMap,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<Token>,
+) -> Result<impl IntoResponse, AppError> {
+    let website_path = get_website_path(&label)?;
+
+    let session = check_session(state.clone(), &headers, &website_path).await?;
+
+    let hashed_ip = get_hashed_ip(state.clone(), &headers, address.ip(), &website_path).await?;
+    let audit_context = AuditContext::new(&headers, &hashed_ip, &website_path);
+
+    let new_email = record_result(
+        state.clone(),
+        &audit_context,
+        &session.record.email,
+        AuditEventType::EmailChanged,
+        finish_email_change(
+            state.clone(),
+            &session,
+            &headers,
+            &payload.token,
+            &hashed_ip,
+            &website_path,
+        )
+        .await,
+    )
+    .await?;
+
+    record_event(
+        state.clone(),
+        &audit_context,
+        &new_email,
+        AuditEventType::EmailChanged,
+        AuditOutcome::Success,
+    )
+    .await;
+
+    let mut response_headers = clear_cookies(&state.config, &website_path);
+    append_cookies(
+        &mut response_headers,
+        generate_secondary_cookie(RedisAction::ChangeEmail.as_ref(), "", 0, &website_path),
+    );
+
+    Ok((StatusCode::OK, response_headers).into_response())
+}
+
+pub async fn audit_events_handler(
+    Extension(label): Extension<String>,
+    headers: HeaderMap,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let website_path = get_website_path(&label)?;
+
+    let session = check_session(state.clone(), &headers, &website_path).await?;
+
+    Ok((
+        StatusCode::OK,
+        session.headers,
+        Json(get_recent_events(state.clone(), &session.record.email, &website_path).await?),
+    )
+        .into_response())
+}
+
+pub async fn admin_user_handler(
+    Extension(label): Extension<String>,
+    headers: HeaderMap,
+    Path(email): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let email = normalize_email(&email);
+
+    let website_path = get_website_path(&label)?;
+
+    let session = check_admin_session(state.clone(), &headers, &website_path).await?;
+
+    Ok((
+        StatusCode::OK,
+        session.headers,
+        Json(get_user_overview(state.clone(), &email, &website_path).await?),
+    )
+        .into_response())
+}
+
+pub async fn admin_unlock_handler(
+    Extension(label): Extension<String>,
+    headers: HeaderMap,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Path(email): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let email = normalize_email(&email);
+
+    let website_path = get_website_path(&label)?;
+
+    let session = check_admin_session(state.clone(), &headers, &website_path).await?;
+
+    force_unlock(state.clone(), &email, &website_path).await?;
+
+    record_event(
+        state.clone(),
+        &AuditContext::new(
+            &headers,
+            &get_hashed_ip(state.clone(), &headers, address.ip(), &website_path).await?,
+            &website_path,
+        ),
+        &email,
+        AuditEventType::AccountUnlocked,
+        AuditOutcome::Success,
+    )
+    .await;
+
+    Ok((StatusCode::OK, session.headers).into_response())
+}
+
+pub async fn admin_freeze_handler(
+    Extension(label): Extension<String>,
+    headers: HeaderMap,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Path(email): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let email = normalize_email(&email);
+
+    let website_path = get_website_path(&label)?;
+
+    let session = check_admin_session(state.clone(), &headers, &website_path).await?;
+
+    force_freeze(state.clone(), &email, &website_path).await?;
+
+    record_event(
+        state.clone(),
+        &AuditContext::new(
+            &headers,
+            &get_hashed_ip(state.clone(), &headers, address.ip(), &website_path).await?,
+            &website_path,
+        ),
+        &email,
+        AuditEventType::AccountFrozen,
+        AuditOutcome::Success,
+    )
+    .await;
+
+    Ok((StatusCode::OK, session.headers).into_response())
+}
+
+pub async fn admin_clear_locks_handler(
+    Extension(label): Extension<String>,
+    headers: HeaderMap,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Path(email): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let email = normalize_email(&email);
+
+    let website_path = get_website_path(&label)?;
+
+    let session = check_admin_session(state.clone(), &headers, &website_path).await?;
+
+    clear_lock_counters(state.clone(), &email, &website_path).await?;
+
+    record_event(
+        state.clone(),
+        &AuditContext::new(
+            &headers,
+            &get_hashed_ip(state.clone(), &headers, address.ip(), &website_path).await?,
+            &website_path,
+        ),
+        &email,
+        AuditEventType::LocksCleared,
+        AuditOutcome::Success,
+    )
+    .await;
+
+    Ok((StatusCode::OK, session.headers).into_response())
+}
+
+pub async fn admin_reset_items_handler(
+    Extension(label): Extension<String>,
+    headers: HeaderMap,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Path(email): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let email = normalize_email(&email);
+
+    let website_path = get_website_path(&label)?;
+
+    let session = check_admin_session(state.clone(), &headers, &website_path).await?;
+
+    reset_item_quota(state.clone(), &email, &website_path).await?;
+
+    record_event(
+        state.clone(),
+        &AuditContext::new(
+            &headers,
+            &get_hashed_ip(state.clone(), &headers, address.ip(), &website_path).await?,
+            &website_path,
+        ),
+        &email,
+        AuditEventType::ItemQuotaReset,
+        AuditOutcome::Success,
+    )
+    .await;
+
+    Ok((StatusCode::OK, session.headers).into_response())
+}
+
+pub async fn admin_delete_item_handler(
+    Extension(label): Extension<String>,
+    headers: HeaderMap,
+    Path(item_id): Path<Uuid>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let website_path = get_website_path(&label)?;
+
+    let session = check_admin_session(state.clone(), &headers, &website_path).await?;
+
+    delete_item(state.clone(), item_id, &website_path).await?;
+
+    Ok((StatusCode::OK, session.headers).into_response())
+}
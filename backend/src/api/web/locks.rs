@@ -1,18 +1,19 @@
 use super::{
+    database::{check_lock, get_user, unlock_account, update_lock},
     models::{Account, Action, RedisAccount, RedisAction, VerifiedTokenResult, WebsitePath},
     sessions::delete_all_sessions,
-    swap::database::{check_lock, get_user, unlock_account, update_lock},
-    twofactor::generate_code,
+    twofactor::{LINK_TOKEN_REGEX, generate_link_token, hash_code},
     utilities::get_key,
     verify::hash_password,
 };
 use crate::{
     AppError, AppState,
     api::microservices::redis::{insert_id, remove_id, try_get},
+    config::Config,
 };
 use chrono::{Duration as chronoDuration, Utc};
 use once_cell::sync::Lazy;
-use redis::Script;
+use redis::{Pipeline, Script};
 use std::sync::Arc;
 use tokio::task::spawn_blocking;
 
@@ -25,9 +26,16 @@ static FAILED_ATTEMPTS_SCRIPT: Lazy<Script> = Lazy::new(|| {
     Script::new(
         r#"
         local attempts = redis.call("INCR", KEYS[1])
+        local duration = tonumber(ARGV[1])
         if attempts <= tonumber(ARGV[2]) then
-            if tonumber(ARGV[1]) > 0 then
-                redis.call("EXPIRE", KEYS[1], tonumber(ARGV[1]))
+            if duration > 0 then
+                redis.call("EXPIRE", KEYS[1], duration)
+                if KEYS[2] then
+                    redis.call("SADD", KEYS[2], KEYS[1])
+                    if redis.call("TTL", KEYS[2]) < duration then
+                        redis.call("EXPIRE", KEYS[2], duration)
+                    end
+                end
             end
         else
             redis.call("DECR", KEYS[1])
@@ -36,6 +44,23 @@ static FAILED_ATTEMPTS_SCRIPT: Lazy<Script> = Lazy::new(|| {
     )
 });
 
+static CARRY_OVER_SCRIPT: Lazy<Script> = Lazy::new(|| {
+    Script::new(
+        r#"
+        for i = 2, #KEYS, 2 do
+            local ttl = redis.call("PTTL", KEYS[i])
+            if ttl > 0 then
+                redis.call("SET", KEYS[i + 1], redis.call("GET", KEYS[i]), "PX", ttl, "NX")
+                redis.call("SADD", KEYS[1], KEYS[i + 1])
+                if redis.call("PTTL", KEYS[1]) < ttl then
+                    redis.call("PEXPIRE", KEYS[1], ttl)
+                end
+            end
+        end
+    "#,
+    )
+});
+
 pub async fn check_db_lock(
     state: Arc<AppState>,
     email: &str,
@@ -55,15 +80,7 @@ pub async fn freeze_account(
         return Ok(());
     }
 
-    insert_id(
-        state.clone(),
-        &format!("{}:{}", RedisAction::LockedTime.as_ref(), &email),
-        &(Utc::now() + chronoDuration::milliseconds(500))
-            .timestamp_millis()
-            .to_string(),
-        900,
-    )
-    .await?;
+    invalidate_pending_logins(state.clone(), email, website_path).await?;
 
     update_lock(state.clone(), email, true, website_path).await?;
 
@@ -79,6 +96,69 @@ pub async fn freeze_account(
     Ok(())
 }
 
+pub async fn create_freeze_link(
+    state: Arc<AppState>,
+    email: &str,
+    website_path: &WebsitePath,
+) -> Result<String, AppError> {
+    let link_token = generate_link_token();
+
+    insert_id(
+        state.clone(),
+        &get_freeze_link_key(&state.config, website_path, &link_token),
+        email,
+        state.config.session.freeze_link_duration_seconds,
+    )
+    .await?;
+
+    Ok(link_token)
+}
+
+pub async fn redeem_freeze_link(
+    state: Arc<AppState>,
+    link_token: &str,
+    website_path: &WebsitePath,
+) -> Result<Option<String>, AppError> {
+    if !LINK_TOKEN_REGEX.is_match(link_token) {
+        return Ok(None);
+    }
+
+    Ok(redis::cmd("GETDEL")
+        .arg(get_freeze_link_key(&state.config, website_path, link_token))
+        .query_async(&mut state.redis_connection_manager.clone())
+        .await?)
+}
+
+fn get_freeze_link_key(config: &Config, website_path: &WebsitePath, link_token: &str) -> String {
+    format!(
+        "{}:{}:{}",
+        website_path.as_ref(),
+        RedisAction::FreezeLink.as_ref(),
+        hash_code(config, RedisAction::FreezeLink.as_ref(), link_token)
+    )
+}
+
+pub async fn invalidate_pending_logins(
+    state: Arc<AppState>,
+    email: &str,
+    website_path: &WebsitePath,
+) -> Result<(), AppError> {
+    insert_id(
+        state.clone(),
+        &format!(
+            "{}:{}:{}",
+            website_path.as_ref(),
+            RedisAction::LockedTime.as_ref(),
+            email
+        ),
+        &(Utc::now() + chronoDuration::milliseconds(500))
+            .timestamp_millis()
+            .to_string(),
+        900,
+    )
+    .await
+}
+
 pub async fn unfreeze_account(
     state: Arc<AppState>,
     email: &str,
@@ -121,32 +201,26 @@ pub async fn check_locks(
     )
     .await?;
 
-    if locked_timestamp.is_some()
-        && issued_timestamp
-            < locked_timestamp
-                .expect("is_some failed")
-                .parse::<i64>()
-                .unwrap_or(i64::MAX)
-    {
-        return Ok(true);
-    }
+    Ok(is_issued_before_lock(issued_timestamp, locked_timestamp))
+}
 
-    Ok(false)
+fn is_issued_before_lock(issued_timestamp: i64, locked_timestamp: Option<String>) -> bool {
+    locked_timestamp.is_some_and(|locked_timestamp| {
+        issued_timestamp < locked_timestamp.parse::<i64>().unwrap_or(i64::MAX)
+    })
 }
 
-pub async fn are_all_locked(
+pub async fn check_all_locks(
     state: Arc<AppState>,
     website_path: &str,
     token: &str,
     locks: &[LockCheck<'_>],
-) -> Result<bool, AppError> {
+) -> Result<(), AppError> {
     for lock in locks {
-        if is_redis_locked(state.clone(), website_path, lock.key, token, lock.check).await? {
-            return Ok(true);
-        }
+        check_redis_lock(state.clone(), website_path, lock.key, token, lock.check).await?;
     }
 
-    Ok(false)
+    Ok(())
 }
 
 pub async fn check_forgot_lock(
@@ -185,7 +259,7 @@ pub async fn check_forgot_locks(
     website_path: &str,
     token: &str,
 ) -> Result<(), AppError> {
-    if are_all_locked(
+    check_all_locks(
         state.clone(),
         website_path,
         token,
@@ -200,14 +274,7 @@ pub async fn check_forgot_locks(
             },
         ],
     )
-    .await?
-    {
-        return Err(AppError::Unauthorized(
-            "Try again in 30 minutes".to_string(),
-        ));
-    }
-
-    Ok(())
+    .await
 }
 
 pub async fn prepare_resend_and_check_locks(
@@ -234,26 +301,23 @@ pub async fn prepare_resend_and_check_locks(
             .expect("is_none failed"),
     )?;
 
-    if are_all_locked(
+    check_redis_lock(
         state.clone(),
         website_path,
+        &get_key(RedisAction::LockedCode, hashed_ip),
         &old_redis_account.email,
-        &[LockCheck {
-            key: &get_key(RedisAction::LockedCode, hashed_ip),
-            check: &state.config.authentication.max_codes,
-        }],
+        &state.config.authentication.max_codes,
     )
-    .await?
-    {
-        return Err(AppError::Unauthorized(
-            "Try again in 30 minutes".to_string(),
-        ));
-    }
+    .await?;
+
+    Ok(get_resend_account(old_redis_account))
+}
 
-    Ok(RedisAccount {
-        code: generate_code(),
+fn get_resend_account(old_redis_account: RedisAccount) -> RedisAccount {
+    RedisAccount {
+        code_hash: String::new(),
         ..old_redis_account
-    })
+    }
 }
 
 pub async fn check_auth_locks(
@@ -262,7 +326,7 @@ pub async fn check_auth_locks(
     website_path: &str,
     payload: &Account,
 ) -> Result<(), AppError> {
-    if are_all_locked(
+    check_all_locks(
         state.clone(),
         website_path,
         &payload.email,
@@ -277,12 +341,7 @@ pub async fn check_auth_locks(
             },
         ],
     )
-    .await?
-    {
-        return Err(AppError::Unauthorized(
-            "Try again in 30 minutes".to_string(),
-        ));
-    }
+    .await?;
 
     if payload.action != Action::Forgot {
         return Ok(());
@@ -301,24 +360,6 @@ pub async fn check_auth_locks(
     Err(AppError::Unauthorized("Invalid Credentials".to_string()))
 }
 
-pub async fn is_home_locked(state: Arc<AppState>, hashed_ip: &str) -> Result<(), AppError> {
-    if is_temporarily_locked_ms(
-        state.clone(),
-        WebsitePath::Home.as_ref(),
-        RedisAction::LockedTemporary.as_ref(),
-        hashed_ip,
-        state.config.website_specific.home_limit_ms.into(),
-    )
-    .await?
-    {
-        return Err(AppError::Unauthorized(
-            "Too many requests from your ip".to_string(),
-        ));
-    }
-
-    Ok(())
-}
-
 pub async fn increment_lock_key(
     state: Arc<AppState>,
     website_path: &str,
@@ -327,8 +368,13 @@ pub async fn increment_lock_key(
     locked_duration_seconds: &u16,
     max_attempts: &u8,
 ) -> Result<(), AppError> {
-    let _count: () = FAILED_ATTEMPTS_SCRIPT
-        .key(format!("{}:{}:{}", website_path, key, email))
+    let mut invocation = FAILED_ATTEMPTS_SCRIPT.key(format!("{}:{}:{}", website_path, key, email));
+
+    if let Some((_, hashed_ip)) = key.split_once(':') {
+        invocation.key(get_ip_lock_index(website_path, hashed_ip));
+    }
+
+    let _count: () = invocation
         .arg(locked_duration_seconds)
         .arg(max_attempts)
         .invoke_async(&mut state.redis_connection_manager.clone())
@@ -355,23 +401,146 @@ pub async fn is_redis_locked(
     }
 }
 
-pub async fn is_temporarily_locked_ms(
+pub async fn check_redis_lock(
     state: Arc<AppState>,
     website_path: &str,
-    key: &str,
-    id: &str,
-    ttl_ms: i64,
-) -> Result<bool, AppError> {
-    let result: Option<String> = redis::cmd("SET")
-        .arg(format!("{}:{}:{}", website_path, key, id))
-        .arg("1")
-        .arg("NX")
-        .arg("PX")
-        .arg(ttl_ms)
+    key_prefix: &str,
+    key_id: &str,
+    threshold: &u8,
+) -> Result<(), AppError> {
+    if !is_redis_locked(state.clone(), website_path, key_prefix, key_id, threshold).await? {
+        return Ok(());
+    }
+
+    Err(AppError::TooManyRequests(
+        get_retry_after(
+            state.clone(),
+            &format!("{}:{}:{}", website_path, key_prefix, key_id),
+        )
+        .await?,
+    ))
+}
+
+pub async fn get_retry_after(state: Arc<AppState>, key: &str) -> Result<u64, AppError> {
+    let remaining_ms: i64 = redis::cmd("PTTL")
+        .arg(key)
         .query_async(&mut state.redis_connection_manager.clone())
         .await?;
 
-    Ok(result.is_none())
+    Ok(u64::try_from(remaining_ms)
+        .unwrap_or(0)
+        .div_ceil(1000)
+        .max(1))
+}
+
+pub async fn carry_over_ip_locks(
+    state: Arc<AppState>,
+    website_path: &WebsitePath,
+    previous_hashed_ip: &str,
+    hashed_ip: &str,
+) -> Result<(), AppError> {
+    if is_temporarily_locked(
+        state.clone(),
+        website_path.as_ref(),
+        RedisAction::IpRotation.as_ref(),
+        hashed_ip,
+        state.config.ip_hashing.grace_seconds.into(),
+    )
+    .await?
+    {
+        return Ok(());
+    }
+
+    let keys: Vec<String> = redis::cmd("SMEMBERS")
+        .arg(get_ip_lock_index(website_path.as_ref(), previous_hashed_ip))
+        .query_async(&mut state.redis_connection_manager.clone())
+        .await?;
+
+    let carried_keys = get_carried_keys(&keys, previous_hashed_ip, hashed_ip);
+
+    if carried_keys.is_empty() {
+        return Ok(());
+    }
+
+    let mut invocation = CARRY_OVER_SCRIPT.key(get_ip_lock_index(website_path.as_ref(), hashed_ip));
+
+    for (key, target) in &carried_keys {
+        invocation.key(key).key(target);
+    }
+
+    let _: () = invocation
+        .invoke_async(&mut state.redis_connection_manager.clone())
+        .await?;
+
+    Ok(())
+}
+
+fn get_carried_keys(
+    keys: &[String],
+    previous_hashed_ip: &str,
+    hashed_ip: &str,
+) -> Vec<(String, String)> {
+    let previous = format!(":{}:", previous_hashed_ip);
+
+    keys.iter()
+        .filter_map(|key| {
+            key.find(&previous).map(|start| {
+                (
+                    key.clone(),
+                    format!(
+                        "{}:{}:{}",
+                        &key[..start],
+                        hashed_ip,
+                        &key[start + previous.len()..]
+                    ),
+                )
+            })
+        })
+        .collect()
+}
+
+fn get_ip_lock_index(website_path: &str, hashed_ip: &str) -> String {
+    format!(
+        "{}:{}:{}",
+        website_path,
+        RedisAction::IpLocks.as_ref(),
+        hashed_ip
+    )
+}
+
+pub async fn increment_code_attempts(
+    state: Arc<AppState>,
+    website_path: &str,
+    id: &str,
+) -> Result<u64, AppError> {
+    let (attempts,): (u64,) = get_code_attempts_pipe(
+        &format!(
+            "{}:{}:{}",
+            website_path,
+            RedisAction::CodeAttempts.as_ref(),
+            id
+        ),
+        state
+            .config
+            .session
+            .temporary_session_duration_seconds
+            .into(),
+    )
+    .query_async(&mut state.redis_connection_manager.clone())
+    .await?;
+
+    Ok(attempts)
+}
+
+pub fn is_code_exhausted(attempts: u64, max_attempts: u8) -> bool {
+    attempts >= max_attempts.into()
+}
+
+fn get_code_attempts_pipe(key: &str, ttl: i64) -> Pipeline {
+    let mut pipe = redis::pipe();
+    pipe.atomic().incr(key, 1).expire(key, ttl).ignore();
+
+    pipe
 }
 
 pub async fn is_temporarily_locked(
@@ -392,3 +561,167 @@ pub async fn is_temporarily_locked(
 
     Ok(result.is_none())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::web::sessions::create_auth_redis_account;
+    use redis::{AsyncCommands, aio::MultiplexedConnection};
+    use uuid::Uuid;
+
+    async fn redis_connection() -> MultiplexedConnection {
+        redis::Client::open(
+            std::env::var("REDIS_TEST_URL").unwrap_or("redis://127.0.0.1:6379".to_string()),
+        )
+        .unwrap()
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap()
+    }
+
+    #[test]
+    fn code_is_exhausted_at_max_attempts() {
+        assert!(!is_code_exhausted(4, 5));
+        assert!(is_code_exhausted(5, 5));
+        assert!(is_code_exhausted(6, 5));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Redis at REDIS_TEST_URL"]
+    async fn code_attempts_count_up_and_expire() {
+        let mut connection = redis_connection().await;
+        let key = format!("test:code_attempts:{}", Uuid::new_v4());
+
+        for expected in 1..=3 {
+            let (attempts,): (u64,) = get_code_attempts_pipe(&key, 60)
+                .query_async(&mut connection)
+                .await
+                .unwrap();
+            assert_eq!(attempts, expected);
+        }
+
+        let ttl: i64 = connection.ttl(&key).await.unwrap();
+        assert!(ttl > 0 && ttl <= 60);
+    }
+
+    #[test]
+    fn logins_issued_before_invalidation_are_locked() {
+        let locked_timestamp = Some("1000".to_string());
+
+        assert!(is_issued_before_lock(999, locked_timestamp.clone()));
+        assert!(!is_issued_before_lock(1000, locked_timestamp));
+        assert!(!is_issued_before_lock(999, None));
+        assert!(is_issued_before_lock(999, Some("corrupt".to_string())));
+    }
+
+    #[test]
+    fn resend_keeps_a_pending_second_factor() {
+        let redis_account = get_resend_account(RedisAccount {
+            code_hash: "hash".to_string(),
+            totp: true,
+            ..create_auth_redis_account("pete@purdue.edu".to_string(), Action::Login, None)
+        });
+
+        assert!(redis_account.totp);
+        assert!(redis_account.code_hash.is_empty());
+        assert_eq!(redis_account.email, "pete@purdue.edu");
+    }
+
+    #[test]
+    fn freeze_link_key_does_not_contain_the_token() {
+        let config = Config::for_tests();
+        let link_token = generate_link_token();
+        let key = get_freeze_link_key(&config, &WebsitePath::BoilerSwap, &link_token);
+
+        assert!(key.starts_with("swap:freeze_link:"));
+        assert!(!key.contains(&link_token));
+        assert_eq!(
+            key,
+            get_freeze_link_key(&config, &WebsitePath::BoilerSwap, &link_token)
+        );
+    }
+
+    #[test]
+    fn ip_lock_index_is_scoped_to_site_and_hash() {
+        assert_eq!(get_ip_lock_index("swap", "abc"), "swap:ip_locks:abc");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Redis at REDIS_TEST_URL"]
+    async fn failed_attempts_are_indexed_by_hashed_ip() {
+        let mut connection = redis_connection().await;
+        let site = format!("test:{}", Uuid::new_v4());
+        let lock_key = format!("{}:auth_lock:previous:user@example.com", site);
+        let index = get_ip_lock_index(&site, "previous");
+
+        let _: () = FAILED_ATTEMPTS_SCRIPT
+            .key(&lock_key)
+            .key(&index)
+            .arg(60)
+            .arg(3)
+            .invoke_async(&mut connection)
+            .await
+            .unwrap();
+
+        let members: Vec<String> = connection.smembers(&index).await.unwrap();
+        let index_ttl: i64 = connection.ttl(&index).await.unwrap();
+
+        assert_eq!(members, vec![lock_key]);
+        assert!(index_ttl > 0 && index_ttl <= 60);
+    }
+
+    #[test]
+    fn carried_keys_swap_only_the_hashed_ip() {
+        assert_eq!(
+            get_carried_keys(
+                &[
+                    "swap:auth_lock:previous:previous@example.com".to_string(),
+                    "swap:code_lock:other:user@example.com".to_string(),
+                ],
+                "previous",
+                "current",
+            ),
+            vec![(
+                "swap:auth_lock:previous:previous@example.com".to_string(),
+                "swap:auth_lock:current:previous@example.com".to_string(),
+            )]
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Redis at REDIS_TEST_URL"]
+    async fn carry_over_copies_live_locks_to_the_new_hash() {
+        let mut connection = redis_connection().await;
+        let site = format!("test:{}", Uuid::new_v4());
+        let live_key = format!("{}:auth_lock:previous:user@example.com", site);
+        let expired_key = format!("{}:code_lock:previous:user@example.com", site);
+        let index = get_ip_lock_index(&site, "current");
+
+        let _: () = connection.set_ex(&live_key, "2", 60).await.unwrap();
+
+        let mut invocation = CARRY_OVER_SCRIPT.key(&index);
+
+        for (key, target) in get_carried_keys(&[live_key, expired_key], "previous", "current") {
+            invocation.key(key).key(target);
+        }
+
+        let _: () = invocation.invoke_async(&mut connection).await.unwrap();
+
+        let carried: Option<String> = connection
+            .get(format!("{}:auth_lock:current:user@example.com", site))
+            .await
+            .unwrap();
+        let expired: Option<String> = connection
+            .get(format!("{}:code_lock:current:user@example.com", site))
+            .await
+            .unwrap();
+        let members: Vec<String> = connection.smembers(&index).await.unwrap();
+
+        assert_eq!(carried.as_deref(), Some("2"));
+        assert_eq!(expired, None);
+        assert_eq!(
+            members,
+            vec![format!("{}:auth_lock:current:user@example.com", site)]
+        );
+    }
+}
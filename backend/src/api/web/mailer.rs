@@ -0,0 +1,645 @@
+use super::{
+    locks::{check_forgot_lock, increment_lock_key},
+    models::{Action, IssuedCode, RedisAccount, RedisAction, WebsitePath},
+    templates::EmailTemplates,
+};
+use crate::{
+    AppError, AppState,
+    config::Config,
+    metrics::{RedisMetricAction, incr_metric},
+};
+use chacha20poly1305::{
+    Key, XChaCha20Poly1305, XNonce,
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+};
+use chrono::Utc;
+use data_encoding::BASE64URL_NOPAD;
+use lettre::{Message, message::MultiPart};
+use once_cell::sync::Lazy;
+use redis::{AsyncTypedCommands, Script, pipe};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{str::FromStr, sync::Arc, time::Duration};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+const MAX_DEAD_LETTERS: isize = 1000;
+const DEAD_LETTER_TTL_SECONDS: i64 = 259_200;
+const MAX_PROMOTED_RETRIES: u8 = 100;
+const NONCE_BYTES: usize = 24;
+
+#[derive(Serialize, Deserialize)]
+pub struct MailJob {
+    pub id: String,
+    pub email: String,
+    pub action: Action,
+    #[serde(skip)]
+    pub code: String,
+    pub locale: Option<String>,
+    pub forgot_key: Option<String>,
+    #[serde(skip)]
+    pub link_token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sealed: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    pub website_path: String,
+    #[serde(default)]
+    pub attempts: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct MailSecrets {
+    code: String,
+    link_token: Option<String>,
+}
+
+enum MailOutcome {
+    Sent,
+    Skipped,
+    Retry(String),
+    Failed(String),
+}
+
+static CLAIM_MAIL_SCRIPT: Lazy<Script> = Lazy::new(|| {
+    Script::new(
+        r#"
+        local due = redis.call("ZRANGEBYSCORE", KEYS[2], "-inf", ARGV[1], "LIMIT", 0, ARGV[2])
+        for _, job in ipairs(due) do
+            redis.call("RPUSH", KEYS[1], job)
+            redis.call("ZREM", KEYS[2], job)
+        end
+        return redis.call("LMOVE", KEYS[1], KEYS[3], "LEFT", "RIGHT")
+    "#,
+    )
+});
+
+static RECOVER_MAIL_SCRIPT: Lazy<Script> = Lazy::new(|| {
+    Script::new(
+        r#"
+        local count = 0
+        while redis.call("LMOVE", KEYS[1], KEYS[2], "RIGHT", "LEFT") do
+            count = count + 1
+        end
+        return count
+    "#,
+    )
+});
+
+pub async fn enqueue_code_email(
+    state: Arc<AppState>,
+    redis_account: &RedisAccount,
+    issued_code: &IssuedCode,
+    forgot_key: &Option<String>,
+    website_path: &WebsitePath,
+) -> Result<(), AppError> {
+    let job = MailJob {
+        id: Uuid::new_v4().to_string(),
+        email: redis_account.email.clone(),
+        action: redis_account.action.clone(),
+        code: issued_code.code.clone(),
+        locale: redis_account.locale.clone(),
+        forgot_key: forgot_key.clone(),
+        link_token: issued_code.link_token.clone(),
+        sealed: None,
+        device: None,
+        website_path: website_path.as_ref().to_string(),
+        attempts: 0,
+        last_error: None,
+    };
+
+    enqueue_job(state.clone(), job).await
+}
+
+pub async fn enqueue_notice_email(
+    state: Arc<AppState>,
+    email: &str,
+    locale: Option<String>,
+    link_token: String,
+    device: &str,
+    website_path: &WebsitePath,
+) -> Result<(), AppError> {
+    let job = MailJob {
+        id: Uuid::new_v4().to_string(),
+        email: email.to_string(),
+        action: Action::NewSignIn,
+        code: String::new(),
+        locale,
+        forgot_key: None,
+        link_token: Some(link_token),
+        sealed: None,
+        device: Some(device.to_string()),
+        website_path: website_path.as_ref().to_string(),
+        attempts: 0,
+        last_error: None,
+    };
+
+    enqueue_job(state.clone(), job).await
+}
+
+async fn enqueue_job(state: Arc<AppState>, mut job: MailJob) -> Result<(), AppError> {
+    job.sealed = Some(seal_secrets(&state.config, &job)?);
+
+    state
+        .redis_connection_manager
+        .clone()
+        .rpush(
+            RedisAction::MailQueue.as_ref(),
+            serde_json::to_string(&job)?,
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn start_mail_workers(state: Arc<AppState>) -> Result<(), AppError> {
+    let recovered: u32 = RECOVER_MAIL_SCRIPT
+        .key(RedisAction::MailProcessing.as_ref())
+        .key(RedisAction::MailQueue.as_ref())
+        .invoke_async(&mut state.redis_connection_manager.clone())
+        .await?;
+
+    if recovered > 0 {
+        info!("Requeued {} unfinished emails", recovered);
+    }
+
+    for _ in 0..state.config.mail_queue.workers {
+        let state = state.clone();
+
+        tokio::spawn(async move {
+            loop {
+                match process_next_job(state.clone()).await {
+                    Ok(true) => (),
+                    Ok(false) => {
+                        tokio::time::sleep(Duration::from_millis(
+                            state.config.mail_queue.poll_interval_ms.into(),
+                        ))
+                        .await
+                    }
+                    Err(error) => {
+                        warn!("Mail worker error: {:?}", error);
+
+                        tokio::time::sleep(Duration::from_millis(
+                            state.config.mail_queue.poll_interval_ms.into(),
+                        ))
+                        .await
+                    }
+                }
+            }
+        });
+    }
+
+    Ok(())
+}
+
+async fn process_next_job(state: Arc<AppState>) -> Result<bool, AppError> {
+    let claimed: Option<String> = CLAIM_MAIL_SCRIPT
+        .key(RedisAction::MailQueue.as_ref())
+        .key(RedisAction::MailRetry.as_ref())
+        .key(RedisAction::MailProcessing.as_ref())
+        .arg(Utc::now().timestamp())
+        .arg(MAX_PROMOTED_RETRIES)
+        .invoke_async(&mut state.redis_connection_manager.clone())
+        .await?;
+
+    let Some(raw_job) = claimed else {
+        return Ok(false);
+    };
+
+    let mut job = match serde_json::from_str::<MailJob>(&raw_job) {
+        Ok(job) => job,
+        Err(error) => {
+            warn!("Dropping malformed email job: {}", error);
+
+            ack_job(state.clone(), &raw_job).await?;
+
+            return Ok(true);
+        }
+    };
+
+    let Ok(website_path) = WebsitePath::from_str(&job.website_path) else {
+        warn!(
+            "Email {} moved to dead letters: unknown website path {}",
+            job.id, job.website_path
+        );
+
+        job.last_error = Some(format!("unknown website path {}", job.website_path));
+        bury_job(state.clone(), &raw_job, job).await?;
+
+        return Ok(true);
+    };
+
+    let Some(secrets) = open_secrets(&state.config, &job) else {
+        warn!("Email {} moved to dead letters: unreadable secrets", job.id);
+
+        job.last_error = Some("unreadable secrets".to_string());
+        bury_job(state.clone(), &raw_job, job).await?;
+
+        return Ok(true);
+    };
+
+    job.code = secrets.code;
+    job.link_token = secrets.link_token;
+
+    match deliver_job(state.clone(), &job, &website_path).await {
+        MailOutcome::Sent => {
+            ack_job(state.clone(), &raw_job).await?;
+            record_metric(state.clone(), &website_path, RedisMetricAction::EmailsSent).await?;
+
+            if let Some(forgot_key) = &job.forgot_key {
+                increment_lock_key(
+                    state.clone(),
+                    website_path.as_ref(),
+                    forgot_key,
+                    &job.email,
+                    &state.config.authentication.verify_lock_duration_seconds,
+                    &state.config.authentication.verify_max_attempts,
+                )
+                .await?;
+            }
+        }
+        MailOutcome::Skipped => ack_job(state.clone(), &raw_job).await?,
+        MailOutcome::Retry(error) if job.attempts + 1 < state.config.mail_queue.max_attempts => {
+            debug!("Retrying email {}: {}", job.id, error);
+
+            job.attempts += 1;
+            job.last_error = Some(error);
+
+            let retry_at =
+                Utc::now().timestamp() + get_backoff_seconds(state.clone(), job.attempts);
+
+            let _: () = pipe()
+                .atomic()
+                .lrem(RedisAction::MailProcessing.as_ref(), 1, &raw_job)
+                .ignore()
+                .zadd(
+                    RedisAction::MailRetry.as_ref(),
+                    serde_json::to_string(&job)?,
+                    retry_at,
+                )
+                .ignore()
+                .query_async(&mut state.redis_connection_manager.clone())
+                .await?;
+
+            record_metric(
+                state.clone(),
+                &website_path,
+                RedisMetricAction::EmailsRetried,
+            )
+            .await?;
+        }
+        MailOutcome::Retry(error) | MailOutcome::Failed(error) => {
+            warn!("Email {} moved to dead letters: {}", job.id, error);
+
+            job.attempts += 1;
+            job.last_error = Some(error);
+
+            bury_job(state.clone(), &raw_job, job).await?;
+            record_metric(
+                state.clone(),
+                &website_path,
+                RedisMetricAction::EmailsFailed,
+            )
+            .await?;
+        }
+    }
+
+    Ok(true)
+}
+
+async fn deliver_job(
+    state: Arc<AppState>,
+    job: &MailJob,
+    website_path: &WebsitePath,
+) -> MailOutcome {
+    if check_forgot_lock(state.clone(), &job.email, &job.forgot_key, website_path).await {
+        return MailOutcome::Skipped;
+    }
+
+    match send_code_email(state.clone(), job).await {
+        Ok(()) => MailOutcome::Sent,
+        Err(AppError::LettreTransport(error)) if !error.is_permanent() => {
+            MailOutcome::Retry(error.to_string())
+        }
+        Err(error) => MailOutcome::Failed(error.to_string()),
+    }
+}
+
+async fn send_code_email(state: Arc<AppState>, job: &MailJob) -> Result<(), AppError> {
+    state
+        .mailer
+        .send(build_email(
+            &state.email_templates,
+            &state.config.email.from_email,
+            &state.config.server.svelte_url,
+            state.config.session.temporary_session_duration_seconds / 60,
+            job,
+        )?)
+        .await
+}
+
+fn build_email(
+    email_templates: &EmailTemplates,
+    from_email: &str,
+    svelte_url: &str,
+    expiry_minutes: u16,
+    job: &MailJob,
+) -> Result<Message, AppError> {
+    let expiry_minutes = expiry_minutes.to_string();
+
+    let link = job
+        .link_token
+        .as_ref()
+        .map(|link_token| {
+            format!(
+                "{}/{}/auth/{}?token={}",
+                svelte_url,
+                job.website_path,
+                match job.action {
+                    Action::NewSignIn => "freeze",
+                    _ => "link",
+                },
+                link_token
+            )
+        })
+        .unwrap_or_default();
+
+    let rendered = email_templates.render(
+        job.locale.as_deref(),
+        &job.action,
+        &[
+            ("code", &job.code),
+            ("expiry_minutes", &expiry_minutes),
+            ("action", job.action.as_ref()),
+            ("email", &job.email),
+            ("link", &link),
+            ("device", job.device.as_deref().unwrap_or_default()),
+        ],
+    );
+
+    Ok(Message::builder()
+        .from(format!("BoilerSwap <{}>", from_email).parse()?)
+        .to(job.email.parse()?)
+        .subject(rendered.subject)
+        .multipart(MultiPart::alternative_plain_html(
+            rendered.text,
+            rendered.html,
+        ))?)
+}
+
+async fn ack_job(state: Arc<AppState>, raw_job: &str) -> Result<(), AppError> {
+    state
+        .redis_connection_manager
+        .clone()
+        .lrem(RedisAction::MailProcessing.as_ref(), 1, raw_job)
+        .await?;
+
+    Ok(())
+}
+
+async fn bury_job(state: Arc<AppState>, raw_job: &str, mut job: MailJob) -> Result<(), AppError> {
+    job.sealed = None;
+
+    let _: () = pipe()
+        .atomic()
+        .lrem(RedisAction::MailProcessing.as_ref(), 1, raw_job)
+        .ignore()
+        .lpush(
+            RedisAction::MailDeadLetter.as_ref(),
+            serde_json::to_string(&job)?,
+        )
+        .ignore()
+        .ltrim(
+            RedisAction::MailDeadLetter.as_ref(),
+            0,
+            MAX_DEAD_LETTERS - 1,
+        )
+        .ignore()
+        .expire(
+            RedisAction::MailDeadLetter.as_ref(),
+            DEAD_LETTER_TTL_SECONDS,
+        )
+        .ignore()
+        .query_async(&mut state.redis_connection_manager.clone())
+        .await?;
+
+    Ok(())
+}
+
+fn seal_secrets(config: &Config, job: &MailJob) -> Result<String, AppError> {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let secrets = serde_json::to_vec(&MailSecrets {
+        code: job.code.clone(),
+        link_token: job.link_token.clone(),
+    })?;
+
+    let ciphertext = get_cipher(config)
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: &secrets,
+                aad: job.id.as_bytes(),
+            },
+        )
+        .map_err(|_| AppError::Encryption)?;
+
+    Ok(BASE64URL_NOPAD.encode(&[nonce.as_slice(), &ciphertext].concat()))
+}
+
+fn open_secrets(config: &Config, job: &MailJob) -> Option<MailSecrets> {
+    let sealed = BASE64URL_NOPAD
+        .decode(job.sealed.as_ref()?.as_bytes())
+        .ok()?;
+
+    if sealed.len() < NONCE_BYTES {
+        return None;
+    }
+
+    let (nonce, ciphertext) = sealed.split_at(NONCE_BYTES);
+
+    let secrets = get_cipher(config)
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: job.id.as_bytes(),
+            },
+        )
+        .ok()?;
+
+    serde_json::from_slice(&secrets).ok()
+}
+
+fn get_cipher(config: &Config) -> XChaCha20Poly1305 {
+    XChaCha20Poly1305::new(Key::from_slice(&Sha256::digest(
+        config.secrets.mail_queue_secret.as_bytes(),
+    )))
+}
+
+fn get_backoff_seconds(state: Arc<AppState>, attempts: u8) -> i64 {
+    let base = i64::from(state.config.mail_queue.retry_base_seconds);
+
+    base.saturating_mul(1 << attempts.saturating_sub(1).min(16))
+        .min(state.config.mail_queue.retry_max_seconds.into())
+}
+
+async fn record_metric(
+    state: Arc<AppState>,
+    website_path: &WebsitePath,
+    metric_action: RedisMetricAction,
+) -> Result<(), AppError> {
+    incr_metric(
+        state.clone(),
+        &format!(
+            "{}:{}:{}",
+            website_path.as_ref(),
+            RedisAction::Metric.as_ref(),
+            metric_action.as_ref()
+        ),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::microservices::mail::{MailTransport, MemoryMailTransport};
+
+    fn login_job(locale: Option<&str>) -> MailJob {
+        MailJob {
+            id: Uuid::new_v4().to_string(),
+            email: "pete@purdue.edu".to_string(),
+            action: Action::Login,
+            code: "482913".to_string(),
+            locale: locale.map(str::to_string),
+            forgot_key: None,
+            link_token: Some("link-token".to_string()),
+            sealed: None,
+            device: None,
+            website_path: WebsitePath::BoilerSwap.as_ref().to_string(),
+            attempts: 0,
+            last_error: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn memory_transport_captures_templated_email() {
+        let email_templates = EmailTemplates::load("templates/email", "en").unwrap();
+        let outbox = Arc::new(MemoryMailTransport::default());
+
+        outbox
+            .send(
+                build_email(
+                    &email_templates,
+                    "noreply@boiler.example",
+                    "https://boiler.example",
+                    10,
+                    &login_job(None),
+                )
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let messages = outbox.messages();
+        assert_eq!(messages.len(), 1);
+
+        let formatted = String::from_utf8(messages[0].formatted()).unwrap();
+        assert!(formatted.contains("To: pete@purdue.edu"));
+        assert!(formatted.contains("From: BoilerSwap <noreply@boiler.example>"));
+        assert!(formatted.contains("482913"));
+        assert!(formatted.contains("https://boiler.example/swap/auth/link?token"));
+        assert!(!formatted.contains("{{"));
+    }
+
+    #[test]
+    fn unknown_locale_falls_back_to_default() {
+        let email_templates = EmailTemplates::load("templates/email", "en").unwrap();
+
+        let fallback = build_email(
+            &email_templates,
+            "noreply@boiler.example",
+            "https://boiler.example",
+            10,
+            &login_job(Some("fr")),
+        )
+        .unwrap();
+        let english = build_email(
+            &email_templates,
+            "noreply@boiler.example",
+            "https://boiler.example",
+            10,
+            &login_job(None),
+        )
+        .unwrap();
+
+        assert_eq!(
+            fallback.headers().get_raw("Subject"),
+            english.headers().get_raw("Subject")
+        );
+    }
+
+    #[test]
+    fn queued_job_does_not_expose_secrets() {
+        let config = Config::for_tests();
+        let mut job = login_job(None);
+        job.sealed = Some(seal_secrets(&config, &job).unwrap());
+
+        let serialized = serde_json::to_string(&job).unwrap();
+        assert!(!serialized.contains("482913"));
+        assert!(!serialized.contains("link-token"));
+
+        let queued: MailJob = serde_json::from_str(&serialized).unwrap();
+        let secrets = open_secrets(&config, &queued).unwrap();
+        assert_eq!(secrets.code, "482913");
+        assert_eq!(secrets.link_token.as_deref(), Some("link-token"));
+    }
+
+    #[test]
+    fn sealed_secrets_reject_other_keys_and_jobs() {
+        let config = Config::for_tests();
+        let mut job = login_job(None);
+        job.sealed = Some(seal_secrets(&config, &job).unwrap());
+
+        let mut rotated = Config::for_tests();
+        rotated.secrets.mail_queue_secret = "another-secret".to_string();
+        assert!(open_secrets(&rotated, &job).is_none());
+
+        let transplanted = MailJob {
+            id: Uuid::new_v4().to_string(),
+            ..job
+        };
+        assert!(open_secrets(&config, &transplanted).is_none());
+        assert!(
+            open_secrets(
+                &config,
+                &MailJob {
+                    sealed: Some("short".to_string()),
+                    ..login_job(None)
+                }
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn invalid_recipient_is_rejected() {
+        let email_templates = EmailTemplates::load("templates/email", "en").unwrap();
+
+        assert!(
+            build_email(
+                &email_templates,
+                "noreply@boiler.example",
+                "https://boiler.example",
+                10,
+                &MailJob {
+                    email: "not an address".to_string(),
+                    ..login_job(None)
+                },
+            )
+            .is_err()
+        );
+    }
+}
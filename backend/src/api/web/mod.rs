@@ -1,9 +1,24 @@
+pub mod account;
+pub mod admin;
+pub mod audit;
 pub mod cookies;
+pub mod database;
 pub mod handlers;
 pub mod locks;
+pub mod mailer;
 pub mod models;
+pub mod oidc;
+#[cfg(test)]
+pub mod oidc_mock;
+#[cfg(test)]
+pub mod passkey_mock;
+pub mod passkeys;
+pub mod password;
+pub mod rate_limit;
 pub mod sessions;
 pub mod swap;
+pub mod templates;
+pub mod totp;
 pub mod twofactor;
 pub mod utilities;
 pub mod verify;
@@ -1,21 +1,149 @@
+use axum::http::header::HeaderMap;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use strum_macros::{AsRefStr, EnumString};
+use webauthn_rs::prelude::PasskeyAuthentication;
 
 #[derive(Serialize, Deserialize)]
 pub struct Account {
     pub email: String,
     pub password: String,
     pub action: Action,
+    #[serde(default)]
+    pub remember: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct RedisAccount {
     pub email: String,
     pub action: Action,
-    pub code: String,
+    pub code_hash: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub issued_timestamp: Option<i64>,
     pub password_hash: Option<String>,
+    #[serde(default)]
+    pub remember: bool,
+    #[serde(default)]
+    pub totp: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_email: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SessionRecord {
+    pub email: String,
+    pub created_at: i64,
+    pub last_seen: i64,
+    pub renewed_at: i64,
+    pub remember: bool,
+    pub hashed_ip: String,
+    pub user_agent: String,
+    #[serde(default)]
+    pub device: String,
+    #[serde(default)]
+    pub network: String,
+}
+
+pub struct NetworkHash {
+    pub current: String,
+    pub previous: Option<String>,
+}
+
+pub struct ActiveSession {
+    pub id: String,
+    pub record: SessionRecord,
+    pub headers: HeaderMap,
+}
+
+#[derive(Serialize)]
+pub struct SessionPayload {
+    pub id: String,
+    pub created_at: i64,
+    pub last_seen: i64,
+    pub hashed_ip: String,
+    pub user_agent: String,
+    pub device: String,
+    pub current: bool,
+}
+
+pub type AuditEventRow = (DateTime<Utc>, String, String, String, String);
+
+#[derive(Serialize)]
+pub struct AuditEventPayload {
+    pub event_type: String,
+    pub outcome: String,
+    pub hashed_ip: String,
+    pub user_agent: String,
+    pub created_at: i64,
+}
+
+#[derive(Serialize)]
+pub struct LockPayload {
+    pub lock: String,
+    pub hashed_ip: String,
+    pub attempts: u8,
+    pub expires_in: i64,
+}
+
+#[derive(Serialize)]
+pub struct AdminUserPayload {
+    pub email: String,
+    pub role: String,
+    pub locked: bool,
+    pub items_posted: u8,
+    pub locks: Vec<LockPayload>,
+    pub sessions: Vec<SessionPayload>,
+}
+
+#[derive(Serialize)]
+pub struct TotpEnrollment {
+    pub secret: String,
+    pub otpauth_url: String,
+    pub qr_code: String,
+}
+
+#[derive(Serialize)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PasskeyChallenge {
+    pub email: String,
+    pub authentication: PasskeyAuthentication,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct OidcChallenge {
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+#[derive(Deserialize)]
+pub struct OidcCallback {
+    pub code: String,
+    pub state: String,
+}
+
+#[derive(Serialize)]
+pub struct OidcRedirect {
+    pub url: String,
+}
+
+#[derive(Serialize)]
+pub struct OidcResult {
+    pub second_factor: bool,
+}
+
+#[derive(Deserialize)]
+pub struct PasswordChange {
+    pub current_password: String,
+    pub new_password: String,
 }
 
 #[derive(Deserialize)]
@@ -23,13 +151,21 @@ pub struct Token {
     pub token: String,
 }
 
-#[derive(Serialize, Deserialize, PartialEq)]
+#[derive(Serialize, Deserialize, PartialEq, AsRefStr)]
 #[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
 #[derive(Clone)]
 pub enum Action {
     Login,
     Signup,
     Forgot,
+    Delete,
+    #[serde(rename = "change_email")]
+    #[strum(serialize = "change_email")]
+    ChangeEmail,
+    #[serde(rename = "new_sign_in")]
+    #[strum(serialize = "new_sign_in")]
+    NewSignIn,
 }
 
 #[derive(EnumString, AsRefStr, PartialEq, Clone)]
@@ -75,10 +211,79 @@ pub enum RedisAction {
 
     #[strum(serialize = "metric")]
     Metric,
+
+    #[strum(serialize = "totp_pending")]
+    TotpPending,
+
+    #[strum(serialize = "totp_used")]
+    TotpUsed,
+
+    #[strum(serialize = "passkey_registration")]
+    PasskeyRegistration,
+
+    #[strum(serialize = "passkey_id")]
+    Passkey,
+
+    #[strum(serialize = "delete_account_id")]
+    DeleteAccount,
+
+    #[strum(serialize = "change_email_id")]
+    ChangeEmail,
+
+    #[strum(serialize = "oidc_id")]
+    Oidc,
+
+    #[strum(serialize = "known_devices")]
+    KnownDevices,
+
+    #[strum(serialize = "freeze_link")]
+    FreezeLink,
+
+    #[strum(serialize = "mail_queue")]
+    MailQueue,
+
+    #[strum(serialize = "mail_processing")]
+    MailProcessing,
+
+    #[strum(serialize = "mail_retry")]
+    MailRetry,
+
+    #[strum(serialize = "mail_dead_letter")]
+    MailDeadLetter,
+
+    #[strum(serialize = "rate_limit")]
+    RateLimit,
+
+    #[strum(serialize = "ip_rotation")]
+    IpRotation,
+
+    #[strum(serialize = "ip_locks")]
+    IpLocks,
+
+    #[strum(serialize = "code_attempts")]
+    CodeAttempts,
+
+    #[strum(serialize = "migration")]
+    Migration,
+}
+
+#[derive(EnumString, AsRefStr, PartialEq)]
+pub enum Role {
+    #[strum(serialize = "user")]
+    User,
+
+    #[strum(serialize = "admin")]
+    Admin,
+
+    #[strum(serialize = "csrf")]
+    Csrf,
 }
 
 #[derive(Deserialize)]
-pub struct DummyClaims {}
+pub struct ApiClaims {
+    pub iat: i64,
+    pub exp: i64,
+}
 
 #[derive(EnumString, AsRefStr, PartialEq, Clone)]
 pub enum WebsitePath {
@@ -92,6 +297,11 @@ pub enum WebsitePath {
     Home,
 }
 
+pub struct IssuedCode {
+    pub code: String,
+    pub link_token: Option<String>,
+}
+
 pub struct VerifiedTokenResult {
     pub serialized_account: Option<String>,
     pub redis_action: RedisAction,
@@ -123,8 +333,34 @@ pub enum WebsiteRoute {
 
     #[strum(serialize = "resend")]
     Resend,
+
+    #[strum(serialize = "sessions")]
+    Sessions,
+
+    #[strum(serialize = "totp")]
+    Totp,
+
+    #[strum(serialize = "account")]
+    Account,
+
+    #[strum(serialize = "admin")]
+    Admin,
+
+    #[strum(serialize = "passkeys")]
+    Passkeys,
+
+    #[strum(serialize = "csrf")]
+    Csrf,
+
+    #[strum(serialize = "freeze")]
+    Freeze,
+
+    #[strum(serialize = "oidc")]
+    Oidc,
 }
 
 pub const METRICS_ROUTE: &str = "/metrics";
 
 pub const PHOTOS_PREFIX: &str = "/photos/";
+
+pub const MAX_USER_AGENT_CHARS: usize = 256;
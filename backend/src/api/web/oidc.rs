@@ -0,0 +1,564 @@
+use super::{
+    cookies::{generate_cookie, get_cookie},
+    database::{get_totp, get_user},
+    models::{
+        Action, NetworkHash, OidcCallback, OidcChallenge, OidcRedirect, OidcResult, RedisAccount,
+        RedisAction, WebsitePath,
+    },
+    sessions::{create_auth_redis_account, create_session, create_temporary_session},
+    twofactor::generate_link_token,
+    verify::{get_email_policy, hash_password, normalize_email, validate_email},
+};
+use crate::{
+    AppError, AppState,
+    api::microservices::redis::insert_id,
+    config::{Config, Oidc},
+};
+use axum::http::header::HeaderMap;
+use data_encoding::BASE64URL_NOPAD;
+use jsonwebtoken::{
+    Algorithm, DecodingKey, Validation, decode, decode_header,
+    jwk::{Jwk, JwkSet},
+};
+use once_cell::sync::Lazy;
+use reqwest::{Client, Url};
+use serde::{Deserialize, de::DeserializeOwned};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+use tokio::task::spawn_blocking;
+
+const ALLOWED_ALGORITHMS: [Algorithm; 2] = [Algorithm::RS256, Algorithm::ES256];
+
+const PROVIDER_CACHE_TTL: Duration = Duration::from_secs(3600);
+
+// An unknown kid usually means the provider rotated its keys, but refetching on every
+// miss would let forged tokens hammer the provider
+const JWKS_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+static HTTP_CLIENT: Lazy<Client> = Lazy::new(|| {
+    Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .expect("reqwest client builds with static settings")
+});
+
+type ProviderCache<T> = Lazy<RwLock<HashMap<String, Cached<T>>>>;
+
+static METADATA_CACHE: ProviderCache<ProviderMetadata> = Lazy::new(Default::default);
+
+static JWKS_CACHE: ProviderCache<JwkSet> = Lazy::new(Default::default);
+
+struct Cached<T> {
+    value: Arc<T>,
+    fetched_at: Instant,
+}
+
+#[derive(Deserialize)]
+struct ProviderMetadata {
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize)]
+struct IdTokenClaims {
+    nonce: Option<String>,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+}
+
+pub async fn start_oidc_login(
+    state: Arc<AppState>,
+    website_path: &WebsitePath,
+) -> Result<(HeaderMap, OidcRedirect), AppError> {
+    if !state.config.oidc.is_enabled() {
+        return Err(AppError::BadRequest(
+            "Single sign-on unavailable".to_string(),
+        ));
+    }
+
+    let (challenge, url) = get_authorization_request(&state.config.oidc, website_path).await?;
+
+    let id = generate_link_token();
+
+    insert_id(
+        state.clone(),
+        &format!(
+            "{}:{}:{}",
+            website_path.as_ref(),
+            RedisAction::Oidc.as_ref(),
+            &id
+        ),
+        &serde_json::to_string(&challenge)?,
+        state
+            .config
+            .session
+            .temporary_session_duration_seconds
+            .into(),
+    )
+    .await?;
+
+    Ok((
+        generate_cookie(
+            &state.config,
+            RedisAction::Oidc.as_ref(),
+            &id,
+            state
+                .config
+                .session
+                .temporary_session_duration_seconds
+                .into(),
+            website_path,
+        ),
+        OidcRedirect {
+            url: url.to_string(),
+        },
+    ))
+}
+
+pub async fn take_oidc_challenge(
+    state: Arc<AppState>,
+    headers: &HeaderMap,
+    website_path: &WebsitePath,
+) -> Result<OidcChallenge, AppError> {
+    let id = get_cookie(headers, RedisAction::Oidc.as_ref())
+        .ok_or(AppError::Unauthorized("Unable to verify".to_string()))?;
+
+    let serialized: Option<String> = redis::cmd("GETDEL")
+        .arg(format!(
+            "{}:{}:{}",
+            website_path.as_ref(),
+            RedisAction::Oidc.as_ref(),
+            &id
+        ))
+        .query_async(&mut state.redis_connection_manager.clone())
+        .await?;
+
+    Ok(serde_json::from_str(&serialized.ok_or(
+        AppError::Unauthorized("Unable to verify".to_string()),
+    )?)?)
+}
+
+pub async fn verify_oidc_callback(
+    config: &Config,
+    challenge: &OidcChallenge,
+    callback: &OidcCallback,
+    website_path: &WebsitePath,
+) -> Result<String, AppError> {
+    if callback.state != challenge.state || callback.code.is_empty() {
+        return Err(AppError::Unauthorized("Unable to verify".to_string()));
+    }
+
+    let metadata = get_provider_metadata(&config.oidc).await?;
+    let redirect_uri = config.oidc.redirect_uri(website_path);
+
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", &callback.code),
+        ("redirect_uri", &redirect_uri),
+        ("client_id", &config.oidc.client_id),
+        ("code_verifier", &challenge.code_verifier),
+    ];
+
+    if let Some(client_secret) = &config.oidc.client_secret {
+        form.push(("client_secret", client_secret));
+    }
+
+    let response = HTTP_CLIENT
+        .post(&metadata.token_endpoint)
+        .form(&form)
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        return Err(AppError::Unauthorized("Unable to verify".to_string()));
+    }
+
+    let token_response: TokenResponse = serde_json::from_str(&response.text().await?)
+        .map_err(|_| AppError::Unauthorized("Unable to verify".to_string()))?;
+
+    let claims = verify_id_token(&config.oidc, &metadata, &token_response.id_token).await?;
+
+    if claims.nonce.as_deref() != Some(&challenge.nonce) || !claims.email_verified {
+        return Err(AppError::Unauthorized("Unable to verify".to_string()));
+    }
+
+    let email = normalize_email(
+        &claims
+            .email
+            .ok_or(AppError::Unauthorized("Unable to verify".to_string()))?,
+    );
+
+    validate_email(get_email_policy(config, website_path)?, &email)
+        .map_err(AppError::BadRequest)?;
+
+    Ok(email)
+}
+
+pub async fn finish_oidc_login(
+    state: Arc<AppState>,
+    email: &str,
+    locale: String,
+    hashed_ip: &str,
+    network: &NetworkHash,
+    user_agent: &str,
+    website_path: &WebsitePath,
+) -> Result<(HeaderMap, OidcResult), AppError> {
+    let redis_account = match get_user(state.clone(), email, website_path).await? {
+        Some((_, true)) => return Err(AppError::Unauthorized("Unable to verify".to_string())),
+        Some(_) => RedisAccount {
+            totp: get_totp(state.clone(), email, website_path)
+                .await?
+                .is_some(),
+            locale: Some(locale),
+            ..create_auth_redis_account(email.to_string(), Action::Login, None)
+        },
+        None => {
+            let password_hash = spawn_blocking(|| hash_password(&generate_link_token())).await?;
+
+            RedisAccount {
+                locale: Some(locale),
+                ..create_auth_redis_account(email.to_string(), Action::Signup, Some(password_hash))
+            }
+        }
+    };
+
+    if redis_account.totp {
+        return Ok((
+            create_temporary_session(
+                state.clone(),
+                &None,
+                &redis_account,
+                &RedisAction::Auth,
+                &None,
+                &None,
+                website_path,
+            )
+            .await?,
+            OidcResult {
+                second_factor: true,
+            },
+        ));
+    }
+
+    Ok((
+        create_session(
+            state.clone(),
+            &redis_account,
+            hashed_ip,
+            network,
+            user_agent,
+            website_path,
+        )
+        .await?,
+        OidcResult {
+            second_factor: false,
+        },
+    ))
+}
+
+async fn get_authorization_request(
+    oidc: &Oidc,
+    website_path: &WebsitePath,
+) -> Result<(OidcChallenge, Url), AppError> {
+    let metadata = get_provider_metadata(oidc).await?;
+
+    let challenge = OidcChallenge {
+        state: generate_link_token(),
+        nonce: generate_link_token(),
+        code_verifier: generate_link_token(),
+    };
+
+    let url = Url::parse_with_params(
+        &metadata.authorization_endpoint,
+        &[
+            ("response_type", "code"),
+            ("client_id", &oidc.client_id),
+            ("redirect_uri", &oidc.redirect_uri(website_path)),
+            ("scope", &oidc.scopes.join(" ")),
+            ("state", &challenge.state),
+            ("nonce", &challenge.nonce),
+            (
+                "code_challenge",
+                &get_code_challenge(&challenge.code_verifier),
+            ),
+            ("code_challenge_method", "S256"),
+        ],
+    )
+    .map_err(|e| AppError::Config(format!("Invalid authorization endpoint: {}", e)))?;
+
+    Ok((challenge, url))
+}
+
+async fn verify_id_token(
+    oidc: &Oidc,
+    metadata: &ProviderMetadata,
+    id_token: &str,
+) -> Result<IdTokenClaims, AppError> {
+    let header = decode_header(id_token)
+        .map_err(|_| AppError::Unauthorized("Unable to verify".to_string()))?;
+
+    if !ALLOWED_ALGORITHMS.contains(&header.alg) {
+        return Err(AppError::Unauthorized("Unable to verify".to_string()));
+    }
+
+    let jwk = get_jwk(&metadata.jwks_uri, header.kid.as_deref()).await?;
+
+    let decoding_key = DecodingKey::from_jwk(&jwk)
+        .map_err(|_| AppError::Unauthorized("Unable to verify".to_string()))?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_audience(&[&oidc.client_id]);
+    validation.set_issuer(&[&oidc.issuer]);
+    validation.set_required_spec_claims(&["exp", "iat", "aud", "iss", "sub"]);
+
+    Ok(
+        decode::<IdTokenClaims>(id_token, &decoding_key, &validation)
+            .map_err(|_| AppError::Unauthorized("Unable to verify".to_string()))?
+            .claims,
+    )
+}
+
+async fn get_jwk(jwks_uri: &str, kid: Option<&str>) -> Result<Jwk, AppError> {
+    let jwks = get_cached(&JWKS_CACHE, jwks_uri, PROVIDER_CACHE_TTL).await?;
+
+    if let Some(jwk) = find_jwk(&jwks, kid) {
+        return Ok(jwk.clone());
+    }
+
+    let jwks = get_cached(&JWKS_CACHE, jwks_uri, JWKS_REFRESH_INTERVAL).await?;
+
+    find_jwk(&jwks, kid)
+        .cloned()
+        .ok_or(AppError::Unauthorized("Unable to verify".to_string()))
+}
+
+fn find_jwk<'a>(jwks: &'a JwkSet, kid: Option<&str>) -> Option<&'a Jwk> {
+    match kid {
+        Some(kid) => jwks.find(kid),
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    }
+}
+
+async fn get_provider_metadata(oidc: &Oidc) -> Result<Arc<ProviderMetadata>, AppError> {
+    get_cached(
+        &METADATA_CACHE,
+        &format!("{}/.well-known/openid-configuration", oidc.issuer),
+        PROVIDER_CACHE_TTL,
+    )
+    .await
+}
+
+async fn get_cached<T: DeserializeOwned>(
+    cache: &RwLock<HashMap<String, Cached<T>>>,
+    url: &str,
+    max_age: Duration,
+) -> Result<Arc<T>, AppError> {
+    let cached = cache
+        .read()
+        .unwrap()
+        .get(url)
+        .filter(|cached| cached.fetched_at.elapsed() < max_age)
+        .map(|cached| cached.value.clone());
+
+    if let Some(value) = cached {
+        return Ok(value);
+    }
+
+    let value = Arc::new(fetch_json::<T>(url).await?);
+
+    cache.write().unwrap().insert(
+        url.to_string(),
+        Cached {
+            value: value.clone(),
+            fetched_at: Instant::now(),
+        },
+    );
+
+    Ok(value)
+}
+
+async fn fetch_json<T: DeserializeOwned>(url: &str) -> Result<T, AppError> {
+    let response = HTTP_CLIENT.get(url).send().await?.error_for_status()?;
+
+    Ok(serde_json::from_str(&response.text().await?)?)
+}
+
+fn get_code_challenge(code_verifier: &str) -> String {
+    BASE64URL_NOPAD.encode(&Sha256::digest(code_verifier.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::web::{models::WebsitePath, oidc_mock::MockProvider};
+    use reqwest::{header::LOCATION, redirect::Policy};
+
+    async fn start(email: &str) -> (MockProvider, Config) {
+        let provider = MockProvider::start(email).await;
+
+        let mut config = Config::for_tests();
+        config.oidc = Oidc {
+            issuer: provider.issuer.clone(),
+            client_id: "boiler".to_string(),
+            client_secret: None,
+            redirect_base_url: "http://localhost:5173".to_string(),
+            scopes: vec!["openid".to_string(), "email".to_string()],
+        };
+
+        (provider, config)
+    }
+
+    async fn authorize(config: &Config) -> (OidcChallenge, OidcCallback) {
+        let (challenge, url) = get_authorization_request(&config.oidc, &WebsitePath::BoilerSwap)
+            .await
+            .unwrap();
+
+        let response = Client::builder()
+            .redirect(Policy::none())
+            .build()
+            .unwrap()
+            .get(url)
+            .send()
+            .await
+            .unwrap();
+
+        let location = Url::parse(response.headers()[LOCATION].to_str().unwrap()).unwrap();
+        let params: HashMap<_, _> = location.query_pairs().into_owned().collect();
+
+        (
+            challenge,
+            OidcCallback {
+                code: params["code"].clone(),
+                state: params["state"].clone(),
+            },
+        )
+    }
+
+    async fn login(
+        config: &Config,
+        challenge: &OidcChallenge,
+        callback: &OidcCallback,
+    ) -> Result<String, AppError> {
+        verify_oidc_callback(config, challenge, callback, &WebsitePath::BoilerSwap).await
+    }
+
+    #[tokio::test]
+    async fn valid_login_returns_normalized_email() {
+        let (_provider, config) = start("Student@Purdue.edu").await;
+        let (challenge, callback) = authorize(&config).await;
+
+        assert_eq!(
+            login(&config, &challenge, &callback).await.unwrap(),
+            "student@purdue.edu"
+        );
+    }
+
+    #[tokio::test]
+    async fn state_mismatch_is_rejected() {
+        let (_provider, config) = start("student@purdue.edu").await;
+        let (challenge, mut callback) = authorize(&config).await;
+        callback.state = generate_link_token();
+
+        assert!(matches!(
+            login(&config, &challenge, &callback).await,
+            Err(AppError::Unauthorized(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn nonce_mismatch_is_rejected() {
+        let (_provider, config) = start("student@purdue.edu").await;
+        let (mut challenge, callback) = authorize(&config).await;
+        challenge.nonce = generate_link_token();
+
+        assert!(matches!(
+            login(&config, &challenge, &callback).await,
+            Err(AppError::Unauthorized(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn wrong_code_verifier_is_rejected() {
+        let (_provider, config) = start("student@purdue.edu").await;
+        let (mut challenge, callback) = authorize(&config).await;
+        challenge.code_verifier = generate_link_token();
+
+        assert!(matches!(
+            login(&config, &challenge, &callback).await,
+            Err(AppError::Unauthorized(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn untrusted_signature_is_rejected() {
+        let (provider, config) = start("student@purdue.edu").await;
+        provider.sign_with_untrusted_key();
+        let (challenge, callback) = authorize(&config).await;
+
+        assert!(matches!(
+            login(&config, &challenge, &callback).await,
+            Err(AppError::Unauthorized(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn wrong_audience_is_rejected() {
+        let (provider, config) = start("student@purdue.edu").await;
+        provider.issue_for_audience("someone-else");
+        let (challenge, callback) = authorize(&config).await;
+
+        assert!(matches!(
+            login(&config, &challenge, &callback).await,
+            Err(AppError::Unauthorized(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn disallowed_email_is_rejected() {
+        let (_provider, config) = start("student@example.com").await;
+        let (challenge, callback) = authorize(&config).await;
+
+        assert!(matches!(
+            login(&config, &challenge, &callback).await,
+            Err(AppError::BadRequest(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn rotated_kid_refetches_jwks_after_refresh_interval() {
+        let (provider, config) = start("student@purdue.edu").await;
+        let (challenge, callback) = authorize(&config).await;
+        login(&config, &challenge, &callback).await.unwrap();
+
+        provider.rotate_kid("rotated");
+        let (challenge, callback) = authorize(&config).await;
+
+        assert!(matches!(
+            login(&config, &challenge, &callback).await,
+            Err(AppError::Unauthorized(_))
+        ));
+
+        for cached in JWKS_CACHE.write().unwrap().values_mut() {
+            cached.fetched_at -= JWKS_REFRESH_INTERVAL;
+        }
+
+        let (challenge, callback) = authorize(&config).await;
+
+        assert_eq!(
+            login(&config, &challenge, &callback).await.unwrap(),
+            "student@purdue.edu"
+        );
+    }
+}
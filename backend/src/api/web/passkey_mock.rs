@@ -0,0 +1,156 @@
+use data_encoding::BASE64URL_NOPAD;
+use ring::{
+    rand::{SecureRandom, SystemRandom},
+    signature::{ECDSA_P256_SHA256_ASN1_SIGNING, EcdsaKeyPair, KeyPair},
+};
+use serde_cbor_2::Value;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use webauthn_rs::prelude::{
+    CreationChallengeResponse, PublicKeyCredential, RegisterPublicKeyCredential,
+    RequestChallengeResponse,
+};
+
+const USER_PRESENT: u8 = 0x01;
+
+const USER_VERIFIED: u8 = 0x04;
+
+const ATTESTED_CREDENTIAL: u8 = 0x40;
+
+pub struct SoftAuthenticator {
+    origin: String,
+    key_pair: EcdsaKeyPair,
+    credential_id: Vec<u8>,
+    counter: u32,
+    rng: SystemRandom,
+}
+
+impl SoftAuthenticator {
+    pub fn new(origin: &str) -> Self {
+        let rng = SystemRandom::new();
+
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng)
+            .expect("P-256 key generates");
+        let key_pair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng)
+                .expect("generated key parses");
+
+        let mut credential_id = vec![0u8; 16];
+        rng.fill(&mut credential_id).expect("rng fills");
+
+        Self {
+            origin: origin.to_string(),
+            key_pair,
+            credential_id,
+            counter: 0,
+            rng,
+        }
+    }
+
+    pub fn register(
+        &mut self,
+        challenge: &CreationChallengeResponse,
+    ) -> RegisterPublicKeyCredential {
+        let client_data =
+            self.get_client_data("webauthn.create", challenge.public_key.challenge.as_ref());
+
+        let mut auth_data = self.get_auth_data(
+            &challenge.public_key.rp.id,
+            USER_PRESENT | USER_VERIFIED | ATTESTED_CREDENTIAL,
+        );
+        auth_data.extend_from_slice(&[0u8; 16]);
+        auth_data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+        auth_data.extend_from_slice(&self.credential_id);
+        auth_data.extend_from_slice(&self.get_cose_key());
+
+        let attestation_object = serde_cbor_2::to_vec(&Value::Map(BTreeMap::from([
+            (
+                Value::Text("fmt".to_string()),
+                Value::Text("none".to_string()),
+            ),
+            (
+                Value::Text("attStmt".to_string()),
+                Value::Map(BTreeMap::new()),
+            ),
+            (Value::Text("authData".to_string()), Value::Bytes(auth_data)),
+        ])))
+        .expect("attestation object encodes");
+
+        serde_json::from_value(json!({
+            "id": BASE64URL_NOPAD.encode(&self.credential_id),
+            "rawId": BASE64URL_NOPAD.encode(&self.credential_id),
+            "response": {
+                "attestationObject": BASE64URL_NOPAD.encode(&attestation_object),
+                "clientDataJSON": BASE64URL_NOPAD.encode(&client_data),
+            },
+            "type": "public-key",
+        }))
+        .expect("registration credential deserializes")
+    }
+
+    pub fn sign(&mut self, challenge: &RequestChallengeResponse) -> PublicKeyCredential {
+        let client_data =
+            self.get_client_data("webauthn.get", challenge.public_key.challenge.as_ref());
+
+        let auth_data =
+            self.get_auth_data(&challenge.public_key.rp_id, USER_PRESENT | USER_VERIFIED);
+
+        let mut signed = auth_data.clone();
+        signed.extend_from_slice(&Sha256::digest(&client_data));
+
+        let signature = self
+            .key_pair
+            .sign(&self.rng, &signed)
+            .expect("assertion signs");
+
+        serde_json::from_value(json!({
+            "id": BASE64URL_NOPAD.encode(&self.credential_id),
+            "rawId": BASE64URL_NOPAD.encode(&self.credential_id),
+            "response": {
+                "authenticatorData": BASE64URL_NOPAD.encode(&auth_data),
+                "clientDataJSON": BASE64URL_NOPAD.encode(&client_data),
+                "signature": BASE64URL_NOPAD.encode(signature.as_ref()),
+                "userHandle": null,
+            },
+            "type": "public-key",
+        }))
+        .expect("assertion credential deserializes")
+    }
+
+    fn get_client_data(&self, client_data_type: &str, challenge: &[u8]) -> Vec<u8> {
+        serde_json::to_vec(&json!({
+            "type": client_data_type,
+            "challenge": BASE64URL_NOPAD.encode(challenge),
+            "origin": self.origin,
+            "crossOrigin": false,
+        }))
+        .expect("client data serializes")
+    }
+
+    fn get_auth_data(&mut self, rp_id: &str, flags: u8) -> Vec<u8> {
+        self.counter += 1;
+
+        let mut auth_data = Sha256::digest(rp_id.as_bytes()).to_vec();
+        auth_data.push(flags);
+        auth_data.extend_from_slice(&self.counter.to_be_bytes());
+
+        auth_data
+    }
+
+    fn get_cose_key(&self) -> Vec<u8> {
+        let public_key = self.key_pair.public_key().as_ref();
+
+        serde_cbor_2::to_vec(&Value::Map(BTreeMap::from([
+            (Value::Integer(1), Value::Integer(2)),
+            (Value::Integer(3), Value::Integer(-7)),
+            (Value::Integer(-1), Value::Integer(1)),
+            (Value::Integer(-2), Value::Bytes(public_key[1..33].to_vec())),
+            (
+                Value::Integer(-3),
+                Value::Bytes(public_key[33..65].to_vec()),
+            ),
+        ])))
+        .expect("COSE key encodes")
+    }
+}
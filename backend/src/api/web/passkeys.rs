@@ -0,0 +1,470 @@
+use super::{
+    cookies::{generate_cookie, get_cookie},
+    database::{get_passkeys, get_user, insert_passkey},
+    locks::{check_db_lock, check_redis_lock, increment_lock_key},
+    models::{Action, NetworkHash, PasskeyChallenge, RedisAccount, RedisAction, WebsitePath},
+    sessions::create_session,
+    utilities::get_key,
+};
+use crate::{
+    AppError, AppState,
+    api::microservices::redis::{insert_id, remove_id, try_get},
+};
+use axum::http::header::HeaderMap;
+use chrono::Utc;
+use data_encoding::BASE64URL_NOPAD;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use uuid::Uuid;
+use webauthn_rs::prelude::{
+    AuthenticationResult, CreationChallengeResponse, Passkey, PasskeyRegistration,
+    PublicKeyCredential, RegisterPublicKeyCredential, RequestChallengeResponse, Webauthn,
+};
+
+pub async fn start_passkey_registration(
+    state: Arc<AppState>,
+    email: &str,
+    website_path: &WebsitePath,
+) -> Result<CreationChallengeResponse, AppError> {
+    let (challenge, registration) = get_registration_challenge(
+        &state.webauthn,
+        email,
+        &load_passkeys(state.clone(), email, website_path).await?,
+    )?;
+
+    insert_id(
+        state.clone(),
+        &format!(
+            "{}:{}:{}",
+            website_path.as_ref(),
+            RedisAction::PasskeyRegistration.as_ref(),
+            email
+        ),
+        &serde_json::to_string(&registration)?,
+        state
+            .config
+            .session
+            .temporary_session_duration_seconds
+            .into(),
+    )
+    .await?;
+
+    Ok(challenge)
+}
+
+pub async fn finish_passkey_registration(
+    state: Arc<AppState>,
+    email: &str,
+    credential: &RegisterPublicKeyCredential,
+    website_path: &WebsitePath,
+) -> Result<(), AppError> {
+    let registration_key = format!(
+        "{}:{}:{}",
+        website_path.as_ref(),
+        RedisAction::PasskeyRegistration.as_ref(),
+        email
+    );
+
+    let registration: PasskeyRegistration =
+        serde_json::from_str(&try_get(state.clone(), &registration_key).await?.ok_or(
+            AppError::BadRequest("No registration in progress".to_string()),
+        )?)?;
+
+    remove_id(state.clone(), &registration_key).await?;
+
+    let passkey = state
+        .webauthn
+        .finish_passkey_registration(credential, &registration)
+        .map_err(|_| AppError::Unauthorized("Unable to verify".to_string()))?;
+
+    store_passkey(state.clone(), email, &passkey, website_path).await?;
+
+    Ok(())
+}
+
+pub async fn start_passkey_login(
+    state: Arc<AppState>,
+    email: &str,
+    hashed_ip: &str,
+    website_path: &WebsitePath,
+) -> Result<(HeaderMap, RequestChallengeResponse), AppError> {
+    check_redis_lock(
+        state.clone(),
+        website_path.as_ref(),
+        &get_key(RedisAction::LockedAuth, hashed_ip),
+        email,
+        &state.config.authentication.auth_max_attempts,
+    )
+    .await?;
+
+    let passkeys = match get_user(state.clone(), email, website_path).await? {
+        Some((_, false)) => load_passkeys(state.clone(), email, website_path).await?,
+        _ => Vec::new(),
+    };
+
+    let (challenge, passkey_challenge) =
+        get_authentication_challenge(&state.webauthn, email, &passkeys)?;
+
+    let id = Uuid::new_v4().to_string();
+
+    insert_id(
+        state.clone(),
+        &format!(
+            "{}:{}:{}",
+            website_path.as_ref(),
+            RedisAction::Passkey.as_ref(),
+            &id
+        ),
+        &serde_json::to_string(&passkey_challenge)?,
+        state
+            .config
+            .session
+            .temporary_session_duration_seconds
+            .into(),
+    )
+    .await?;
+
+    Ok((
+        generate_cookie(
+            &state.config,
+            RedisAction::Passkey.as_ref(),
+            &id,
+            state
+                .config
+                .session
+                .temporary_session_duration_seconds
+                .into(),
+            website_path,
+        ),
+        challenge,
+    ))
+}
+
+pub async fn take_passkey_challenge(
+    state: Arc<AppState>,
+    headers: &HeaderMap,
+    website_path: &WebsitePath,
+) -> Result<PasskeyChallenge, AppError> {
+    let id = get_cookie(headers, RedisAction::Passkey.as_ref())
+        .ok_or(AppError::Unauthorized("Unable to verify".to_string()))?;
+
+    let challenge_key = format!(
+        "{}:{}:{}",
+        website_path.as_ref(),
+        RedisAction::Passkey.as_ref(),
+        &id
+    );
+
+    let challenge: PasskeyChallenge = serde_json::from_str(
+        &try_get(state.clone(), &challenge_key)
+            .await?
+            .ok_or(AppError::Unauthorized("Unable to verify".to_string()))?,
+    )?;
+
+    remove_id(state.clone(), &challenge_key).await?;
+
+    Ok(challenge)
+}
+
+pub async fn finish_passkey_login(
+    state: Arc<AppState>,
+    challenge: PasskeyChallenge,
+    credential: &PublicKeyCredential,
+    hashed_ip: &str,
+    network: &NetworkHash,
+    user_agent: &str,
+    website_path: &WebsitePath,
+) -> Result<HeaderMap, AppError> {
+    let Ok(result) = state
+        .webauthn
+        .finish_passkey_authentication(credential, &challenge.authentication)
+    else {
+        increment_lock_key(
+            state.clone(),
+            website_path.as_ref(),
+            &get_key(RedisAction::LockedAuth, hashed_ip),
+            &challenge.email,
+            &state.config.authentication.auth_lock_duration_seconds,
+            &state.config.authentication.auth_max_attempts,
+        )
+        .await?;
+
+        return Err(AppError::Unauthorized("Unable to verify".to_string()));
+    };
+
+    if check_db_lock(state.clone(), &challenge.email, website_path).await? {
+        return Err(AppError::Unauthorized("Unable to verify".to_string()));
+    }
+
+    if result.needs_update() {
+        for passkey in get_updated_passkeys(
+            load_passkeys(state.clone(), &challenge.email, website_path).await?,
+            &result,
+        ) {
+            store_passkey(state.clone(), &challenge.email, &passkey, website_path).await?;
+        }
+    }
+
+    create_session(
+        state.clone(),
+        &RedisAccount {
+            email: challenge.email,
+            action: Action::Login,
+            code_hash: String::new(),
+            issued_timestamp: Some(Utc::now().timestamp_millis()),
+            password_hash: None,
+            remember: false,
+            totp: false,
+            locale: None,
+            link_hash: None,
+            previous_email: None,
+        },
+        hashed_ip,
+        network,
+        user_agent,
+        website_path,
+    )
+    .await
+}
+
+fn get_registration_challenge(
+    webauthn: &Webauthn,
+    email: &str,
+    existing_passkeys: &[Passkey],
+) -> Result<(CreationChallengeResponse, PasskeyRegistration), AppError> {
+    Ok(webauthn.start_passkey_registration(
+        get_user_handle(email),
+        email,
+        email,
+        Some(
+            existing_passkeys
+                .iter()
+                .map(|passkey| passkey.cred_id().clone())
+                .collect(),
+        ),
+    )?)
+}
+
+fn get_authentication_challenge(
+    webauthn: &Webauthn,
+    email: &str,
+    passkeys: &[Passkey],
+) -> Result<(RequestChallengeResponse, PasskeyChallenge), AppError> {
+    if passkeys.is_empty() {
+        return Err(AppError::Unauthorized("Unable to verify".to_string()));
+    }
+
+    let (challenge, authentication) = webauthn.start_passkey_authentication(passkeys)?;
+
+    Ok((
+        challenge,
+        PasskeyChallenge {
+            email: email.to_string(),
+            authentication,
+        },
+    ))
+}
+
+fn get_updated_passkeys(passkeys: Vec<Passkey>, result: &AuthenticationResult) -> Vec<Passkey> {
+    passkeys
+        .into_iter()
+        .filter_map(|mut passkey| {
+            (passkey.update_credential(result) == Some(true)).then_some(passkey)
+        })
+        .collect()
+}
+
+async fn load_passkeys(
+    state: Arc<AppState>,
+    email: &str,
+    website_path: &WebsitePath,
+) -> Result<Vec<Passkey>, AppError> {
+    get_passkeys(state.clone(), email, website_path)
+        .await?
+        .iter()
+        .map(|serialized| Ok(serde_json::from_str(serialized)?))
+        .collect()
+}
+
+async fn store_passkey(
+    state: Arc<AppState>,
+    email: &str,
+    passkey: &Passkey,
+    website_path: &WebsitePath,
+) -> Result<(), AppError> {
+    insert_passkey(
+        state.clone(),
+        email,
+        &BASE64URL_NOPAD.encode(passkey.cred_id()),
+        &serde_json::to_string(passkey)?,
+        website_path,
+    )
+    .await
+}
+
+fn get_user_handle(email: &str) -> Uuid {
+    let mut hasher = Sha256::new();
+    hasher.update(email.as_bytes());
+
+    let digest = hasher.finalize();
+
+    Uuid::from_slice(&digest[..16]).expect("digest has at least 16 bytes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{api::web::passkey_mock::SoftAuthenticator, config::Config, state::init_webauthn};
+
+    fn register(webauthn: &Webauthn, authenticator: &mut SoftAuthenticator) -> Passkey {
+        let (challenge, registration) =
+            get_registration_challenge(webauthn, "pete@purdue.edu", &[]).unwrap();
+        let registration: PasskeyRegistration =
+            serde_json::from_str(&serde_json::to_string(&registration).unwrap()).unwrap();
+
+        webauthn
+            .finish_passkey_registration(&authenticator.register(&challenge), &registration)
+            .unwrap()
+    }
+
+    fn start_login(
+        webauthn: &Webauthn,
+        passkey: &Passkey,
+    ) -> (RequestChallengeResponse, PasskeyChallenge) {
+        let (challenge, passkey_challenge) = get_authentication_challenge(
+            webauthn,
+            "pete@purdue.edu",
+            std::slice::from_ref(passkey),
+        )
+        .unwrap();
+
+        (
+            challenge,
+            serde_json::from_str(&serde_json::to_string(&passkey_challenge).unwrap()).unwrap(),
+        )
+    }
+
+    #[test]
+    fn registered_passkey_signs_in() {
+        let config = Config::for_tests();
+        let webauthn = init_webauthn(&config).unwrap();
+        let mut authenticator = SoftAuthenticator::new(&config.server.svelte_url);
+
+        let passkey = register(&webauthn, &mut authenticator);
+        let (challenge, passkey_challenge) = start_login(&webauthn, &passkey);
+
+        let result = webauthn
+            .finish_passkey_authentication(
+                &authenticator.sign(&challenge),
+                &passkey_challenge.authentication,
+            )
+            .unwrap();
+
+        assert_eq!(passkey_challenge.email, "pete@purdue.edu");
+        assert_eq!(result.cred_id(), passkey.cred_id());
+    }
+
+    #[test]
+    fn assertion_for_another_challenge_is_rejected() {
+        let config = Config::for_tests();
+        let webauthn = init_webauthn(&config).unwrap();
+        let mut authenticator = SoftAuthenticator::new(&config.server.svelte_url);
+
+        let passkey = register(&webauthn, &mut authenticator);
+        let (challenge, _) = start_login(&webauthn, &passkey);
+        let (_, other_challenge) = start_login(&webauthn, &passkey);
+
+        assert!(
+            webauthn
+                .finish_passkey_authentication(
+                    &authenticator.sign(&challenge),
+                    &other_challenge.authentication,
+                )
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn passkey_from_another_origin_is_rejected() {
+        let config = Config::for_tests();
+        let webauthn = init_webauthn(&config).unwrap();
+        let mut authenticator = SoftAuthenticator::new("https://evil.example");
+
+        let (challenge, registration) =
+            get_registration_challenge(&webauthn, "pete@purdue.edu", &[]).unwrap();
+
+        assert!(
+            webauthn
+                .finish_passkey_registration(&authenticator.register(&challenge), &registration)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn registration_excludes_existing_passkeys() {
+        let config = Config::for_tests();
+        let webauthn = init_webauthn(&config).unwrap();
+        let passkey = register(
+            &webauthn,
+            &mut SoftAuthenticator::new(&config.server.svelte_url),
+        );
+
+        let (challenge, _) = get_registration_challenge(
+            &webauthn,
+            "pete@purdue.edu",
+            std::slice::from_ref(&passkey),
+        )
+        .unwrap();
+
+        assert!(
+            challenge
+                .public_key
+                .exclude_credentials
+                .unwrap()
+                .iter()
+                .any(|credential| credential.id == *passkey.cred_id())
+        );
+    }
+
+    #[test]
+    fn login_without_passkeys_is_rejected() {
+        let webauthn = init_webauthn(&Config::for_tests()).unwrap();
+
+        assert!(matches!(
+            get_authentication_challenge(&webauthn, "pete@purdue.edu", &[]),
+            Err(AppError::Unauthorized(_))
+        ));
+    }
+
+    #[test]
+    fn sign_in_updates_the_stored_counter() {
+        let config = Config::for_tests();
+        let webauthn = init_webauthn(&config).unwrap();
+        let mut authenticator = SoftAuthenticator::new(&config.server.svelte_url);
+
+        let passkey = register(&webauthn, &mut authenticator);
+        let (challenge, passkey_challenge) = start_login(&webauthn, &passkey);
+
+        let result = webauthn
+            .finish_passkey_authentication(
+                &authenticator.sign(&challenge),
+                &passkey_challenge.authentication,
+            )
+            .unwrap();
+
+        assert!(result.needs_update());
+        assert_eq!(get_updated_passkeys(vec![passkey], &result).len(), 1);
+    }
+
+    #[test]
+    fn user_handle_is_stable_per_email() {
+        assert_eq!(
+            get_user_handle("pete@purdue.edu"),
+            get_user_handle("pete@purdue.edu")
+        );
+        assert_ne!(
+            get_user_handle("pete@purdue.edu"),
+            get_user_handle("other@purdue.edu")
+        );
+    }
+}
@@ -0,0 +1,205 @@
+use crate::{AppError, config::Passwords};
+use std::fs::read_to_string;
+
+const MIN_LOCAL_PART_CHARS: usize = 3;
+
+pub struct PasswordPolicy {
+    pub min_entropy_bits: f64,
+    pub blocklist: Vec<String>,
+}
+
+impl PasswordPolicy {
+    pub fn load(passwords: &Passwords) -> Result<Self, AppError> {
+        let mut blocklist: Vec<String> = read_to_string(&passwords.blocklist_path)
+            .map_err(|e| {
+                AppError::Config(format!(
+                    "Failed to load password blocklist {}: {}",
+                    passwords.blocklist_path, e
+                ))
+            })?
+            .lines()
+            .map(|line| line.trim().to_lowercase())
+            .filter(|line| !line.is_empty())
+            .collect();
+
+        blocklist.sort_unstable();
+        blocklist.dedup();
+
+        Ok(Self {
+            min_entropy_bits: passwords.min_entropy_bits,
+            blocklist,
+        })
+    }
+
+    pub fn check(&self, email: &str, password: &str) -> Result<(), String> {
+        let lowercase = password.to_lowercase();
+
+        if self.is_blocklisted(&lowercase) || self.is_blocklisted(get_repeated_unit(&lowercase)) {
+            return Err("Password is too common".to_string());
+        }
+
+        if let Some((local_part, _)) = email.to_lowercase().rsplit_once('@')
+            && local_part.chars().count() >= MIN_LOCAL_PART_CHARS
+            && lowercase.contains(local_part)
+        {
+            return Err("Password cannot contain your email".to_string());
+        }
+
+        if estimate_entropy(get_repeated_unit(password)) < self.min_entropy_bits {
+            return Err("Password is too predictable".to_string());
+        }
+
+        Ok(())
+    }
+
+    fn is_blocklisted(&self, lowercase: &str) -> bool {
+        let stripped = lowercase.trim_end_matches(|c: char| !c.is_alphabetic());
+
+        [lowercase, stripped].iter().any(|candidate| {
+            self.blocklist
+                .binary_search_by(|entry| entry.as_str().cmp(candidate))
+                .is_ok()
+        })
+    }
+}
+
+fn get_repeated_unit(password: &str) -> &str {
+    let chars: Vec<(usize, char)> = password.char_indices().collect();
+
+    (1..=chars.len() / 2)
+        .find(|period| {
+            (*period..chars.len()).all(|index| chars[index].1 == chars[index - period].1)
+        })
+        .map_or(password, |period| &password[..chars[period].0])
+}
+
+fn estimate_entropy(password: &str) -> f64 {
+    let mut pool = 0;
+
+    if password.chars().any(|c| c.is_ascii_lowercase()) {
+        pool += 26;
+    }
+
+    if password.chars().any(|c| c.is_ascii_uppercase()) {
+        pool += 26;
+    }
+
+    if password.chars().any(|c| c.is_ascii_digit()) {
+        pool += 10;
+    }
+
+    if password
+        .chars()
+        .any(|c| c.is_ascii_punctuation() || c == ' ')
+    {
+        pool += 33;
+    }
+
+    if !password.is_ascii() {
+        pool += 100;
+    }
+
+    if pool == 0 {
+        return 0.0;
+    }
+
+    let mut effective_length = 0.0;
+    let mut previous: Option<i64> = None;
+    let mut previous_step: Option<i64> = None;
+
+    for c in password.chars().map(|c| i64::from(u32::from(c))) {
+        let step = previous.map(|p| c - p).filter(|step| step.abs() <= 1);
+
+        effective_length += match step {
+            Some(_) if step == previous_step => 0.0,
+            Some(_) => 0.5,
+            None => 1.0,
+        };
+
+        previous = Some(c);
+        previous_step = step;
+    }
+
+    effective_length * f64::from(pool).log2()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> PasswordPolicy {
+        PasswordPolicy::load(&Passwords {
+            min_entropy_bits: 40.0,
+            blocklist_path: "data/common_passwords.txt".to_string(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn accepts_strong_passwords() {
+        let policy = policy();
+
+        assert!(
+            policy
+                .check("pete@purdue.edu", "correct horse battery staple")
+                .is_ok()
+        );
+        assert!(policy.check("pete@purdue.edu", "Vq7#tLm2!xRz").is_ok());
+    }
+
+    #[test]
+    fn rejects_repeated_characters_and_blocks() {
+        let policy = policy();
+
+        assert_eq!(
+            policy.check("pete@purdue.edu", "aaaaaaaaaaaaaaaaaaaa"),
+            Err("Password is too predictable".to_string())
+        );
+        assert_eq!(
+            policy.check("pete@purdue.edu", "abc1abc1abc1abc1abc1"),
+            Err("Password is too predictable".to_string())
+        );
+        assert_eq!(
+            policy.check("pete@purdue.edu", "passwordpasswordpassword"),
+            Err("Password is too common".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_sequences() {
+        assert_eq!(
+            policy().check("pete@purdue.edu", "abcdefghijklmnopqrst"),
+            Err("Password is too predictable".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_common_passwords_with_trailing_symbols() {
+        let policy = policy();
+
+        assert_eq!(
+            policy.check("pete@purdue.edu", "Password1"),
+            Err("Password is too common".to_string())
+        );
+        assert_eq!(
+            policy.check("pete@purdue.edu", "qwertyuiop!!"),
+            Err("Password is too common".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_email_local_part() {
+        assert_eq!(
+            policy().check("boilermaker@purdue.edu", "Xq9!BoilerMaker#2"),
+            Err("Password cannot contain your email".to_string())
+        );
+    }
+
+    #[test]
+    fn finds_repeated_units() {
+        assert_eq!(get_repeated_unit("abcabcab"), "abc");
+        assert_eq!(get_repeated_unit("ééé"), "é");
+        assert_eq!(get_repeated_unit("abcd"), "abcd");
+        assert_eq!(get_repeated_unit(""), "");
+    }
+}
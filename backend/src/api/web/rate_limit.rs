@@ -0,0 +1,378 @@
+use super::{
+    cookies::get_cookie,
+    models::{RedisAction, WebsitePath},
+    utilities::{get_ip_pseudonyms, get_session_handle},
+    verify::normalize_email,
+};
+use crate::{
+    AppError, AppState,
+    config::{Config, RateLimitAlgorithm, RateLimitKey, RateLimitPolicy},
+};
+use axum::{
+    body::{Body, to_bytes},
+    extract::{ConnectInfo, Request},
+    http::request::Parts,
+    response::{IntoResponse, Response},
+};
+use futures_util::future::BoxFuture;
+use once_cell::sync::Lazy;
+use redis::Script;
+use serde_json::Value;
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tower::{Layer, Service};
+use uuid::Uuid;
+
+const MAX_INSPECTED_BODY_BYTES: usize = 64 * 1024;
+
+static SLIDING_WINDOW_SCRIPT: Lazy<Script> = Lazy::new(|| {
+    Script::new(
+        r#"
+        local time = redis.call("TIME")
+        local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+        local window = tonumber(ARGV[1])
+        redis.call("ZREMRANGEBYSCORE", KEYS[1], "-inf", now - window)
+        if redis.call("ZCARD", KEYS[1]) < tonumber(ARGV[2]) then
+            redis.call("ZADD", KEYS[1], now, ARGV[3])
+            redis.call("PEXPIRE", KEYS[1], window)
+            return 0
+        end
+        local oldest = redis.call("ZRANGE", KEYS[1], 0, 0, "WITHSCORES")
+        return math.max(1, tonumber(oldest[2]) + window - now)
+    "#,
+    )
+});
+
+static TOKEN_BUCKET_SCRIPT: Lazy<Script> = Lazy::new(|| {
+    Script::new(
+        r#"
+        local time = redis.call("TIME")
+        local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+        local window = tonumber(ARGV[1])
+        local limit = tonumber(ARGV[2])
+        local bucket = redis.call("HMGET", KEYS[1], "tokens", "updated")
+        local tokens = tonumber(bucket[1]) or limit
+        local updated = tonumber(bucket[2]) or now
+        tokens = math.min(limit, tokens + (now - updated) * limit / window)
+        local wait = 0
+        if tokens >= 1 then
+            tokens = tokens - 1
+        else
+            wait = math.max(1, math.ceil((1 - tokens) * window / limit))
+        end
+        redis.call("HSET", KEYS[1], "tokens", tostring(tokens), "updated", now)
+        redis.call("PEXPIRE", KEYS[1], window)
+        return wait
+    "#,
+    )
+});
+
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    state: Arc<AppState>,
+    website_path: WebsitePath,
+    route: String,
+    policies: Arc<[RateLimitPolicy]>,
+}
+
+#[derive(Clone)]
+pub struct RateLimitService<S> {
+    inner: S,
+    layer: RateLimitLayer,
+}
+
+impl RateLimitLayer {
+    pub fn new(
+        state: Arc<AppState>,
+        website_path: WebsitePath,
+        route: &str,
+        policies: &[RateLimitPolicy],
+    ) -> Self {
+        Self {
+            state,
+            website_path,
+            route: route.to_string(),
+            policies: policies.into(),
+        }
+    }
+
+    async fn enforce(&self, request: Request) -> Result<Request, AppError> {
+        let (parts, body) = request.into_parts();
+
+        let (body, body_bytes) = if self
+            .policies
+            .iter()
+            .any(|policy| matches!(policy.key, RateLimitKey::Email))
+        {
+            let bytes = to_bytes(body, MAX_INSPECTED_BODY_BYTES)
+                .await
+                .map_err(|_| AppError::BadRequest("Payload too large".to_string()))?;
+
+            (Body::from(bytes.clone()), Some(bytes))
+        } else {
+            (body, None)
+        };
+
+        for policy in self.policies.iter() {
+            let Some(id) = get_key_id(
+                &policy.key,
+                &parts,
+                body_bytes.as_deref(),
+                &self.state.config,
+            ) else {
+                continue;
+            };
+
+            let script = match policy.algorithm {
+                RateLimitAlgorithm::SlidingWindow => &SLIDING_WINDOW_SCRIPT,
+                RateLimitAlgorithm::TokenBucket => &TOKEN_BUCKET_SCRIPT,
+            };
+
+            let retry_after_ms: u64 = script
+                .key(format!(
+                    "{}:{}:{}:{}:{}",
+                    self.website_path.as_ref(),
+                    RedisAction::RateLimit.as_ref(),
+                    self.route,
+                    policy.key.as_ref(),
+                    id
+                ))
+                .arg(policy.window_ms)
+                .arg(policy.limit)
+                .arg(Uuid::new_v4().to_string())
+                .invoke_async(&mut self.state.redis_connection_manager.clone())
+                .await?;
+
+            if retry_after_ms > 0 {
+                return Err(AppError::TooManyRequests(retry_after_ms.div_ceil(1000)));
+            }
+        }
+
+        Ok(Request::from_parts(parts, body))
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+impl<S> Service<Request> for RateLimitService<S>
+where
+    S: Service<Request, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Response, Infallible>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let ready_inner = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, ready_inner);
+        let layer = self.layer.clone();
+
+        Box::pin(async move {
+            match layer.enforce(request).await {
+                Ok(request) => inner.call(request).await,
+                Err(error) => Ok(error.into_response()),
+            }
+        })
+    }
+}
+
+fn get_key_id(
+    key: &RateLimitKey,
+    parts: &Parts,
+    body: Option<&[u8]>,
+    config: &Config,
+) -> Option<String> {
+    let hashed_ip = || {
+        parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(address)| get_ip_pseudonyms(&parts.headers, address.ip(), config).0)
+    };
+
+    match key {
+        RateLimitKey::Ip => hashed_ip(),
+        RateLimitKey::Session => get_cookie(&parts.headers, RedisAction::Session.as_ref())
+            .map(|session_id| get_session_handle(&session_id))
+            .or_else(hashed_ip),
+        RateLimitKey::Email => {
+            let payload: Value = serde_json::from_slice(body?).ok()?;
+
+            ["email", "token"]
+                .iter()
+                .filter_map(|field| payload.get(field)?.as_str())
+                .find(|value| value.contains('@'))
+                .map(normalize_email)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::header::COOKIE;
+    use redis::aio::MultiplexedConnection;
+
+    fn parts(cookie: Option<&str>) -> Parts {
+        let mut builder = Request::builder().uri("/swap/api/authenticate");
+
+        if let Some(cookie) = cookie {
+            builder = builder.header(COOKIE, cookie);
+        }
+
+        builder.body(()).unwrap().into_parts().0
+    }
+
+    #[test]
+    fn email_key_reads_normalized_email_from_body() {
+        let config = Config::for_tests();
+
+        assert_eq!(
+            get_key_id(
+                &RateLimitKey::Email,
+                &parts(None),
+                Some(br#"{"email":" Pete@Purdue.edu ","password":"hunter22"}"#),
+                &config
+            ),
+            Some("pete@purdue.edu".to_string())
+        );
+        assert_eq!(
+            get_key_id(
+                &RateLimitKey::Email,
+                &parts(None),
+                Some(br#"{"token":"pete@purdue.edu"}"#),
+                &config
+            ),
+            Some("pete@purdue.edu".to_string())
+        );
+    }
+
+    #[test]
+    fn email_key_skips_bodies_without_email() {
+        let config = Config::for_tests();
+
+        for body in [&br#"{"token":"123456"}"#[..], b"not json", b""] {
+            assert_eq!(
+                get_key_id(&RateLimitKey::Email, &parts(None), Some(body), &config),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn session_key_uses_session_handle() {
+        let config = Config::for_tests();
+
+        assert_eq!(
+            get_key_id(
+                &RateLimitKey::Session,
+                &parts(Some("session_id=abc")),
+                None,
+                &config
+            ),
+            Some(get_session_handle("abc"))
+        );
+        assert_eq!(
+            get_key_id(&RateLimitKey::Session, &parts(None), None, &config),
+            None
+        );
+    }
+
+    async fn redis_connection() -> MultiplexedConnection {
+        redis::Client::open(
+            std::env::var("REDIS_TEST_URL").unwrap_or("redis://127.0.0.1:6379".to_string()),
+        )
+        .unwrap()
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap()
+    }
+
+    async fn invoke(
+        connection: &mut MultiplexedConnection,
+        script: &Script,
+        key: &str,
+        window_ms: u64,
+        limit: u32,
+    ) -> u64 {
+        script
+            .key(key)
+            .arg(window_ms)
+            .arg(limit)
+            .arg(Uuid::new_v4().to_string())
+            .invoke_async(connection)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Redis at REDIS_TEST_URL"]
+    async fn sliding_window_blocks_after_limit() {
+        let mut connection = redis_connection().await;
+        let key = format!("test:rate_limit:{}", Uuid::new_v4());
+
+        for _ in 0..3 {
+            assert_eq!(
+                invoke(&mut connection, &SLIDING_WINDOW_SCRIPT, &key, 60_000, 3).await,
+                0
+            );
+        }
+
+        let retry_after_ms = invoke(&mut connection, &SLIDING_WINDOW_SCRIPT, &key, 60_000, 3).await;
+        assert!(retry_after_ms > 0 && retry_after_ms <= 60_000);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Redis at REDIS_TEST_URL"]
+    async fn sliding_window_admits_again_after_window() {
+        let mut connection = redis_connection().await;
+        let key = format!("test:rate_limit:{}", Uuid::new_v4());
+
+        assert_eq!(
+            invoke(&mut connection, &SLIDING_WINDOW_SCRIPT, &key, 100, 1).await,
+            0
+        );
+        assert!(invoke(&mut connection, &SLIDING_WINDOW_SCRIPT, &key, 100, 1).await > 0);
+
+        tokio::time::sleep(std::time::Duration::from_millis(150)).await;
+
+        assert_eq!(
+            invoke(&mut connection, &SLIDING_WINDOW_SCRIPT, &key, 100, 1).await,
+            0
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Redis at REDIS_TEST_URL"]
+    async fn token_bucket_spends_burst_then_waits() {
+        let mut connection = redis_connection().await;
+        let key = format!("test:rate_limit:{}", Uuid::new_v4());
+
+        for _ in 0..5 {
+            assert_eq!(
+                invoke(&mut connection, &TOKEN_BUCKET_SCRIPT, &key, 60_000, 5).await,
+                0
+            );
+        }
+
+        let retry_after_ms = invoke(&mut connection, &TOKEN_BUCKET_SCRIPT, &key, 60_000, 5).await;
+        assert!(retry_after_ms > 0 && retry_after_ms <= 12_000);
+    }
+}
@@ -1,20 +1,33 @@
 use super::{
-    cookies::generate_cookie,
-    locks::{check_locks, increment_lock_key, is_redis_locked, is_temporarily_locked},
-    models::{Account, Action, RedisAccount, RedisAction, VerifiedTokenResult, WebsitePath},
-    swap::database::{get_user, insert_user},
-    twofactor::{generate_code, spawn_code_task},
-    utilities::{clear_all_keys, get_key},
-    verify::{hash_password, verify_password},
+    cookies::{generate_cookie, generate_secondary_cookie, get_cookie},
+    database::{get_totp, get_user, insert_user, update_password},
+    locks::{
+        check_locks, create_freeze_link, increment_code_attempts, increment_lock_key,
+        is_code_exhausted, is_redis_locked, is_temporarily_locked,
+    },
+    mailer::{enqueue_code_email, enqueue_notice_email},
+    models::{
+        Account, Action, ActiveSession, IssuedCode, NetworkHash, RedisAccount, RedisAction,
+        SessionPayload, SessionRecord, VerifiedTokenResult, WebsitePath,
+    },
+    totp::verify_second_factor,
+    twofactor::{
+        LINK_TOKEN_REGEX, generate_code, generate_link_token, hash_code, is_code_hash_valid,
+    },
+    utilities::{
+        clear_all_keys, format_verified_result, get_device_label, get_key, get_session_handle,
+    },
+    verify::{check_token, hash_password, needs_rehash, verify_password},
 };
 use crate::{
     AppError, AppState,
     api::microservices::redis::{insert_id, remove_id},
+    config::Config,
 };
 use axum::http::header::HeaderMap;
 use chrono::Utc;
 use once_cell::sync::Lazy;
-use redis::{AsyncTypedCommands, Script};
+use redis::{AsyncTypedCommands, Cmd, Pipeline, Script};
 use std::sync::Arc;
 use tokio::task::spawn_blocking;
 use uuid::Uuid;
@@ -24,7 +37,9 @@ static INSERT_SESSION_SCRIPT: Lazy<Script> = Lazy::new(|| {
         r#"
         redis.call("SETEX", KEYS[1], tonumber(ARGV[3]), ARGV[2])
         local length = redis.call("LPUSH", KEYS[2], ARGV[1])
-        redis.call("EXPIRE", KEYS[2], tonumber(ARGV[3]))
+        if redis.call("TTL", KEYS[2]) < tonumber(ARGV[3]) then
+            redis.call("EXPIRE", KEYS[2], tonumber(ARGV[3]))
+        end
         if length > tonumber(ARGV[4]) then
             local removed_id = redis.call("RPOP", KEYS[2])
             local removed_key = ARGV[5] .. removed_id
@@ -34,6 +49,50 @@ static INSERT_SESSION_SCRIPT: Lazy<Script> = Lazy::new(|| {
     )
 });
 
+static RENEW_SESSION_SCRIPT: Lazy<Script> = Lazy::new(|| {
+    Script::new(
+        r#"
+        if redis.call("SET", KEYS[1], ARGV[1], "XX", "EX", tonumber(ARGV[2])) then
+            if redis.call("TTL", KEYS[2]) < tonumber(ARGV[2]) then
+                redis.call("EXPIRE", KEYS[2], tonumber(ARGV[2]))
+            end
+        end
+    "#,
+    )
+});
+
+static KNOWN_DEVICE_SCRIPT: Lazy<Script> = Lazy::new(|| {
+    Script::new(
+        r#"
+        local known = redis.call("ZSCORE", KEYS[1], ARGV[1])
+        if not known and ARGV[5] then
+            known = redis.call("ZSCORE", KEYS[1], ARGV[5])
+            redis.call("ZREM", KEYS[1], ARGV[5])
+        end
+        local count = redis.call("ZCARD", KEYS[1])
+        if not known and count >= tonumber(ARGV[3]) then
+            redis.call("ZREMRANGEBYRANK", KEYS[1], 0, count - tonumber(ARGV[3]))
+        end
+        redis.call("ZADD", KEYS[1], ARGV[4], ARGV[1])
+        redis.call("EXPIRE", KEYS[1], tonumber(ARGV[2]))
+        if not known and count > 0 then
+            return 1
+        end
+        return 0
+    "#,
+    )
+});
+
+const MAX_KNOWN_DEVICES: u8 = 20;
+
+const LAST_SEEN_INTERVAL_SECONDS: i64 = 60;
+
+enum SessionRenewal {
+    Expired,
+    Current,
+    Renew(i64),
+}
+
 pub async fn create_temporary_session(
     state: Arc<AppState>,
     result: &Option<String>,
@@ -43,10 +102,22 @@ pub async fn create_temporary_session(
     code_key: &Option<String>,
     website_path: &WebsitePath,
 ) -> Result<HeaderMap, AppError> {
+    let issued_code = get_issued_code(redis_account, redis_action);
+
+    let redis_account = &RedisAccount {
+        code_hash: hash_code(&state.config, &redis_account.email, &issued_code.code),
+        link_hash: issued_code
+            .link_token
+            .as_deref()
+            .map(|link_token| hash_code(&state.config, &redis_account.email, link_token)),
+        ..redis_account.clone()
+    };
+
     send_code(
         state.clone(),
         redis_action,
         redis_account,
+        &issued_code,
         forgot_key,
         code_key,
         website_path,
@@ -77,7 +148,21 @@ pub async fn create_temporary_session(
     )
     .await?;
 
+    if *redis_action == RedisAction::ChangeEmail {
+        return Ok(generate_secondary_cookie(
+            redis_action.as_ref(),
+            &id,
+            state
+                .config
+                .session
+                .temporary_session_duration_seconds
+                .into(),
+            website_path,
+        ));
+    }
+
     Ok(generate_cookie(
+        &state.config,
         redis_action.as_ref(),
         &id,
         state
@@ -89,25 +174,27 @@ pub async fn create_temporary_session(
     ))
 }
 
-async fn send_code(
+pub async fn send_code(
     state: Arc<AppState>,
     redis_action: &RedisAction,
     redis_account: &RedisAccount,
+    issued_code: &IssuedCode,
     forgot_key: &Option<String>,
     code_key: &Option<String>,
     website_path: &WebsitePath,
 ) -> Result<(), AppError> {
-    if *redis_action == RedisAction::Update {
+    if *redis_action == RedisAction::Update || redis_account.totp {
         return Ok(());
     }
 
-    spawn_code_task(
+    enqueue_code_email(
         state.clone(),
-        redis_account.email.clone(),
-        redis_account.code.clone(),
-        forgot_key.clone(),
-        website_path.clone(),
-    );
+        redis_account,
+        issued_code,
+        forgot_key,
+        website_path,
+    )
+    .await?;
 
     increment_lock_key(
         state.clone(),
@@ -125,6 +212,9 @@ async fn send_code(
 pub async fn create_session(
     state: Arc<AppState>,
     redis_account: &RedisAccount,
+    hashed_ip: &str,
+    network: &NetworkHash,
+    user_agent: &str,
     website_path: &WebsitePath,
 ) -> Result<HeaderMap, AppError> {
     if redis_account.action == Action::Signup {
@@ -132,6 +222,12 @@ pub async fn create_session(
     }
 
     let session_id = Uuid::new_v4().to_string();
+    let now = Utc::now().timestamp();
+    let device = get_device_label(user_agent);
+
+    let (duration_seconds, max_lifetime_seconds) =
+        get_session_lifetime(state.clone(), redis_account.remember);
+    let ttl_seconds = duration_seconds.min(max_lifetime_seconds);
 
     insert_session(
         state.clone(),
@@ -139,18 +235,85 @@ pub async fn create_session(
         RedisAction::Session.as_ref(),
         &session_id,
         RedisAction::SessionStore.as_ref(),
-        &redis_account.email,
+        &SessionRecord {
+            email: redis_account.email.clone(),
+            created_at: now,
+            last_seen: now,
+            renewed_at: now,
+            remember: redis_account.remember,
+            hashed_ip: hashed_ip.to_string(),
+            user_agent: user_agent.to_string(),
+            device: device.clone(),
+            network: network.current.clone(),
+        },
+        ttl_seconds,
     )
     .await?;
 
+    if is_new_device(
+        state.clone(),
+        website_path.as_ref(),
+        &redis_account.email,
+        &format!("{}:{}", network.current, device),
+        network
+            .previous
+            .as_ref()
+            .map(|previous| format!("{}:{}", previous, device)),
+    )
+    .await?
+        && redis_account.action != Action::Signup
+    {
+        enqueue_notice_email(
+            state.clone(),
+            &redis_account.email,
+            redis_account.locale.clone(),
+            create_freeze_link(state.clone(), &redis_account.email, website_path).await?,
+            &device,
+            website_path,
+        )
+        .await?;
+    }
+
     Ok(generate_cookie(
+        &state.config,
         RedisAction::Session.as_ref(),
         &session_id,
-        state.config.session.session_duration_seconds.into(),
+        ttl_seconds,
         website_path,
     ))
 }
 
+async fn is_new_device(
+    state: Arc<AppState>,
+    website_path: &str,
+    email: &str,
+    fingerprint: &str,
+    previous_fingerprint: Option<String>,
+) -> Result<bool, AppError> {
+    let mut invocation = KNOWN_DEVICE_SCRIPT.key(format!(
+        "{}:{}:{}",
+        website_path,
+        RedisAction::KnownDevices.as_ref(),
+        email
+    ));
+
+    invocation
+        .arg(fingerprint)
+        .arg(state.config.session.known_device_duration_seconds)
+        .arg(MAX_KNOWN_DEVICES)
+        .arg(Utc::now().timestamp_millis());
+
+    if let Some(previous_fingerprint) = previous_fingerprint {
+        invocation.arg(previous_fingerprint);
+    }
+
+    let is_new: u8 = invocation
+        .invoke_async(&mut state.redis_connection_manager.clone())
+        .await?;
+
+    Ok(is_new == 1)
+}
+
 pub async fn try_create_redis_account(
     state: Arc<AppState>,
     hashed_ip: &str,
@@ -176,7 +339,10 @@ pub async fn try_create_redis_account(
     )
     .await?;
 
-    Ok(account)
+    Ok(RedisAccount {
+        remember: payload.remember,
+        ..account
+    })
 }
 
 pub async fn get_redis_account(
@@ -231,7 +397,10 @@ pub async fn get_redis_account(
         _ => false,
     };
 
-    if !locked && verified_result.redis_action != RedisAction::Update && code != deserialized.code {
+    if !locked
+        && verified_result.redis_action != RedisAction::Update
+        && !is_code_valid(state.clone(), &deserialized, code, website_path).await?
+    {
         increment_lock_key(
             state.clone(),
             website_path.as_ref(),
@@ -242,6 +411,23 @@ pub async fn get_redis_account(
         )
         .await?;
 
+        if is_code_exhausted(
+            increment_code_attempts(state.clone(), website_path.as_ref(), &verified_result.id)
+                .await?,
+            state.config.authentication.code_max_attempts,
+        ) {
+            remove_id(
+                state.clone(),
+                &format!(
+                    "{}:{}:{}",
+                    website_path.as_ref(),
+                    verified_result.redis_action.as_ref(),
+                    &verified_result.id
+                ),
+            )
+            .await?;
+        }
+
         return Ok(None);
     }
 
@@ -263,6 +449,44 @@ pub async fn get_redis_account(
     Ok(Some(deserialized))
 }
 
+async fn is_code_valid(
+    state: Arc<AppState>,
+    redis_account: &RedisAccount,
+    code: &str,
+    website_path: &WebsitePath,
+) -> Result<bool, AppError> {
+    if LINK_TOKEN_REGEX.is_match(code) {
+        return Ok(is_link_token_valid(&state.config, redis_account, code));
+    }
+
+    if redis_account.totp {
+        return verify_second_factor(state.clone(), &redis_account.email, code, website_path).await;
+    }
+
+    Ok(is_code_hash_valid(
+        &state.config,
+        &redis_account.email,
+        code,
+        &redis_account.code_hash,
+    ))
+}
+
+fn get_issued_code(redis_account: &RedisAccount, redis_action: &RedisAction) -> IssuedCode {
+    IssuedCode {
+        code: generate_code(),
+        link_token: (*redis_action == RedisAction::Auth
+            && !redis_account.totp
+            && matches!(redis_account.action, Action::Login | Action::Signup))
+        .then(generate_link_token),
+    }
+}
+
+fn is_link_token_valid(config: &Config, redis_account: &RedisAccount, link_token: &str) -> bool {
+    redis_account.link_hash.as_deref().is_some_and(|link_hash| {
+        is_code_hash_valid(config, &redis_account.email, link_token, link_hash)
+    })
+}
+
 pub async fn create_redis_account(
     state: Arc<AppState>,
     action: Action,
@@ -312,15 +536,35 @@ pub async fn create_redis_account(
                 return Ok(None);
             }
 
-            Ok(Some(create_auth_redis_account(
-                email.to_string(),
-                action.clone(),
-                None,
-            )))
+            if action == Action::Login && needs_rehash(&hash) {
+                upgrade_password_hash(state.clone(), email, password, website_path).await?;
+            }
+
+            Ok(Some(RedisAccount {
+                totp: get_totp(state.clone(), email, website_path)
+                    .await?
+                    .is_some(),
+                ..create_auth_redis_account(email.to_string(), action.clone(), None)
+            }))
         }
     }
 }
 
+async fn upgrade_password_hash(
+    state: Arc<AppState>,
+    email: &str,
+    password: &str,
+    website_path: &WebsitePath,
+) -> Result<(), AppError> {
+    let password_hash = spawn_blocking({
+        let password_owned = password.to_owned();
+        move || hash_password(&password_owned)
+    })
+    .await?;
+
+    update_password(state.clone(), email, &password_hash, website_path).await
+}
+
 pub async fn try_get_redis_account(
     state: Arc<AppState>,
     verified_result: &VerifiedTokenResult,
@@ -362,9 +606,14 @@ pub fn create_forgot_redis_account(email: String) -> RedisAccount {
     RedisAccount {
         email,
         action: Action::Forgot,
-        code: generate_code().clone(),
+        code_hash: String::new(),
         issued_timestamp: None,
         password_hash: None,
+        remember: false,
+        totp: false,
+        locale: None,
+        link_hash: None,
+        previous_email: None,
     }
 }
 
@@ -376,9 +625,14 @@ pub fn create_auth_redis_account(
     RedisAccount {
         email,
         action,
-        code: generate_code().clone(),
+        code_hash: String::new(),
         issued_timestamp: Some(Utc::now().timestamp_millis()),
         password_hash,
+        remember: false,
+        totp: false,
+        locale: None,
+        link_hash: None,
+        previous_email: None,
     }
 }
 
@@ -420,14 +674,18 @@ async fn insert_session(
     key: &str,
     session_id: &str,
     key_secondary: &str,
-    email: &str,
+    session_record: &SessionRecord,
+    ttl_seconds: i64,
 ) -> Result<(), AppError> {
     let _: () = INSERT_SESSION_SCRIPT
         .key(format!("{}:{}:{}", website_path, key, session_id))
-        .key(format!("{}:{}:{}", website_path, key_secondary, email))
+        .key(format!(
+            "{}:{}:{}",
+            website_path, key_secondary, session_record.email
+        ))
         .arg(session_id)
-        .arg(email)
-        .arg(state.config.session.session_duration_seconds)
+        .arg(serde_json::to_string(session_record)?)
+        .arg(ttl_seconds)
         .arg(state.config.session.max_sessions)
         .arg(format!("{}:{}:", website_path, key))
         .invoke_async(&mut state.redis_connection_manager.clone())
@@ -435,3 +693,765 @@ async fn insert_session(
 
     Ok(())
 }
+
+pub async fn check_session(
+    state: Arc<AppState>,
+    headers: &HeaderMap,
+    website_path: &WebsitePath,
+) -> Result<ActiveSession, AppError> {
+    let verified_result = check_token(
+        state.clone(),
+        headers.clone(),
+        &[RedisAction::Session],
+        website_path,
+    )
+    .await?;
+
+    let mut session_record: SessionRecord = serde_json::from_str(
+        &verified_result
+            .serialized_account
+            .expect("session creation faulty"),
+    )?;
+
+    let now = Utc::now().timestamp();
+
+    let (duration_seconds, max_lifetime_seconds) =
+        get_session_lifetime(state.clone(), session_record.remember);
+
+    let ttl_seconds = match get_session_renewal(
+        &session_record,
+        now,
+        duration_seconds,
+        max_lifetime_seconds,
+        state.config.session.session_renewal_percent,
+    ) {
+        SessionRenewal::Expired => {
+            remove_session(
+                state.clone(),
+                website_path.as_ref(),
+                &verified_result.id,
+                &session_record.email,
+            )
+            .await?;
+
+            return Err(AppError::Unauthorized("Session expired".to_string()));
+        }
+        SessionRenewal::Current => {
+            if is_last_seen_stale(&session_record, now) {
+                session_record.last_seen = now;
+
+                touch_session(
+                    state.clone(),
+                    website_path.as_ref(),
+                    &verified_result.id,
+                    &session_record,
+                )
+                .await?;
+            }
+
+            return Ok(ActiveSession {
+                id: verified_result.id,
+                record: session_record,
+                headers: HeaderMap::new(),
+            });
+        }
+        SessionRenewal::Renew(ttl_seconds) => ttl_seconds,
+    };
+
+    session_record.last_seen = now;
+    session_record.renewed_at = now;
+
+    renew_session(
+        state.clone(),
+        website_path.as_ref(),
+        &verified_result.id,
+        &session_record,
+        ttl_seconds,
+    )
+    .await?;
+
+    Ok(ActiveSession {
+        headers: generate_cookie(
+            &state.config,
+            RedisAction::Session.as_ref(),
+            &verified_result.id,
+            ttl_seconds,
+            website_path,
+        ),
+        id: verified_result.id,
+        record: session_record,
+    })
+}
+
+fn get_session_renewal(
+    session_record: &SessionRecord,
+    now: i64,
+    duration_seconds: i64,
+    max_lifetime_seconds: i64,
+    renewal_percent: u8,
+) -> SessionRenewal {
+    let remaining_lifetime_seconds = session_record.created_at + max_lifetime_seconds - now;
+
+    if remaining_lifetime_seconds <= 0 {
+        return SessionRenewal::Expired;
+    }
+
+    if now - session_record.renewed_at < duration_seconds * i64::from(renewal_percent) / 100 {
+        return SessionRenewal::Current;
+    }
+
+    SessionRenewal::Renew(duration_seconds.min(remaining_lifetime_seconds))
+}
+
+fn is_last_seen_stale(session_record: &SessionRecord, now: i64) -> bool {
+    now - session_record.last_seen >= LAST_SEEN_INTERVAL_SECONDS
+}
+
+fn get_session_lifetime(state: Arc<AppState>, remember: bool) -> (i64, i64) {
+    if remember {
+        return (
+            state
+                .config
+                .session
+                .remember_session_duration_seconds
+                .into(),
+            state
+                .config
+                .session
+                .remember_session_max_lifetime_seconds
+                .into(),
+        );
+    }
+
+    (
+        state.config.session.session_duration_seconds.into(),
+        state.config.session.session_max_lifetime_seconds.into(),
+    )
+}
+
+async fn renew_session(
+    state: Arc<AppState>,
+    website_path: &str,
+    session_id: &str,
+    session_record: &SessionRecord,
+    ttl_seconds: i64,
+) -> Result<(), AppError> {
+    let _: () = RENEW_SESSION_SCRIPT
+        .key(format!(
+            "{}:{}:{}",
+            website_path,
+            RedisAction::Session.as_ref(),
+            session_id
+        ))
+        .key(format!(
+            "{}:{}:{}",
+            website_path,
+            RedisAction::SessionStore.as_ref(),
+            session_record.email
+        ))
+        .arg(serde_json::to_string(session_record)?)
+        .arg(ttl_seconds)
+        .invoke_async(&mut state.redis_connection_manager.clone())
+        .await?;
+
+    Ok(())
+}
+
+async fn touch_session(
+    state: Arc<AppState>,
+    website_path: &str,
+    session_id: &str,
+    session_record: &SessionRecord,
+) -> Result<(), AppError> {
+    let _: () = get_touch_session_cmd(
+        &format!(
+            "{}:{}:{}",
+            website_path,
+            RedisAction::Session.as_ref(),
+            session_id
+        ),
+        &serde_json::to_string(session_record)?,
+    )
+    .query_async(&mut state.redis_connection_manager.clone())
+    .await?;
+
+    Ok(())
+}
+
+fn get_touch_session_cmd(key: &str, serialized: &str) -> Cmd {
+    let mut cmd = redis::cmd("SET");
+    cmd.arg(key).arg(serialized).arg("XX").arg("KEEPTTL");
+
+    cmd
+}
+
+pub async fn list_sessions(
+    state: Arc<AppState>,
+    current_session_id: &str,
+    email: &str,
+    website_path: &WebsitePath,
+) -> Result<Vec<SessionPayload>, AppError> {
+    let mut sessions = Vec::new();
+
+    for session_id in get_session_ids(state.clone(), website_path.as_ref(), email).await? {
+        let Some(serialized) = state
+            .redis_connection_manager
+            .clone()
+            .get(format!(
+                "{}:{}:{}",
+                website_path.as_ref(),
+                RedisAction::Session.as_ref(),
+                session_id
+            ))
+            .await?
+        else {
+            continue;
+        };
+
+        sessions.push(get_session_payload(
+            &session_id,
+            serde_json::from_str(&serialized)?,
+            current_session_id,
+        ));
+    }
+
+    Ok(sessions)
+}
+
+fn get_session_payload(
+    session_id: &str,
+    session_record: SessionRecord,
+    current_session_id: &str,
+) -> SessionPayload {
+    SessionPayload {
+        id: get_session_handle(session_id),
+        created_at: session_record.created_at,
+        last_seen: session_record.last_seen,
+        hashed_ip: session_record.hashed_ip,
+        user_agent: session_record.user_agent,
+        device: session_record.device,
+        current: session_id == current_session_id,
+    }
+}
+
+pub async fn revoke_session(
+    state: Arc<AppState>,
+    email: &str,
+    session_handle: &str,
+    website_path: &WebsitePath,
+) -> Result<Option<String>, AppError> {
+    let Some(session_id) = get_session_ids(state.clone(), website_path.as_ref(), email)
+        .await?
+        .into_iter()
+        .find(|session_id| get_session_handle(session_id) == session_handle)
+    else {
+        return Ok(None);
+    };
+
+    remove_session(state.clone(), website_path.as_ref(), &session_id, email).await?;
+
+    Ok(Some(session_id))
+}
+
+pub async fn end_session(
+    state: Arc<AppState>,
+    headers: &HeaderMap,
+    website_path: &WebsitePath,
+) -> Result<Option<String>, AppError> {
+    let Some(session_id) = get_cookie(headers, RedisAction::Session.as_ref()) else {
+        return Ok(None);
+    };
+
+    if let Some(verified_result) = format_verified_result(
+        state.clone(),
+        website_path,
+        RedisAction::Session,
+        session_id,
+    )
+    .await?
+    {
+        let session_record: SessionRecord = serde_json::from_str(
+            &verified_result
+                .serialized_account
+                .expect("session creation faulty"),
+        )?;
+
+        remove_session(
+            state.clone(),
+            website_path.as_ref(),
+            &verified_result.id,
+            &session_record.email,
+        )
+        .await?;
+
+        return Ok(Some(session_record.email));
+    }
+
+    Ok(None)
+}
+
+pub async fn remove_session(
+    state: Arc<AppState>,
+    website_path: &str,
+    session_id: &str,
+    email: &str,
+) -> Result<(), AppError> {
+    get_remove_session_pipe(website_path, session_id, email)
+        .query_async::<()>(&mut state.redis_connection_manager.clone())
+        .await?;
+
+    Ok(())
+}
+
+fn get_remove_session_pipe(website_path: &str, session_id: &str, email: &str) -> Pipeline {
+    let mut pipe = redis::pipe();
+    pipe.del(format!(
+        "{}:{}:{}",
+        website_path,
+        RedisAction::Session.as_ref(),
+        session_id
+    ))
+    .ignore()
+    .lrem(
+        format!(
+            "{}:{}:{}",
+            website_path,
+            RedisAction::SessionStore.as_ref(),
+            email
+        ),
+        0,
+        session_id,
+    )
+    .ignore();
+
+    pipe
+}
+
+pub async fn delete_other_sessions(
+    state: Arc<AppState>,
+    website_path: &str,
+    email: &str,
+    current_session_id: &str,
+) -> Result<(), AppError> {
+    for session_id in get_other_session_ids(
+        get_session_ids(state.clone(), website_path, email).await?,
+        current_session_id,
+    ) {
+        remove_session(state.clone(), website_path, &session_id, email).await?;
+    }
+
+    Ok(())
+}
+
+fn get_other_session_ids(session_ids: Vec<String>, current_session_id: &str) -> Vec<String> {
+    session_ids
+        .into_iter()
+        .filter(|session_id| session_id != current_session_id)
+        .collect()
+}
+
+async fn get_session_ids(
+    state: Arc<AppState>,
+    website_path: &str,
+    email: &str,
+) -> Result<Vec<String>, AppError> {
+    Ok(state
+        .redis_connection_manager
+        .clone()
+        .lrange(
+            format!(
+                "{}:{}:{}",
+                website_path,
+                RedisAction::SessionStore.as_ref(),
+                email
+            ),
+            0,
+            -1,
+        )
+        .await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redis::aio::MultiplexedConnection;
+
+    const DURATION: i64 = 3600;
+    const MAX_LIFETIME: i64 = 86400;
+
+    fn session_record(created_at: i64, renewed_at: i64) -> SessionRecord {
+        SessionRecord {
+            email: "pete@purdue.edu".to_string(),
+            created_at,
+            last_seen: renewed_at,
+            renewed_at,
+            remember: false,
+            hashed_ip: String::new(),
+            user_agent: String::new(),
+            device: String::new(),
+            network: String::new(),
+        }
+    }
+
+    #[test]
+    fn recent_session_is_not_rewritten() {
+        let record = session_record(0, 1000);
+
+        assert!(matches!(
+            get_session_renewal(&record, 1000 + DURATION / 2 - 1, DURATION, MAX_LIFETIME, 50),
+            SessionRenewal::Current
+        ));
+    }
+
+    #[test]
+    fn session_renews_once_idle_threshold_passes() {
+        let record = session_record(0, 1000);
+
+        assert!(matches!(
+            get_session_renewal(&record, 1000 + DURATION / 2, DURATION, MAX_LIFETIME, 50),
+            SessionRenewal::Renew(DURATION)
+        ));
+    }
+
+    #[test]
+    fn renewal_never_extends_past_max_lifetime() {
+        let record = session_record(0, MAX_LIFETIME - DURATION);
+
+        assert!(matches!(
+            get_session_renewal(&record, MAX_LIFETIME - 60, DURATION, MAX_LIFETIME, 50),
+            SessionRenewal::Renew(60)
+        ));
+    }
+
+    #[test]
+    fn session_past_max_lifetime_is_expired() {
+        let record = session_record(0, MAX_LIFETIME - 10);
+
+        assert!(matches!(
+            get_session_renewal(&record, MAX_LIFETIME, DURATION, MAX_LIFETIME, 50),
+            SessionRenewal::Expired
+        ));
+        assert!(matches!(
+            get_session_renewal(&record, MAX_LIFETIME + 1, DURATION, MAX_LIFETIME, 50),
+            SessionRenewal::Expired
+        ));
+    }
+
+    #[test]
+    fn last_seen_is_refreshed_once_interval_passes() {
+        let record = session_record(0, 1000);
+
+        assert!(!is_last_seen_stale(
+            &record,
+            1000 + LAST_SEEN_INTERVAL_SECONDS - 1
+        ));
+        assert!(is_last_seen_stale(
+            &record,
+            1000 + LAST_SEEN_INTERVAL_SECONDS
+        ));
+    }
+
+    fn linked_account(config: &Config, link_token: &str) -> RedisAccount {
+        RedisAccount {
+            link_hash: Some(hash_code(config, "pete@purdue.edu", link_token)),
+            ..create_auth_redis_account("pete@purdue.edu".to_string(), Action::Login, None)
+        }
+    }
+
+    #[test]
+    fn sign_in_links_are_issued_only_for_email_code_logins() {
+        let login = create_auth_redis_account("pete@purdue.edu".to_string(), Action::Login, None);
+        let signup = create_auth_redis_account("pete@purdue.edu".to_string(), Action::Signup, None);
+        let pending_totp = RedisAccount {
+            totp: true,
+            ..login.clone()
+        };
+        let delete = create_auth_redis_account("pete@purdue.edu".to_string(), Action::Delete, None);
+
+        assert!(
+            get_issued_code(&login, &RedisAction::Auth)
+                .link_token
+                .is_some_and(|link_token| LINK_TOKEN_REGEX.is_match(&link_token))
+        );
+        assert!(
+            get_issued_code(&signup, &RedisAction::Auth)
+                .link_token
+                .is_some()
+        );
+        assert!(
+            get_issued_code(&pending_totp, &RedisAction::Auth)
+                .link_token
+                .is_none()
+        );
+        assert!(
+            get_issued_code(&delete, &RedisAction::Auth)
+                .link_token
+                .is_none()
+        );
+        assert!(
+            get_issued_code(&login, &RedisAction::Forgot)
+                .link_token
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn sign_in_links_verify_only_the_issued_token() {
+        let config = Config::for_tests();
+        let link_token = generate_link_token();
+        let redis_account = linked_account(&config, &link_token);
+
+        assert!(is_link_token_valid(&config, &redis_account, &link_token));
+        assert!(!is_link_token_valid(
+            &config,
+            &redis_account,
+            &generate_link_token()
+        ));
+        assert!(!is_link_token_valid(
+            &config,
+            &RedisAccount {
+                email: "other@purdue.edu".to_string(),
+                ..redis_account.clone()
+            },
+            &link_token
+        ));
+    }
+
+    #[test]
+    fn sign_in_links_are_rejected_without_an_issued_link() {
+        let config = Config::for_tests();
+        let link_token = generate_link_token();
+        let redis_account = RedisAccount {
+            code_hash: hash_code(&config, "pete@purdue.edu", &link_token),
+            ..create_auth_redis_account("pete@purdue.edu".to_string(), Action::Login, None)
+        };
+
+        assert!(!is_link_token_valid(&config, &redis_account, &link_token));
+    }
+
+    #[test]
+    fn password_change_keeps_only_the_current_session() {
+        assert_eq!(
+            get_other_session_ids(
+                vec![
+                    "first".to_string(),
+                    "current".to_string(),
+                    "second".to_string(),
+                ],
+                "current",
+            ),
+            vec!["first".to_string(), "second".to_string()]
+        );
+    }
+
+    #[test]
+    fn session_payload_hides_the_session_id() {
+        let payload = get_session_payload("session", session_record(0, 1000), "session");
+
+        assert_eq!(payload.id, get_session_handle("session"));
+        assert_ne!(payload.id, "session");
+        assert_eq!(payload.last_seen, 1000);
+        assert!(payload.current);
+        assert!(!get_session_payload("other", session_record(0, 1000), "session").current);
+    }
+
+    async fn redis_connection() -> MultiplexedConnection {
+        redis::Client::open(
+            std::env::var("REDIS_TEST_URL").unwrap_or("redis://127.0.0.1:6379".to_string()),
+        )
+        .unwrap()
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap()
+    }
+
+    async fn visit(
+        connection: &mut MultiplexedConnection,
+        key: &str,
+        fingerprint: &str,
+        seen_at: i64,
+        previous_fingerprint: Option<&str>,
+    ) -> bool {
+        let mut invocation = KNOWN_DEVICE_SCRIPT.key(key);
+        invocation.arg(fingerprint).arg(60).arg(2).arg(seen_at);
+
+        if let Some(previous_fingerprint) = previous_fingerprint {
+            invocation.arg(previous_fingerprint);
+        }
+
+        invocation.invoke_async::<u8>(connection).await.unwrap() == 1
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Redis at REDIS_TEST_URL"]
+    async fn known_devices_evict_the_least_recently_seen() {
+        let mut connection = redis_connection().await;
+        let key = format!("test:known_devices:{}", Uuid::new_v4());
+
+        assert!(!visit(&mut connection, &key, "laptop", 1, None).await);
+        assert!(visit(&mut connection, &key, "phone", 2, None).await);
+        assert!(!visit(&mut connection, &key, "laptop", 3, None).await);
+        assert!(visit(&mut connection, &key, "tablet", 4, None).await);
+
+        assert!(!visit(&mut connection, &key, "laptop", 5, None).await);
+        assert!(visit(&mut connection, &key, "phone", 6, None).await);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Redis at REDIS_TEST_URL"]
+    async fn known_devices_survive_ip_hash_rotation() {
+        let mut connection = redis_connection().await;
+        let key = format!("test:known_devices:{}", Uuid::new_v4());
+
+        assert!(!visit(&mut connection, &key, "old:laptop", 1, None).await);
+        assert!(!visit(&mut connection, &key, "new:laptop", 2, Some("old:laptop")).await);
+
+        let fingerprints = connection.zrange(&key, 0, -1).await.unwrap();
+        assert_eq!(fingerprints, vec!["new:laptop".to_string()]);
+    }
+
+    async fn insert(
+        connection: &mut MultiplexedConnection,
+        site: &str,
+        session_id: &str,
+        max_sessions: u8,
+    ) {
+        let _: () = INSERT_SESSION_SCRIPT
+            .key(format!("{}:session:{}", site, session_id))
+            .key(format!("{}:session_store:pete@purdue.edu", site))
+            .arg(session_id)
+            .arg(serde_json::to_string(&session_record(0, 0)).unwrap())
+            .arg(60)
+            .arg(max_sessions)
+            .arg(format!("{}:session:", site))
+            .invoke_async(connection)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Redis at REDIS_TEST_URL"]
+    async fn sessions_past_the_cap_evict_the_oldest() {
+        let mut connection = redis_connection().await;
+        let site = format!("test:{}", Uuid::new_v4());
+
+        for session_id in ["first", "second", "third"] {
+            insert(&mut connection, &site, session_id, 2).await;
+        }
+
+        let session_ids = connection
+            .lrange(format!("{}:session_store:pete@purdue.edu", site), 0, -1)
+            .await
+            .unwrap();
+        let evicted = connection
+            .get(format!("{}:session:first", site))
+            .await
+            .unwrap();
+
+        assert_eq!(session_ids, vec!["third".to_string(), "second".to_string()]);
+        assert_eq!(evicted, None);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Redis at REDIS_TEST_URL"]
+    async fn revoked_session_leaves_the_others() {
+        let mut connection = redis_connection().await;
+        let site = format!("test:{}", Uuid::new_v4());
+
+        insert(&mut connection, &site, "first", 2).await;
+        insert(&mut connection, &site, "second", 2).await;
+
+        get_remove_session_pipe(&site, "first", "pete@purdue.edu")
+            .query_async::<()>(&mut connection)
+            .await
+            .unwrap();
+
+        let session_ids = connection
+            .lrange(format!("{}:session_store:pete@purdue.edu", site), 0, -1)
+            .await
+            .unwrap();
+        let revoked = connection
+            .get(format!("{}:session:first", site))
+            .await
+            .unwrap();
+
+        assert_eq!(session_ids, vec!["second".to_string()]);
+        assert_eq!(revoked, None);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Redis at REDIS_TEST_URL"]
+    async fn revoking_a_session_leaves_other_sites_signed_in() {
+        let mut connection = redis_connection().await;
+        let prefix = format!("test:{}", Uuid::new_v4());
+        let swap = format!("{}:swap", prefix);
+        let home = format!("{}:home", prefix);
+
+        insert(&mut connection, &swap, "shared", 2).await;
+        insert(&mut connection, &home, "shared", 2).await;
+
+        get_remove_session_pipe(&home, "shared", "pete@purdue.edu")
+            .query_async::<()>(&mut connection)
+            .await
+            .unwrap();
+
+        let swap_session_ids = connection
+            .lrange(format!("{}:session_store:pete@purdue.edu", swap), 0, -1)
+            .await
+            .unwrap();
+        let swap_session = connection
+            .get(format!("{}:session:shared", swap))
+            .await
+            .unwrap();
+        let home_session = connection
+            .get(format!("{}:session:shared", home))
+            .await
+            .unwrap();
+
+        assert_eq!(swap_session_ids, vec!["shared".to_string()]);
+        assert!(swap_session.is_some());
+        assert_eq!(home_session, None);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Redis at REDIS_TEST_URL"]
+    async fn touching_a_session_keeps_its_ttl() {
+        let mut connection = redis_connection().await;
+        let site = format!("test:{}", Uuid::new_v4());
+        let key = format!("{}:session:first", site);
+
+        insert(&mut connection, &site, "first", 2).await;
+
+        let _: () = get_touch_session_cmd(
+            &key,
+            &serde_json::to_string(&session_record(0, 30)).unwrap(),
+        )
+        .query_async(&mut connection)
+        .await
+        .unwrap();
+
+        let serialized = connection.get(&key).await.unwrap().unwrap();
+        let record: SessionRecord = serde_json::from_str(&serialized).unwrap();
+        let ttl = connection.ttl(&key).await.unwrap().raw();
+
+        assert_eq!(record.last_seen, 30);
+        assert!(ttl > 0 && ttl <= 60);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Redis at REDIS_TEST_URL"]
+    async fn touching_a_revoked_session_does_not_recreate_it() {
+        let mut connection = redis_connection().await;
+        let key = format!("test:session:{}", Uuid::new_v4());
+
+        let _: () = get_touch_session_cmd(
+            &key,
+            &serde_json::to_string(&session_record(0, 30)).unwrap(),
+        )
+        .query_async(&mut connection)
+        .await
+        .unwrap();
+
+        assert_eq!(connection.get(&key).await.unwrap(), None);
+    }
+}
@@ -1,19 +1,35 @@
-use super::models::{Condition, Emoji, Item, ItemPayload, ItemRow, ItemType, Location};
+use super::models::{
+    Condition, CronItemRow, Emoji, Item, ItemPayload, ItemRow, ItemType, Location,
+};
 use crate::{
-    AppError, AppState,
-    api::{
-        microservices::cdc::convert_i8_to_u8,
-        web::models::{RedisAccount, WebsitePath},
+    AppError, AppState, RedisAction, WebsitePath,
+    api::microservices::{
+        cdc::convert_i8_to_u8,
+        database::init::{BoilerSwap, DatabaseQueries},
     },
 };
 use chrono::{Duration as chronoDuration, Utc};
-use scylla::response::{PagingState, query_result::FirstRowError::RowsEmpty};
-use std::sync::Arc;
+use redis::{AsyncTypedCommands, aio::ConnectionManager};
+use scylla::{
+    client::session::Session,
+    response::{PagingState, query_result::FirstRowError::RowsEmpty},
+    statement::batch::Batch,
+    value::CqlValue,
+};
+use std::{ops::ControlFlow, sync::Arc};
+use tracing::info;
 use uuid::Uuid;
 
-pub async fn insert_item(state: Arc<AppState>, item: ItemPayload) -> Result<Uuid, AppError> {
+const USER_ITEMS_MIGRATION: &str = "user_items";
+
+pub async fn insert_item(
+    state: Arc<AppState>,
+    item: ItemPayload,
+    email: &str,
+) -> Result<Uuid, AppError> {
     let fallback_page_state = PagingState::start();
     let id = Uuid::new_v4();
+    let ttl_seconds = 604800 * 3;
 
     state
         .database_session
@@ -28,8 +44,17 @@ pub async fn insert_item(state: Arc<AppState>, item: ItemPayload) -> Result<Uuid
                 item.description,
                 item.emoji as i8,
                 Utc::now().date_naive() + chronoDuration::days(7),
-                604800 * 3,
+                ttl_seconds,
             ),
+            fallback_page_state.clone(),
+        )
+        .await?;
+
+    state
+        .database_session
+        .execute_single_page(
+            &state.database_queries.boiler_swap.insert_user_item,
+            (email, &id, ttl_seconds),
             fallback_page_state,
         )
         .await?;
@@ -76,48 +101,42 @@ pub fn convert_db_items(row_vec: &Vec<ItemRow>) -> Vec<Item> {
         .collect()
 }
 
-pub async fn get_user(
-    state: Arc<AppState>,
-    email: &str,
-    website_path: &WebsitePath,
-) -> Result<Option<(String, bool)>, AppError> {
-    is_this_for_swap(website_path);
-
+pub async fn get_user_items(state: Arc<AppState>, email: &str) -> Result<Vec<Uuid>, AppError> {
     let fallback_page_state = PagingState::start();
 
     let (returned_rows, _) = state
         .database_session
         .execute_single_page(
-            &state.database_queries.boiler_swap.get_user,
+            &state.database_queries.boiler_swap.get_user_items,
             (email,),
             fallback_page_state,
         )
         .await?;
 
-    match returned_rows
+    Ok(returned_rows
         .into_rows_result()?
-        .first_row::<(String, bool)>()
-    {
-        Ok((password_hash, locked)) => Ok(Some((password_hash, locked))),
-        Err(RowsEmpty) => Ok(None),
-        Err(e) => Err(e.into()),
-    }
+        .rows::<(Uuid,)>()?
+        .map(|row| row.map(|(item_id,)| item_id))
+        .collect::<Result<Vec<_>, _>>()?)
 }
 
-pub async fn insert_user(
-    state: Arc<AppState>,
-    account: &RedisAccount,
-    website_path: &WebsitePath,
-) -> Result<(), AppError> {
-    is_this_for_swap(website_path);
-
+pub async fn remove_item(state: Arc<AppState>, item_id: Uuid, email: &str) -> Result<(), AppError> {
     let fallback_page_state = PagingState::start();
 
     state
         .database_session
         .execute_single_page(
-            &state.database_queries.boiler_swap.insert_user,
-            (account.email.clone(), account.password_hash.clone(), false),
+            &state.database_queries.boiler_swap.delete_item,
+            (item_id,),
+            fallback_page_state.clone(),
+        )
+        .await?;
+
+    state
+        .database_session
+        .execute_single_page(
+            &state.database_queries.boiler_swap.delete_user_item,
+            (email, item_id),
             fallback_page_state,
         )
         .await?;
@@ -125,79 +144,204 @@ pub async fn insert_user(
     Ok(())
 }
 
-fn is_this_for_swap(website_path: &WebsitePath) {
-    match website_path {
-        WebsitePath::BoilerSwap => {}
-        WebsitePath::Photos => panic!("Photos not implemented"),
-        WebsitePath::Home => panic!("Home not implemented"),
-    }
-}
-
-pub async fn check_lock(
-    state: Arc<AppState>,
-    email: &str,
-    website_path: &WebsitePath,
-) -> Result<Option<bool>, AppError> {
-    is_this_for_swap(website_path);
-
+pub async fn delete_user_items(state: Arc<AppState>, email: &str) -> Result<(), AppError> {
     let fallback_page_state = PagingState::start();
 
-    let (returned_rows, _) = state
+    for item_id in get_user_items(state.clone(), email).await? {
+        state
+            .database_session
+            .execute_single_page(
+                &state.database_queries.boiler_swap.delete_item,
+                (item_id,),
+                fallback_page_state.clone(),
+            )
+            .await?;
+    }
+
+    state
         .database_session
         .execute_single_page(
-            &state.database_queries.boiler_swap.check_lock,
+            &state.database_queries.boiler_swap.delete_user_items,
             (email,),
             fallback_page_state,
         )
         .await?;
 
-    match returned_rows.into_rows_result()?.first_row::<(bool,)>() {
-        Ok((locked,)) => Ok(Some(locked)),
-        Err(RowsEmpty) => Ok(None),
-        Err(e) => Err(e.into()),
-    }
+    Ok(())
 }
 
-pub async fn update_lock(
+pub async fn append_user_items_migration(
     state: Arc<AppState>,
+    batch: &mut Batch,
+    batch_values: &mut Vec<Vec<Option<CqlValue>>>,
     email: &str,
-    lock: bool,
-    website_path: &WebsitePath,
+    new_email: &str,
+) -> Result<Vec<Uuid>, AppError> {
+    let queries = &state.database_queries.boiler_swap;
+
+    let item_ids = get_user_items(state.clone(), email).await?;
+
+    for item_id in &item_ids {
+        if let Some(ttl_seconds) = get_item_ttl(&state.database_session, queries, item_id).await? {
+            batch.append_statement(queries.insert_user_item.clone());
+            batch_values.push(vec![
+                Some(CqlValue::Text(new_email.to_string())),
+                Some(CqlValue::Uuid(*item_id)),
+                Some(CqlValue::Int(ttl_seconds)),
+            ]);
+        }
+    }
+
+    batch.append_statement(queries.delete_user_items.clone());
+    batch_values.push(vec![Some(CqlValue::Text(email.to_string()))]);
+
+    Ok(item_ids)
+}
+
+// Items posted before user_items existed are only linked to their owner through the
+// item_deleted key the CDC consumer reads, which outlives the item's expiration date
+pub async fn backfill_user_items(
+    database_session: Arc<Session>,
+    database_queries: &DatabaseQueries,
+    redis_connection_manager: ConnectionManager,
 ) -> Result<(), AppError> {
-    is_this_for_swap(website_path);
+    let mut redis_connection_manager = redis_connection_manager.clone();
+    let queries = &database_queries.boiler_swap;
 
-    let fallback_page_state = PagingState::start();
+    let migration_key = format!(
+        "{}:{}:{}",
+        WebsitePath::BoilerSwap.as_ref(),
+        RedisAction::Migration.as_ref(),
+        USER_ITEMS_MIGRATION
+    );
 
-    state
-        .database_session
-        .execute_single_page(
-            &state.database_queries.boiler_swap.update_lock,
-            (lock, email),
-            fallback_page_state,
-        )
-        .await?;
+    if redis_connection_manager.exists(&migration_key).await? {
+        return Ok(());
+    }
+
+    let mut paging_state = PagingState::start();
+    let mut backfilled = 0;
+
+    loop {
+        let (query_result, paging_state_response) = database_session
+            .execute_single_page(&queries.get_cron_items, &[], paging_state)
+            .await?;
+
+        let item_ids: Vec<Uuid> = query_result
+            .into_rows_result()?
+            .rows::<CronItemRow>()?
+            .map(|row| row.map(|(item_id, _)| item_id))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if !item_ids.is_empty() {
+            let mut pipe = redis::pipe();
+
+            for item_id in &item_ids {
+                pipe.get(get_item_owner_key(item_id));
+            }
+
+            let owners: Vec<Option<String>> =
+                pipe.query_async(&mut redis_connection_manager).await?;
+
+            for (email, item_id) in get_owned_items(&item_ids, owners) {
+                if let Some(ttl_seconds) =
+                    get_item_ttl(&database_session, queries, &item_id).await?
+                {
+                    database_session
+                        .execute_single_page(
+                            &queries.insert_user_item,
+                            (email, item_id, ttl_seconds),
+                            PagingState::start(),
+                        )
+                        .await?;
+
+                    backfilled += 1;
+                }
+            }
+        }
+
+        match paging_state_response.into_paging_control_flow() {
+            ControlFlow::Break(()) => break,
+            ControlFlow::Continue(new_paging_state) => paging_state = new_paging_state,
+        }
+    }
+
+    redis_connection_manager.set(&migration_key, "1").await?;
+
+    info!("Backfilled {} user items", backfilled);
 
     Ok(())
 }
 
-pub async fn unlock_account(
-    state: Arc<AppState>,
-    email: &str,
-    password_hash: &str,
-    website_path: &WebsitePath,
-) -> Result<(), AppError> {
-    is_this_for_swap(website_path);
+fn get_item_owner_key(item_id: &Uuid) -> String {
+    format!(
+        "{}:{}:{}",
+        WebsitePath::BoilerSwap.as_ref(),
+        RedisAction::DeletedItem.as_ref(),
+        item_id
+    )
+}
 
-    let fallback_page_state = PagingState::start();
+fn get_owned_items(item_ids: &[Uuid], owners: Vec<Option<String>>) -> Vec<(String, Uuid)> {
+    item_ids
+        .iter()
+        .zip(owners)
+        .filter_map(|(item_id, email)| email.map(|email| (email, *item_id)))
+        .collect()
+}
 
-    state
-        .database_session
-        .execute_single_page(
-            &state.database_queries.boiler_swap.unlock_account,
-            (password_hash, email),
-            fallback_page_state,
-        )
+async fn get_item_ttl(
+    database_session: &Session,
+    queries: &BoilerSwap,
+    item_id: &Uuid,
+) -> Result<Option<i32>, AppError> {
+    let (returned_rows, _) = database_session
+        .execute_single_page(&queries.get_item_ttl, (item_id,), PagingState::start())
         .await?;
 
-    Ok(())
+    let ttl_seconds = match returned_rows
+        .into_rows_result()?
+        .first_row::<(Option<i32>,)>()
+    {
+        Ok((ttl_seconds,)) => ttl_seconds,
+        Err(RowsEmpty) => None,
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok(ttl_seconds.filter(|ttl_seconds| *ttl_seconds > 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn owner_key_matches_the_cdc_deletion_key() {
+        let item_id = Uuid::new_v4();
+
+        assert_eq!(
+            get_item_owner_key(&item_id),
+            format!("swap:item_deleted:{}", item_id)
+        );
+    }
+
+    #[test]
+    fn only_items_with_a_known_owner_are_backfilled() {
+        let item_ids = [Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()];
+
+        assert_eq!(
+            get_owned_items(
+                &item_ids,
+                vec![
+                    Some("pete@purdue.edu".to_string()),
+                    None,
+                    Some("other@purdue.edu".to_string()),
+                ],
+            ),
+            vec![
+                ("pete@purdue.edu".to_string(), item_ids[0]),
+                ("other@purdue.edu".to_string(), item_ids[2]),
+            ]
+        );
+    }
 }
@@ -1,5 +1,5 @@
 use super::{models::ItemPayload, redis::try_post_item, utilities::check_item};
-use crate::{AppError, AppState, RedisAction, WebsitePath, api::web::verify::check_token};
+use crate::{AppError, AppState, WebsitePath, api::web::sessions::check_session};
 use axum::{
     Json,
     extract::State,
@@ -13,23 +13,11 @@ pub async fn post_item_handler(
     State(state): State<Arc<AppState>>,
     Json(payload): Json<ItemPayload>,
 ) -> Result<impl IntoResponse, AppError> {
-    let email = check_token(
-        state.clone(),
-        headers.clone(),
-        &[RedisAction::Session],
-        &WebsitePath::BoilerSwap,
-    )
-    .await?
-    .serialized_account;
+    let session = check_session(state.clone(), &headers, &WebsitePath::BoilerSwap).await?;
 
     check_item(&payload)?;
 
-    try_post_item(
-        state.clone(),
-        &email.expect("session creation faulty"),
-        payload,
-    )
-    .await?;
+    try_post_item(state.clone(), &session.record.email, payload).await?;
 
-    Ok((StatusCode::OK).into_response())
+    Ok((StatusCode::OK, session.headers).into_response())
 }
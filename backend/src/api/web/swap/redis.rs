@@ -9,6 +9,7 @@ use crate::{
 use once_cell::sync::Lazy;
 use redis::{Script, aio::ConnectionManager};
 use std::sync::Arc;
+use uuid::Uuid;
 
 static DECR_ITEMS_SCRIPT: Lazy<Script> = Lazy::new(|| {
     Script::new(
@@ -21,6 +22,21 @@ static DECR_ITEMS_SCRIPT: Lazy<Script> = Lazy::new(|| {
     )
 });
 
+static REASSIGN_ITEMS_SCRIPT: Lazy<Script> = Lazy::new(|| {
+    Script::new(
+        r#"
+        if redis.call("EXISTS", KEYS[1]) == 1 then
+            redis.call("RENAME", KEYS[1], KEYS[2])
+        end
+        for i = 3, #KEYS do
+            if redis.call("GET", KEYS[i]) == ARGV[1] then
+                redis.call("SET", KEYS[i], ARGV[2], "XX", "KEEPTTL")
+            end
+        end
+    "#,
+    )
+});
+
 async fn handle_item_insertion(
     state: Arc<AppState>,
     item: ItemPayload,
@@ -33,7 +49,7 @@ async fn handle_item_insertion(
             "{}:{}:{}",
             website_path,
             RedisAction::DeletedItem.as_ref(),
-            &insert_item(state.clone(), item).await?.to_string()
+            &insert_item(state.clone(), item, email).await?.to_string()
         ),
         email,
         1_209_600,
@@ -67,6 +83,47 @@ pub async fn decrement_items(
     Ok(())
 }
 
+pub async fn reassign_items(
+    state: Arc<AppState>,
+    website_path: &str,
+    email: &str,
+    new_email: &str,
+    item_ids: &[Uuid],
+) -> Result<(), AppError> {
+    let mut invocation = REASSIGN_ITEMS_SCRIPT.prepare_invoke();
+
+    invocation
+        .key(format!(
+            "{}:{}:{}",
+            website_path,
+            RedisAction::LockedItems.as_ref(),
+            email
+        ))
+        .key(format!(
+            "{}:{}:{}",
+            website_path,
+            RedisAction::LockedItems.as_ref(),
+            new_email
+        ));
+
+    for item_id in item_ids {
+        invocation.key(format!(
+            "{}:{}:{}",
+            website_path,
+            RedisAction::DeletedItem.as_ref(),
+            item_id
+        ));
+    }
+
+    let _: () = invocation
+        .arg(email)
+        .arg(new_email)
+        .invoke_async(&mut state.redis_connection_manager.clone())
+        .await?;
+
+    Ok(())
+}
+
 pub async fn try_post_item(
     state: Arc<AppState>,
     email: &str,
@@ -0,0 +1,269 @@
+use super::models::Action;
+use crate::AppError;
+use axum::http::header::{ACCEPT_LANGUAGE, HeaderMap};
+use std::{
+    collections::HashMap,
+    fs::{read_dir, read_to_string},
+    path::Path,
+};
+
+const TEMPLATE_ACTIONS: [Action; 6] = [
+    Action::Login,
+    Action::Signup,
+    Action::Forgot,
+    Action::Delete,
+    Action::ChangeEmail,
+    Action::NewSignIn,
+];
+
+pub struct EmailTemplate {
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+pub struct RenderedEmail {
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+pub struct EmailTemplates {
+    pub default_locale: String,
+    pub locales: HashMap<String, HashMap<String, EmailTemplate>>,
+}
+
+impl EmailTemplates {
+    pub fn load(templates_path: &str, default_locale: &str) -> Result<Self, AppError> {
+        let mut locales = HashMap::new();
+
+        for entry in read_dir(templates_path)? {
+            let entry = entry?;
+
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+
+            let mut templates = HashMap::new();
+
+            for action in TEMPLATE_ACTIONS {
+                templates.insert(
+                    action.as_ref().to_string(),
+                    load_template(&entry.path(), action.as_ref())?,
+                );
+            }
+
+            locales.insert(
+                entry.file_name().to_string_lossy().to_lowercase(),
+                templates,
+            );
+        }
+
+        if !locales.contains_key(default_locale) {
+            return Err(AppError::Config(format!(
+                "Missing email templates for default locale {}",
+                default_locale
+            )));
+        }
+
+        Ok(Self {
+            default_locale: default_locale.to_string(),
+            locales,
+        })
+    }
+
+    pub fn negotiate_locale(&self, headers: &HeaderMap) -> String {
+        let accept_language = headers
+            .get(ACCEPT_LANGUAGE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+
+        let mut preferences: Vec<(String, f32)> = accept_language
+            .split(',')
+            .filter_map(|entry| {
+                let mut parts = entry.trim().split(';');
+                let tag = parts.next()?.trim().to_lowercase();
+
+                let quality = parts
+                    .find_map(|part| part.trim().strip_prefix("q="))
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .unwrap_or(1.0);
+
+                (!tag.is_empty() && quality > 0.0).then_some((tag, quality))
+            })
+            .collect();
+
+        preferences.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        preferences
+            .iter()
+            .find_map(|(tag, _)| {
+                if self.locales.contains_key(tag) {
+                    return Some(tag.clone());
+                }
+
+                tag.split('-')
+                    .next()
+                    .filter(|primary| self.locales.contains_key(*primary))
+                    .map(str::to_string)
+            })
+            .unwrap_or_else(|| self.default_locale.clone())
+    }
+
+    pub fn render(
+        &self,
+        locale: Option<&str>,
+        action: &Action,
+        variables: &[(&str, &str)],
+    ) -> RenderedEmail {
+        let template = self
+            .locales
+            .get(locale.unwrap_or(&self.default_locale))
+            .unwrap_or(&self.locales[&self.default_locale])
+            .get(action.as_ref())
+            .expect("templates loaded for every action");
+
+        RenderedEmail {
+            subject: fill(&template.subject, variables, false),
+            text: fill(&template.text, variables, false),
+            html: fill(&template.html, variables, true),
+        }
+    }
+}
+
+fn load_template(locale_path: &Path, action: &str) -> Result<EmailTemplate, AppError> {
+    let read = |extension: &str| {
+        let path = locale_path.join(format!("{}.{}", action, extension));
+
+        read_to_string(&path).map_err(|e| {
+            AppError::Config(format!(
+                "Failed to load email template {}: {}",
+                path.display(),
+                e
+            ))
+        })
+    };
+
+    Ok(EmailTemplate {
+        subject: read("subject.txt")?.trim().to_string(),
+        text: read("txt")?,
+        html: read("html")?,
+    })
+}
+
+fn fill(template: &str, variables: &[(&str, &str)], escape: bool) -> String {
+    variables
+        .iter()
+        .fold(template.to_string(), |rendered, (name, value)| {
+            let value = match escape {
+                true => escape_html(value),
+                false => value.to_string(),
+            };
+
+            rendered.replace(&format!("{{{{{}}}}}", name), &value)
+        })
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn load() -> EmailTemplates {
+        EmailTemplates::load("templates/email", "en").unwrap()
+    }
+
+    fn negotiate(email_templates: &EmailTemplates, accept_language: &str) -> String {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            ACCEPT_LANGUAGE,
+            HeaderValue::from_str(accept_language).unwrap(),
+        );
+
+        email_templates.negotiate_locale(&headers)
+    }
+
+    #[test]
+    fn every_locale_has_every_action() {
+        let email_templates = load();
+
+        assert!(email_templates.locales.contains_key("es"));
+
+        for templates in email_templates.locales.values() {
+            for action in TEMPLATE_ACTIONS {
+                assert!(templates.contains_key(action.as_ref()));
+            }
+        }
+    }
+
+    #[test]
+    fn missing_default_locale_is_rejected() {
+        assert!(matches!(
+            EmailTemplates::load("templates/email", "fr"),
+            Err(AppError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn negotiates_by_quality_and_primary_tag() {
+        let email_templates = load();
+
+        assert_eq!(negotiate(&email_templates, "es-MX,en;q=0.5"), "es");
+        assert_eq!(negotiate(&email_templates, "en;q=0.1, es;q=0.9"), "es");
+        assert_eq!(negotiate(&email_templates, "fr, es;q=0.2"), "es");
+        assert_eq!(negotiate(&email_templates, "es;q=0"), "en");
+        assert_eq!(negotiate(&email_templates, "fr"), "en");
+        assert_eq!(email_templates.negotiate_locale(&HeaderMap::new()), "en");
+    }
+
+    #[test]
+    fn renders_variables_and_escapes_html_only() {
+        let rendered = load().render(
+            Some("en"),
+            &Action::Login,
+            &[
+                ("code", "123456"),
+                ("expiry_minutes", "10"),
+                ("link", "https://example.com/?a=1&b=<2>"),
+            ],
+        );
+
+        assert!(rendered.subject.contains("login code"));
+        assert!(rendered.text.contains("123456"));
+        assert!(rendered.text.contains("https://example.com/?a=1&b=<2>"));
+        assert!(
+            rendered
+                .html
+                .contains("https://example.com/?a=1&amp;b=&lt;2&gt;")
+        );
+        assert!(!rendered.text.contains("{{"));
+        assert!(!rendered.html.contains("{{"));
+    }
+
+    #[test]
+    fn unknown_locale_falls_back_to_default() {
+        let email_templates = load();
+
+        assert_eq!(
+            email_templates
+                .render(Some("fr"), &Action::Signup, &[])
+                .subject,
+            email_templates.render(None, &Action::Signup, &[]).subject
+        );
+        assert_ne!(
+            email_templates
+                .render(Some("es"), &Action::Signup, &[])
+                .subject,
+            email_templates.render(None, &Action::Signup, &[]).subject
+        );
+    }
+}
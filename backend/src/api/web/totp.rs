@@ -0,0 +1,429 @@
+use super::{
+    database::{get_totp, remove_recovery_code, update_totp},
+    locks::{check_redis_lock, increment_lock_key},
+    models::{RecoveryCodes, RedisAction, TotpEnrollment, WebsitePath},
+    utilities::{get_key, get_keyed_hash},
+    verify::CODE_LENGTH,
+};
+use crate::{
+    AppError, AppState,
+    api::{
+        bot::photo::generate_qr_bytes,
+        microservices::redis::{insert_id, remove_id, try_get},
+    },
+    config::Config,
+};
+use chrono::Utc;
+use data_encoding::{BASE32_NOPAD, BASE64};
+use hmac::{Hmac, Mac};
+use once_cell::sync::Lazy;
+use rand::{RngCore, rngs::OsRng};
+use redis::Script;
+use regex::Regex;
+use reqwest::Url;
+use sha1::Sha1;
+use std::{collections::HashSet, sync::Arc};
+use subtle::ConstantTimeEq;
+
+type HmacSha1 = Hmac<Sha1>;
+
+const TOTP_ISSUER: &str = "BoilerSwap";
+
+const TOTP_PERIOD_SECONDS: u64 = 30;
+
+const TOTP_SKEW_STEPS: u64 = 1;
+
+const TOTP_SECRET_BYTES: usize = 20;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+
+static MARK_TOTP_STEP_SCRIPT: Lazy<Script> = Lazy::new(|| {
+    Script::new(
+        r#"
+        local last_step = redis.call("GET", KEYS[1])
+        if last_step and tonumber(last_step) >= tonumber(ARGV[1]) then
+            return 0
+        end
+        redis.call("SET", KEYS[1], ARGV[1], "EX", tonumber(ARGV[2]))
+        return 1
+    "#,
+    )
+});
+
+pub static RECOVERY_CODE_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^[a-z2-7]{5}-[a-z2-7]{5}$").unwrap());
+
+pub async fn start_totp_enrollment(
+    state: Arc<AppState>,
+    email: &str,
+    website_path: &WebsitePath,
+) -> Result<TotpEnrollment, AppError> {
+    if get_totp(state.clone(), email, website_path)
+        .await?
+        .is_some()
+    {
+        return Err(AppError::BadRequest(
+            "Authenticator already enrolled".to_string(),
+        ));
+    }
+
+    let secret = generate_totp_secret();
+
+    insert_id(
+        state.clone(),
+        &format!(
+            "{}:{}:{}",
+            website_path.as_ref(),
+            RedisAction::TotpPending.as_ref(),
+            email
+        ),
+        &secret,
+        state
+            .config
+            .session
+            .temporary_session_duration_seconds
+            .into(),
+    )
+    .await?;
+
+    let otpauth_url = get_otpauth_url(email, &secret);
+
+    Ok(TotpEnrollment {
+        qr_code: format!(
+            "data:image/png;base64,{}",
+            BASE64.encode(&generate_qr_bytes(&otpauth_url)?)
+        ),
+        secret,
+        otpauth_url,
+    })
+}
+
+pub async fn confirm_totp_enrollment(
+    state: Arc<AppState>,
+    email: &str,
+    code: &str,
+    hashed_ip: &str,
+    website_path: &WebsitePath,
+) -> Result<RecoveryCodes, AppError> {
+    check_totp_lock(state.clone(), email, hashed_ip, website_path).await?;
+
+    let pending_key = format!(
+        "{}:{}:{}",
+        website_path.as_ref(),
+        RedisAction::TotpPending.as_ref(),
+        email
+    );
+
+    let secret = try_get(state.clone(), &pending_key)
+        .await?
+        .ok_or(AppError::BadRequest(
+            "No enrollment in progress".to_string(),
+        ))?;
+
+    let verified = match verify_totp(&secret, code) {
+        Some(step) => mark_totp_step(state.clone(), email, step, website_path).await?,
+        None => false,
+    };
+
+    if !verified {
+        return Err(fail_totp(state.clone(), email, hashed_ip, website_path).await?);
+    }
+
+    let recovery_codes = generate_recovery_codes();
+
+    update_totp(
+        state.clone(),
+        email,
+        Some(&secret),
+        Some(hash_recovery_codes(&state.config, &recovery_codes)),
+        website_path,
+    )
+    .await?;
+
+    remove_id(state.clone(), &pending_key).await?;
+
+    Ok(RecoveryCodes { recovery_codes })
+}
+
+pub async fn remove_totp_enrollment(
+    state: Arc<AppState>,
+    email: &str,
+    code: &str,
+    hashed_ip: &str,
+    website_path: &WebsitePath,
+) -> Result<(), AppError> {
+    check_totp_lock(state.clone(), email, hashed_ip, website_path).await?;
+
+    if !verify_second_factor(state.clone(), email, code, website_path).await? {
+        return Err(fail_totp(state.clone(), email, hashed_ip, website_path).await?);
+    }
+
+    update_totp(state.clone(), email, None, None, website_path).await?;
+
+    Ok(())
+}
+
+pub async fn verify_second_factor(
+    state: Arc<AppState>,
+    email: &str,
+    code: &str,
+    website_path: &WebsitePath,
+) -> Result<bool, AppError> {
+    let Some((secret, recovery_codes)) = get_totp(state.clone(), email, website_path).await? else {
+        return Ok(false);
+    };
+
+    if RECOVERY_CODE_REGEX.is_match(code) {
+        let recovery_code_hash = hash_recovery_code(&state.config, code);
+
+        return remove_recovery_code(
+            state.clone(),
+            email,
+            &recovery_codes,
+            &recovery_code_hash,
+            website_path,
+        )
+        .await;
+    }
+
+    match verify_totp(&secret, code) {
+        Some(step) => mark_totp_step(state.clone(), email, step, website_path).await,
+        None => Ok(false),
+    }
+}
+
+pub fn verify_totp(secret: &str, code: &str) -> Option<u64> {
+    verify_totp_at(secret, code, Utc::now().timestamp().unsigned_abs())
+}
+
+fn verify_totp_at(secret: &str, code: &str, timestamp: u64) -> Option<u64> {
+    let secret = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+
+    let current_step = timestamp / TOTP_PERIOD_SECONDS;
+
+    (current_step.saturating_sub(TOTP_SKEW_STEPS)..=current_step + TOTP_SKEW_STEPS).find(|step| {
+        generate_totp(&secret, *step, *CODE_LENGTH)
+            .as_bytes()
+            .ct_eq(code.as_bytes())
+            .into()
+    })
+}
+
+fn generate_totp(secret: &[u8], step: u64, digits: usize) -> String {
+    let mut mac = HmacSha1::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&step.to_be_bytes());
+
+    let digest = mac.finalize().into_bytes();
+    let offset = usize::from(digest[digest.len() - 1] & 0x0f);
+
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+
+    format!(
+        "{:0width$}",
+        u64::from(binary) % 10_u64.pow(digits as u32),
+        width = digits
+    )
+}
+
+fn generate_totp_secret() -> String {
+    let mut secret = [0u8; TOTP_SECRET_BYTES];
+    OsRng.fill_bytes(&mut secret);
+
+    BASE32_NOPAD.encode(&secret)
+}
+
+fn get_otpauth_url(email: &str, secret: &str) -> String {
+    let mut url = Url::parse("otpauth://totp/").expect("otpauth base url is valid");
+
+    url.set_path(&format!("{}:{}", TOTP_ISSUER, email));
+    url.query_pairs_mut()
+        .append_pair("secret", secret)
+        .append_pair("issuer", TOTP_ISSUER)
+        .append_pair("algorithm", "SHA1")
+        .append_pair("digits", &CODE_LENGTH.to_string())
+        .append_pair("period", &TOTP_PERIOD_SECONDS.to_string());
+
+    url.to_string()
+}
+
+fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0u8; 7];
+            OsRng.fill_bytes(&mut bytes);
+
+            let encoded = BASE32_NOPAD.encode(&bytes).to_lowercase();
+
+            format!("{}-{}", &encoded[..5], &encoded[5..10])
+        })
+        .collect()
+}
+
+fn hash_recovery_codes(config: &Config, recovery_codes: &[String]) -> HashSet<String> {
+    recovery_codes
+        .iter()
+        .map(|recovery_code| hash_recovery_code(config, recovery_code))
+        .collect()
+}
+
+fn hash_recovery_code(config: &Config, recovery_code: &str) -> String {
+    get_keyed_hash(
+        &config.secrets.code_hash_secret,
+        "recovery_code",
+        recovery_code,
+    )
+}
+
+async fn mark_totp_step(
+    state: Arc<AppState>,
+    email: &str,
+    step: u64,
+    website_path: &WebsitePath,
+) -> Result<bool, AppError> {
+    let used_key = format!(
+        "{}:{}:{}",
+        website_path.as_ref(),
+        RedisAction::TotpUsed.as_ref(),
+        email
+    );
+
+    let marked: u8 = MARK_TOTP_STEP_SCRIPT
+        .key(used_key)
+        .arg(step)
+        .arg(TOTP_PERIOD_SECONDS * (2 * TOTP_SKEW_STEPS + 1))
+        .invoke_async(&mut state.redis_connection_manager.clone())
+        .await?;
+
+    Ok(marked == 1)
+}
+
+async fn check_totp_lock(
+    state: Arc<AppState>,
+    email: &str,
+    hashed_ip: &str,
+    website_path: &WebsitePath,
+) -> Result<(), AppError> {
+    check_redis_lock(
+        state.clone(),
+        website_path.as_ref(),
+        &get_key(RedisAction::LockedVerify, hashed_ip),
+        email,
+        &state.config.authentication.verify_max_attempts,
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn fail_totp(
+    state: Arc<AppState>,
+    email: &str,
+    hashed_ip: &str,
+    website_path: &WebsitePath,
+) -> Result<AppError, AppError> {
+    increment_lock_key(
+        state.clone(),
+        website_path.as_ref(),
+        &get_key(RedisAction::LockedVerify, hashed_ip),
+        email,
+        &state.config.authentication.verify_lock_duration_seconds,
+        &state.config.authentication.verify_max_attempts,
+    )
+    .await?;
+
+    Ok(AppError::Unauthorized("Unable to verify".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn generates_rfc_6238_sha1_vectors() {
+        for (timestamp, expected) in [
+            (59, "94287082"),
+            (1111111109, "07081804"),
+            (1111111111, "14050471"),
+            (1234567890, "89005924"),
+            (2000000000, "69279037"),
+            (20000000000, "65353130"),
+        ] {
+            assert_eq!(
+                generate_totp(RFC_SECRET, timestamp / TOTP_PERIOD_SECONDS, 8),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn accepts_codes_within_skew() {
+        let secret = BASE32_NOPAD.encode(RFC_SECRET);
+        let step = 1234567890 / TOTP_PERIOD_SECONDS;
+
+        for offset in [0, TOTP_PERIOD_SECONDS, 2 * TOTP_PERIOD_SECONDS] {
+            let code = generate_totp(RFC_SECRET, step, *CODE_LENGTH);
+
+            assert_eq!(
+                verify_totp_at(&secret, &code, 1234567890 + offset - TOTP_PERIOD_SECONDS),
+                Some(step)
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_codes_outside_skew_or_wrong() {
+        let secret = BASE32_NOPAD.encode(RFC_SECRET);
+        let step = 1234567890 / TOTP_PERIOD_SECONDS;
+        let code = generate_totp(RFC_SECRET, step, *CODE_LENGTH);
+
+        assert_eq!(
+            verify_totp_at(&secret, &code, 1234567890 + 2 * TOTP_PERIOD_SECONDS),
+            None
+        );
+        assert_eq!(verify_totp_at(&secret, "000000", 1234567890), None);
+        assert_eq!(verify_totp_at("not base32!", &code, 1234567890), None);
+    }
+
+    #[test]
+    fn recovery_codes_match_their_format() {
+        let recovery_codes = generate_recovery_codes();
+
+        assert_eq!(recovery_codes.len(), RECOVERY_CODE_COUNT);
+        assert!(
+            recovery_codes
+                .iter()
+                .all(|recovery_code| RECOVERY_CODE_REGEX.is_match(recovery_code))
+        );
+        assert_eq!(
+            hash_recovery_codes(&Config::for_tests(), &recovery_codes).len(),
+            RECOVERY_CODE_COUNT
+        );
+    }
+
+    #[test]
+    fn recovery_code_hashes_depend_on_the_secret() {
+        let config = Config::for_tests();
+        let mut rotated = Config::for_tests();
+        rotated.secrets.code_hash_secret = "rotated".to_string();
+
+        assert_eq!(
+            hash_recovery_code(&config, "abcde-fghij"),
+            hash_recovery_code(&config, "abcde-fghij")
+        );
+        assert_ne!(
+            hash_recovery_code(&config, "abcde-fghij"),
+            hash_recovery_code(&rotated, "abcde-fghij")
+        );
+        assert_ne!(
+            hash_recovery_code(&config, "abcde-fghij"),
+            hash_recovery_code(&config, "abcde-fghik")
+        );
+    }
+}
@@ -1,82 +1,113 @@
-use super::locks::{check_forgot_lock, increment_lock_key};
-use crate::{AppError, AppState, WebsitePath};
-use lettre::{
-    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
-    transport::smtp::authentication::Credentials,
-};
+use crate::config::Config;
+use data_encoding::{BASE64URL_NOPAD, HEXLOWER};
+use hmac::{Hmac, Mac};
 use once_cell::sync::Lazy;
-use rand::{Rng, thread_rng};
+use rand::{Rng, RngCore, thread_rng};
 use regex::Regex;
-use std::sync::Arc;
-use tracing::{debug, warn};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
 
 pub static CODE_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\d+$").unwrap());
 
+pub static LINK_TOKEN_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^[A-Za-z0-9_-]{43}$").unwrap());
+
 pub fn generate_code() -> String {
     let mut rng = thread_rng();
 
     format!("{:06}", rng.gen_range(0..1_000_000))
 }
 
-async fn send_code_email(
-    state: Arc<AppState>,
-    user_email: &str,
-    code: &str,
-) -> Result<(), AppError> {
-    let email = Message::builder()
-        .from(format!("BoilerSwap <{}>", state.config.email.from_email).parse()?)
-        .to(user_email.parse()?)
-        .subject("BoilerSwap Code")
-        .body(format!("Your code is {}", code))?;
-
-    let credentials = Credentials::new(
-        state.config.email.from_email.to_string(),
-        state.config.email.from_email_password.to_string(),
-    );
-
-    let mailer =
-        AsyncSmtpTransport::<Tokio1Executor>::relay(&state.config.email.from_email_server)?
-            .credentials(credentials)
-            .build();
-
-    mailer.send(email).await?;
-
-    Ok(())
+pub fn generate_link_token() -> String {
+    let mut bytes = [0u8; 32];
+    thread_rng().fill_bytes(&mut bytes);
+
+    BASE64URL_NOPAD.encode(&bytes)
 }
 
-pub fn spawn_code_task(
-    state: Arc<AppState>,
-    email: String,
-    token: String,
-    forgot_key: Option<String>,
-    website_path: WebsitePath,
-) {
-    tokio::spawn(async move {
-        if check_forgot_lock(state.clone(), &email, &forgot_key, &website_path).await {
-            return;
-        }
+pub fn hash_code(config: &Config, email: &str, code: &str) -> String {
+    HEXLOWER.encode(&get_code_mac(config, email, code).finalize().into_bytes())
+}
 
-        if let Err(error) = send_code_email(state.clone(), &email, &token).await {
-            match error {
-                AppError::LettreAddress(msg) => debug!("Invalid email: {}", msg),
-                AppError::LettreTransport(msg) => debug!("Transport error: {}", msg),
-                other => warn!("Unexpected error: {:?}", other),
-            }
+pub fn is_code_hash_valid(config: &Config, email: &str, code: &str, code_hash: &str) -> bool {
+    HEXLOWER
+        .decode(code_hash.as_bytes())
+        .is_ok_and(|code_hash| {
+            get_code_mac(config, email, code)
+                .verify_slice(&code_hash)
+                .is_ok()
+        })
+}
 
-            return;
-        }
+fn get_code_mac(config: &Config, email: &str, code: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(config.secrets.code_hash_secret.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(format!("{}:{}", email, code).as_bytes());
+
+    mac
+}
 
-        if forgot_key.is_some()
-            && (increment_lock_key(
-                state.clone(),
-                website_path.as_ref(),
-                &forgot_key.expect("is_some failed"),
-                &email,
-                &state.config.authentication.verify_lock_duration_seconds,
-                &state.config.authentication.verify_max_attempts,
-            )
-            .await)
-                .is_err()
-        {}
-    });
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_hash_verifies_only_the_issued_code() {
+        let config = Config::for_tests();
+        let code_hash = hash_code(&config, "pete@purdue.edu", "482913");
+
+        assert!(is_code_hash_valid(
+            &config,
+            "pete@purdue.edu",
+            "482913",
+            &code_hash
+        ));
+        assert!(!is_code_hash_valid(
+            &config,
+            "pete@purdue.edu",
+            "482914",
+            &code_hash
+        ));
+        assert!(!is_code_hash_valid(
+            &config,
+            "other@purdue.edu",
+            "482913",
+            &code_hash
+        ));
+        assert!(!is_code_hash_valid(
+            &config,
+            "pete@purdue.edu",
+            "482913",
+            "not-hex"
+        ));
+    }
+
+    #[test]
+    fn code_hash_is_keyed_by_secret() {
+        let config = Config::for_tests();
+        let mut rotated = Config::for_tests();
+        rotated.secrets.code_hash_secret = "another-secret".to_string();
+
+        let code_hash = hash_code(&config, "pete@purdue.edu", "482913");
+
+        assert_ne!(code_hash, hash_code(&rotated, "pete@purdue.edu", "482913"));
+        assert!(!is_code_hash_valid(
+            &rotated,
+            "pete@purdue.edu",
+            "482913",
+            &code_hash
+        ));
+    }
+
+    #[test]
+    fn generated_codes_and_link_tokens_match_their_formats() {
+        for _ in 0..100 {
+            let code = generate_code();
+
+            assert_eq!(code.len(), 6);
+            assert!(CODE_REGEX.is_match(&code));
+            assert!(LINK_TOKEN_REGEX.is_match(&generate_link_token()));
+        }
+    }
 }
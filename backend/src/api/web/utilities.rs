@@ -1,10 +1,32 @@
-use super::models::{RedisAction, VerifiedTokenResult, WebsitePath};
+use super::{
+    locks::carry_over_ip_locks,
+    models::{
+        MAX_USER_AGENT_CHARS, NetworkHash, RedisAccount, RedisAction, VerifiedTokenResult,
+        WebsitePath,
+    },
+};
 use crate::{
-    AppError, AppState, WebsiteRoute, api::microservices::redis::try_get, metrics::incr_visitors,
+    AppError, AppState, WebsiteRoute,
+    api::microservices::redis::try_get,
+    config::{Config, Proxies},
+    metrics::incr_visitors,
+};
+use axum::{
+    extract::Request,
+    http::header::{HeaderMap, USER_AGENT},
 };
-use axum::{extract::Request, http::header::HeaderMap};
+use data_encoding::HEXLOWER;
+use hmac::{Hmac, Mac};
+use ipnet::IpNet;
+use redis::Pipeline;
 use sha2::{Digest, Sha256};
-use std::{net::IpAddr, sync::Arc};
+use std::{net::IpAddr, str::FromStr, sync::Arc};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const NETWORK_PREFIX_V4: u8 = 24;
+
+const NETWORK_PREFIX_V6: u8 = 48;
 
 pub async fn clear_all_keys(
     state: Arc<AppState>,
@@ -12,6 +34,14 @@ pub async fn clear_all_keys(
     keys: &[&str],
     email: &str,
 ) -> Result<(), AppError> {
+    get_clear_keys_pipe(website_path, keys, email)
+        .query_async::<()>(&mut state.redis_connection_manager.clone())
+        .await?;
+
+    Ok(())
+}
+
+pub fn get_clear_keys_pipe(website_path: &str, keys: &[&str], email: &str) -> Pipeline {
     let mut pipe = redis::pipe();
 
     for key in keys {
@@ -19,10 +49,18 @@ pub async fn clear_all_keys(
             .ignore();
     }
 
-    pipe.query_async::<()>(&mut state.redis_connection_manager.clone())
-        .await?;
+    pipe
+}
 
-    Ok(())
+pub fn get_pending_account(
+    verified_result: &VerifiedTokenResult,
+) -> Result<RedisAccount, AppError> {
+    Ok(serde_json::from_str(
+        verified_result
+            .serialized_account
+            .as_ref()
+            .ok_or(AppError::Unauthorized("Unable to verify".to_string()))?,
+    )?)
 }
 
 pub async fn format_verified_result(
@@ -77,6 +115,8 @@ pub async fn check_path(
         {
             incr_visitors(state.clone(), WebsitePath::Home).await?;
 
+            label_request(request, WebsitePath::Home);
+
             Ok(Some(WebsitePath::Home))
         }
         _ => Ok(None),
@@ -119,7 +159,57 @@ fn label_request(request: &mut Request, website_path: WebsitePath) {
                     website_path.as_ref(),
                     WebsiteRoute::Api.as_ref(),
                     WebsiteRoute::Resend.as_ref()
-                ) =>
+                )
+            || path
+                == format!(
+                    "/{}/{}/{}",
+                    website_path.as_ref(),
+                    WebsiteRoute::Api.as_ref(),
+                    WebsiteRoute::Csrf.as_ref()
+                )
+            || path
+                == format!(
+                    "/{}/{}/{}",
+                    website_path.as_ref(),
+                    WebsiteRoute::Api.as_ref(),
+                    WebsiteRoute::Freeze.as_ref()
+                )
+            || path.starts_with(&format!(
+                "/{}/{}/{}",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Sessions.as_ref()
+            ))
+            || path.starts_with(&format!(
+                "/{}/{}/{}",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Totp.as_ref()
+            ))
+            || path.starts_with(&format!(
+                "/{}/{}/{}",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Passkeys.as_ref()
+            ))
+            || path.starts_with(&format!(
+                "/{}/{}/{}",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Account.as_ref()
+            ))
+            || path.starts_with(&format!(
+                "/{}/{}/{}",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Admin.as_ref()
+            ))
+            || path.starts_with(&format!(
+                "/{}/{}/{}",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Oidc.as_ref()
+            )) =>
         {
             request
                 .extensions_mut()
@@ -129,34 +219,223 @@ fn label_request(request: &mut Request, website_path: WebsitePath) {
     }
 }
 
-pub fn get_website_path(label: &str) -> WebsitePath {
-    match label {
-        _ if label == WebsitePath::BoilerSwap.as_ref() => WebsitePath::BoilerSwap,
-        _ => {
-            panic!("Added connection invalid")
-        }
+pub fn get_website_path(label: &str) -> Result<WebsitePath, AppError> {
+    match WebsitePath::from_str(label) {
+        Ok(website_path) if website_path != WebsitePath::Photos => Ok(website_path),
+        _ => Err(AppError::Config(format!("Unknown website label {}", label))),
     }
 }
 
-pub fn get_hashed_ip(headers: &HeaderMap, direct_ip: IpAddr) -> String {
-    let ip = headers
-        .get("cf-connecting-ip")
-        .and_then(|v| v.to_str().ok())
-        .map(|s| s.to_string())
-        .or_else(|| {
-            headers
-                .get("x-forwarded-for")
-                .and_then(|v| v.to_str().ok())
-                .and_then(|s| s.split(',').next().map(|s| s.trim().to_string()))
+pub async fn get_hashed_ip(
+    state: Arc<AppState>,
+    headers: &HeaderMap,
+    direct_ip: IpAddr,
+    website_path: &WebsitePath,
+) -> Result<String, AppError> {
+    let (hashed_ip, previous_hashed_ip) = get_ip_pseudonyms(headers, direct_ip, &state.config);
+
+    if let Some(previous_hashed_ip) = previous_hashed_ip {
+        carry_over_ip_locks(state.clone(), website_path, &previous_hashed_ip, &hashed_ip).await?;
+    }
+
+    Ok(hashed_ip)
+}
+
+pub fn get_ip_pseudonyms(
+    headers: &HeaderMap,
+    direct_ip: IpAddr,
+    config: &Config,
+) -> (String, Option<String>) {
+    let ip = get_client_ip(headers, direct_ip, &config.proxies).to_string();
+    let ip_hash = &config.secrets.ip_hash;
+
+    (
+        get_keyed_hash(&ip_hash.current, "ip", &ip),
+        ip_hash
+            .previous
+            .as_ref()
+            .map(|previous| get_keyed_hash(previous, "ip", &ip)),
+    )
+}
+
+pub fn get_client_ip(headers: &HeaderMap, direct_ip: IpAddr, proxies: &Proxies) -> IpAddr {
+    if !proxies.is_trusted(&direct_ip) {
+        return direct_ip;
+    }
+
+    proxies
+        .client_ip_headers
+        .iter()
+        .find_map(|header| {
+            let values = headers
+                .get_all(header.as_str())
+                .iter()
+                .filter_map(|v| v.to_str().ok())
+                .flat_map(|s| s.split(','))
+                .filter_map(|s| s.trim().parse::<IpAddr>().ok())
+                .collect::<Vec<_>>();
+            values.into_iter().rev().find(|ip| !proxies.is_trusted(ip))
         })
-        .unwrap_or_else(|| direct_ip.to_string());
+        .unwrap_or(direct_ip)
+}
 
-    let mut hasher = Sha256::new();
-    hasher.update(ip.as_bytes());
+pub fn get_network_hash(headers: &HeaderMap, direct_ip: IpAddr, config: &Config) -> NetworkHash {
+    let ip = get_client_ip(headers, direct_ip, &config.proxies);
 
-    format!("{:x}", hasher.finalize())
+    let network = match ip {
+        IpAddr::V4(_) => IpNet::new(ip, NETWORK_PREFIX_V4),
+        IpAddr::V6(_) => IpNet::new(ip, NETWORK_PREFIX_V6),
+    }
+    .map(|network| network.trunc().to_string())
+    .unwrap_or_else(|_| ip.to_string());
+
+    let ip_hash = &config.secrets.ip_hash;
+
+    NetworkHash {
+        current: get_keyed_hash(&ip_hash.current, "network", &network),
+        previous: ip_hash
+            .previous
+            .as_ref()
+            .map(|previous| get_keyed_hash(previous, "network", &network)),
+    }
+}
+
+pub fn get_device_label(user_agent: &str) -> String {
+    let browser = [
+        ("Edg/", "Edge"),
+        ("OPR/", "Opera"),
+        ("Firefox/", "Firefox"),
+        ("FxiOS/", "Firefox"),
+        ("CriOS/", "Chrome"),
+        ("Chrome/", "Chrome"),
+        ("Safari/", "Safari"),
+    ]
+    .iter()
+    .find(|(marker, _)| user_agent.contains(marker))
+    .map_or("Unknown browser", |(_, name)| name);
+
+    let os = [
+        ("Windows", "Windows"),
+        ("Android", "Android"),
+        ("iPhone", "iOS"),
+        ("iPad", "iPadOS"),
+        ("CrOS", "ChromeOS"),
+        ("Mac OS X", "macOS"),
+        ("Linux", "Linux"),
+    ]
+    .iter()
+    .find(|(marker, _)| user_agent.contains(marker))
+    .map_or("unknown OS", |(_, name)| name);
+
+    format!("{} on {}", browser, os)
+}
+
+pub fn get_keyed_hash(secret: &str, domain: &str, value: &str) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}:{}", domain, value).as_bytes());
+
+    HEXLOWER.encode(&mac.finalize().into_bytes())
 }
 
 pub fn get_key(redis_action: RedisAction, hashed_ip: &str) -> String {
     format!("{}:{}", redis_action.as_ref(), hashed_ip)
 }
+
+pub fn get_user_agent(headers: &HeaderMap) -> String {
+    headers
+        .get(USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .chars()
+        .take(MAX_USER_AGENT_CHARS)
+        .collect()
+}
+
+pub fn get_session_handle(session_id: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(session_id.as_bytes());
+
+    format!("{:x}", hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn get_proxies() -> Proxies {
+        Proxies {
+            trusted: vec!["10.0.0.0/8".parse().unwrap()],
+            client_ip_headers: vec!["x-forwarded-for".to_string()],
+        }
+    }
+
+    fn get_headers(forwarded_for: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_str(forwarded_for).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn ignores_forwarded_headers_from_untrusted_peers() {
+        let direct_ip = "203.0.113.9".parse().unwrap();
+        let headers = get_headers("198.51.100.1");
+
+        assert_eq!(
+            get_client_ip(&headers, direct_ip, &get_proxies()),
+            direct_ip
+        );
+    }
+
+    #[test]
+    fn uses_rightmost_untrusted_forwarded_ip() {
+        let direct_ip = "10.0.0.2".parse().unwrap();
+        let headers = get_headers("1.2.3.4, 198.51.100.1, 10.0.0.5");
+
+        assert_eq!(
+            get_client_ip(&headers, direct_ip, &get_proxies()),
+            "198.51.100.1".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn falls_back_to_peer_when_forwarded_ips_are_all_trusted() {
+        let direct_ip = "10.0.0.2".parse().unwrap();
+        let headers = get_headers("10.0.0.7, 10.0.0.5");
+
+        assert_eq!(
+            get_client_ip(&headers, direct_ip, &get_proxies()),
+            direct_ip
+        );
+    }
+
+    #[test]
+    fn ignores_headers_that_are_not_configured() {
+        let direct_ip = "10.0.0.2".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("cf-connecting-ip", HeaderValue::from_static("198.51.100.1"));
+
+        assert_eq!(
+            get_client_ip(&headers, direct_ip, &get_proxies()),
+            direct_ip
+        );
+    }
+
+    #[test]
+    fn sites_with_accounts_resolve_their_website_path() {
+        assert!(get_website_path("swap").is_ok_and(|path| path == WebsitePath::BoilerSwap));
+        assert!(get_website_path("home").is_ok_and(|path| path == WebsitePath::Home));
+        assert!(matches!(
+            get_website_path("photos"),
+            Err(AppError::Config(_))
+        ));
+        assert!(matches!(
+            get_website_path("unknown"),
+            Err(AppError::Config(_))
+        ));
+    }
+}
@@ -1,12 +1,14 @@
 use super::{
-    cookies::get_cookie,
-    models::{Account, DummyClaims, VerifiedTokenResult},
-    twofactor::CODE_REGEX,
-    utilities::{check_path, format_verified_result},
+    cookies::{get_cookie, verify_csrf},
+    models::{Account, Action, ApiClaims, VerifiedTokenResult},
+    password::PasswordPolicy,
+    totp::RECOVERY_CODE_REGEX,
+    twofactor::{CODE_REGEX, LINK_TOKEN_REGEX},
+    utilities::{check_path, format_verified_result, get_pending_account},
 };
 use crate::{
     AppError, AppState, RedisAction, WebsitePath,
-    config::{read_secret, try_load},
+    config::{Config, EmailPolicy, try_load},
 };
 use argon2::{
     Algorithm::Argon2id, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier,
@@ -14,21 +16,20 @@ use argon2::{
 };
 use axum::{
     extract::Request,
-    http::header::{HeaderMap, ORIGIN},
+    http::{
+        Method,
+        header::{HeaderMap, ORIGIN},
+    },
 };
-use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode};
+use chrono::Utc;
+use jsonwebtoken::{Algorithm, Validation, decode, decode_header};
 use once_cell::sync::Lazy;
 use rand::rngs::OsRng;
-use regex::Regex;
 use std::sync::Arc;
+use tracing::warn;
 
-pub static EMAIL_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^.+@purdue\.edu$").unwrap());
-
-pub static VALIDATION: Lazy<Validation> = Lazy::new(|| Validation::new(Algorithm::HS256));
-
-pub static SWAP_DECODING_KEY: Lazy<DecodingKey> = Lazy::new(|| read_decoding_key("SWAP_API_TOKEN"));
-
-pub static HOME_DECODING_KEY: Lazy<DecodingKey> = Lazy::new(|| read_decoding_key("HOME_API_TOKEN"));
+pub static API_TOKEN_MAX_AGE_SECS: Lazy<i64> =
+    Lazy::new(|| try_load("RUST_API_TOKEN_MAX_AGE_SECS", "300").unwrap());
 
 pub static MAX_CHARS: Lazy<usize> = Lazy::new(|| try_load("PUBLIC_MAX_CHARS", "100").unwrap());
 
@@ -37,6 +38,16 @@ pub static CODE_LENGTH: Lazy<usize> = Lazy::new(|| try_load("PUBLIC_CODE_LENGTH"
 pub static MIN_PASSWORD_LENGTH: Lazy<usize> =
     Lazy::new(|| try_load("PUBLIC_MIN_PASSWORD_LENGTH", "10").unwrap());
 
+pub static ARGON2_PARAMS: Lazy<Params> = Lazy::new(|| {
+    Params::new(
+        try_load("RUST_ARGON2_MEMORY_KIB", "65536").unwrap(),
+        try_load("RUST_ARGON2_ITERATIONS", "3").unwrap(),
+        try_load("RUST_ARGON2_PARALLELISM", "1").unwrap(),
+        None,
+    )
+    .unwrap_or_else(|e| panic!("Failed to create Argon2 params: {}", e))
+});
+
 pub async fn verify_token(
     state: Arc<AppState>,
     headers: HeaderMap,
@@ -58,32 +69,74 @@ pub async fn verify_token(
     Ok(None)
 }
 
-pub fn verify_api_token(headers: &HeaderMap, website_path: &WebsitePath) -> bool {
+pub fn verify_api_token(config: &Config, headers: &HeaderMap, website_path: &WebsitePath) -> bool {
     let jwt = match get_cookie(headers, "api_token") {
         Some(token) => token,
         None => return false,
     };
 
-    let decoding_key = match website_path {
-        WebsitePath::BoilerSwap => &SWAP_DECODING_KEY,
-        WebsitePath::Photos => panic!("verify_api_token should not be called for Photos"),
-        WebsitePath::Home => &HOME_DECODING_KEY,
+    let decoding_keys = match website_path {
+        WebsitePath::BoilerSwap => &config.secrets.swap_api_keys,
+        WebsitePath::Home => &config.secrets.home_api_keys,
+        WebsitePath::Photos => return false,
     };
 
-    decode::<DummyClaims>(&jwt, decoding_key, &VALIDATION).is_ok()
+    let Some(decoding_key) = decode_header(&jwt)
+        .ok()
+        .and_then(|header| decoding_keys.get(&header.kid?))
+    else {
+        return false;
+    };
+
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.set_audience(&[website_path.as_ref()]);
+    validation.set_required_spec_claims(&["aud", "iat", "exp"]);
+
+    let Ok(token) = decode::<ApiClaims>(&jwt, decoding_key, &validation) else {
+        return false;
+    };
+
+    token.claims.iat <= Utc::now().timestamp() + validation.leeway as i64
+        && token.claims.exp - token.claims.iat <= *API_TOKEN_MAX_AGE_SECS
 }
 
 pub fn verify_password(password: &str, password_hash: &str) -> bool {
-    let parsed_hash = PasswordHash::new(password_hash)
-        .unwrap_or_else(|e| panic!("Failed to parse password hash: {}", e));
+    let parsed_hash = match PasswordHash::new(password_hash) {
+        Ok(parsed_hash) => parsed_hash,
+        Err(e) => {
+            warn!("Failed to parse password hash: {}", e);
+            return false;
+        }
+    };
 
     Argon2::default()
         .verify_password(password.as_bytes(), &parsed_hash)
         .is_ok()
 }
 
-pub fn validate_account(email: &str, password: &str) -> Result<(), &'static str> {
-    validate_email(email)?;
+pub fn needs_rehash(password_hash: &str) -> bool {
+    let Ok(parsed_hash) = PasswordHash::new(password_hash) else {
+        return true;
+    };
+
+    let Ok(params) = Params::try_from(&parsed_hash) else {
+        return true;
+    };
+
+    parsed_hash.hash.is_none()
+        || parsed_hash.algorithm != Argon2id.ident()
+        || parsed_hash.version != Some(V0x13.into())
+        || params.m_cost() != ARGON2_PARAMS.m_cost()
+        || params.t_cost() != ARGON2_PARAMS.t_cost()
+        || params.p_cost() != ARGON2_PARAMS.p_cost()
+}
+
+pub fn validate_account(
+    email_policy: &EmailPolicy,
+    email: &str,
+    password: &str,
+) -> Result<(), String> {
+    validate_email(email_policy, email)?;
 
     validate_password(password)?;
 
@@ -95,53 +148,95 @@ pub fn validate_length(payload: &str) -> bool {
 }
 
 pub fn validate_password(password: &str) -> Result<(), &'static str> {
-    if !validate_length(password) && password.len() >= *MIN_PASSWORD_LENGTH {
+    if password.is_empty() {
+        return Err("Password cannot be empty");
+    }
+
+    if !validate_length(password) {
         return Err("Too many chars");
     }
 
-    if password.is_empty() {
-        return Err("Password cannot be empty");
+    if password.chars().count() < *MIN_PASSWORD_LENGTH {
+        return Err("Password too short");
     }
 
     Ok(())
 }
 
-pub fn validate_email(email: &str) -> Result<(), &'static str> {
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+pub fn validate_email(email_policy: &EmailPolicy, email: &str) -> Result<(), String> {
     if !validate_length(email) {
-        return Err("Too many chars");
+        return Err("Too many chars".to_string());
     }
 
-    if !EMAIL_REGEX.is_match(email) {
-        return Err("Email must be a Purdue address");
+    let email = email.to_lowercase();
+
+    let Some((local_part, domain)) = email.rsplit_once('@') else {
+        return Err("Invalid email".to_string());
+    };
+
+    if local_part.is_empty()
+        || email_policy
+            .denied_domains
+            .iter()
+            .any(|denied| denied == domain)
+        || email_policy
+            .denied_patterns
+            .iter()
+            .any(|denied| denied.is_match(&email))
+    {
+        return Err("Email address not allowed".to_string());
+    }
+
+    if !email_policy
+        .allowed_domains
+        .iter()
+        .any(|allowed| allowed == domain)
+        && !email_policy
+            .allowed_patterns
+            .iter()
+            .any(|allowed| allowed.is_match(&email))
+    {
+        return Err(format!(
+            "Accepted emails: {}",
+            email_policy
+                .allowed_domains
+                .iter()
+                .map(|allowed| format!("@{}", allowed))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
     }
 
     Ok(())
 }
 
+pub fn get_email_policy<'a>(
+    config: &'a Config,
+    website_path: &WebsitePath,
+) -> Result<&'a EmailPolicy, AppError> {
+    match website_path {
+        WebsitePath::BoilerSwap => Ok(&config.email_policies.swap),
+        WebsitePath::Home => Ok(&config.email_policies.home),
+        WebsitePath::Photos => Err(AppError::Config(format!(
+            "{} has no accounts",
+            website_path.as_ref()
+        ))),
+    }
+}
+
 pub fn hash_password(password: &str) -> String {
     let salt = SaltString::generate(&mut OsRng);
 
-    let params = Params::new(65536, 3, 1, None)
-        .unwrap_or_else(|e| panic!("Failed to create Argon2 params: {}", e));
+    let argon2 = Argon2::new(Argon2id, V0x13, ARGON2_PARAMS.clone());
 
-    let argon2 = Argon2::new(Argon2id, V0x13, params);
-
-    let password_hash = argon2
+    argon2
         .hash_password(password.as_bytes(), &salt)
         .unwrap_or_else(|e| panic!("Failed to hash password: {}", e))
-        .to_string();
-
-    password_hash
-}
-
-fn read_decoding_key(secret_name: &str) -> DecodingKey {
-    DecodingKey::from_secret(
-        read_secret(secret_name)
-            .unwrap_or_else(|e| {
-                panic!("Failed to load {}: {}", secret_name, e);
-            })
-            .as_bytes(),
-    )
+        .to_string()
 }
 
 pub async fn check_token(
@@ -158,19 +253,37 @@ pub async fn check_token(
     }
 }
 
-pub fn check_token_content(redis_action: &RedisAction, token: &str) -> Result<(), AppError> {
-    match redis_action {
-        RedisAction::Update => validate_password(token)
-            .map_err(|_| AppError::Unauthorized("Unable to verify".to_string())),
-        RedisAction::Auth | RedisAction::Forgot
-            if token.len() != *CODE_LENGTH || !CODE_REGEX.is_match(token) =>
+pub fn check_token_content(
+    state: Arc<AppState>,
+    verified_result: &VerifiedTokenResult,
+    token: &str,
+) -> Result<(), AppError> {
+    match verified_result.redis_action {
+        RedisAction::Update => check_password(
+            &state.password_policy,
+            &get_pending_account(verified_result)?.email,
+            token,
+        ),
+        RedisAction::Auth
+            if !is_second_factor_format(token) && !LINK_TOKEN_REGEX.is_match(token) =>
         {
             Err(AppError::Unauthorized("Unable to verify".to_string()))
         }
+        RedisAction::Forgot if !is_code_format(token) => {
+            Err(AppError::Unauthorized("Unable to verify".to_string()))
+        }
         _ => Ok(()),
     }
 }
 
+pub fn is_code_format(token: &str) -> bool {
+    token.len() == *CODE_LENGTH && CODE_REGEX.is_match(token)
+}
+
+pub fn is_second_factor_format(token: &str) -> bool {
+    is_code_format(token) || RECOVERY_CODE_REGEX.is_match(token)
+}
+
 pub async fn is_request_authorized(
     state: Arc<AppState>,
     headers: &HeaderMap,
@@ -189,20 +302,64 @@ pub async fn is_request_authorized(
         .await?
         .ok_or_else(|| AppError::Unauthorized("Unable to verify".to_string()))?;
 
-    if !verify_api_token(headers, &website_path) {
+    if !verify_api_token(&state.config, headers, &website_path) {
         return Err(AppError::Unauthorized("Unable to verify".to_string()));
     }
 
+    if (website_path == WebsitePath::BoilerSwap || request.extensions().get::<String>().is_some())
+        && matches!(
+            *request.method(),
+            Method::POST | Method::PUT | Method::PATCH | Method::DELETE
+        )
+    {
+        verify_csrf(&state.config, headers)?;
+    }
+
     Ok(())
 }
 
-pub fn check_email(token: &str) -> Result<(), AppError> {
-    validate_email(token).map_err(|e| AppError::BadRequest(e.to_string()))
+pub fn check_email(
+    state: Arc<AppState>,
+    token: &str,
+    website_path: &WebsitePath,
+) -> Result<(), AppError> {
+    validate_email(get_email_policy(&state.config, website_path)?, token)
+        .map_err(AppError::BadRequest)
 }
 
-pub fn check_account(payload: &Account) -> Result<(), AppError> {
-    validate_account(&payload.email, &payload.password)
-        .map_err(|e| AppError::BadRequest(e.to_string()))
+pub fn check_account(
+    state: Arc<AppState>,
+    payload: &Account,
+    website_path: &WebsitePath,
+) -> Result<(), AppError> {
+    if !matches!(payload.action, Action::Login | Action::Signup) {
+        return Err(AppError::BadRequest("Invalid action".to_string()));
+    }
+
+    validate_account(
+        get_email_policy(&state.config, website_path)?,
+        &payload.email,
+        &payload.password,
+    )
+    .map_err(AppError::BadRequest)?;
+
+    if payload.action == Action::Signup {
+        check_password(&state.password_policy, &payload.email, &payload.password)?;
+    }
+
+    Ok(())
+}
+
+pub fn check_password(
+    password_policy: &PasswordPolicy,
+    email: &str,
+    password: &str,
+) -> Result<(), AppError> {
+    validate_password(password).map_err(|e| AppError::BadRequest(e.to_string()))?;
+
+    password_policy
+        .check(email, password)
+        .map_err(AppError::BadRequest)
 }
 
 pub fn check_resend(payload: &VerifiedTokenResult) -> Result<(), AppError> {
@@ -213,3 +370,73 @@ pub fn check_resend(payload: &VerifiedTokenResult) -> Result<(), AppError> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use argon2::Algorithm::Argon2i;
+
+    const PASSWORD: &str = "correct horse battery staple";
+
+    fn hash_with(argon2: Argon2) -> String {
+        argon2
+            .hash_password(PASSWORD.as_bytes(), &SaltString::generate(&mut OsRng))
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn each_site_with_accounts_has_its_own_email_policy() {
+        let mut config = Config::for_tests();
+        config.email_policies.home.allowed_domains = vec!["example.com".to_string()];
+
+        assert!(
+            get_email_policy(&config, &WebsitePath::Home)
+                .is_ok_and(|policy| policy.allowed_domains == vec!["example.com".to_string()])
+        );
+        assert!(
+            get_email_policy(&config, &WebsitePath::BoilerSwap)
+                .is_ok_and(|policy| policy.allowed_domains != vec!["example.com".to_string()])
+        );
+        assert!(matches!(
+            get_email_policy(&config, &WebsitePath::Photos),
+            Err(AppError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn current_hashes_verify_without_rehash() {
+        let password_hash = hash_password(PASSWORD);
+
+        assert!(verify_password(PASSWORD, &password_hash));
+        assert!(!verify_password(
+            "wrong horse battery staple",
+            &password_hash
+        ));
+        assert!(!needs_rehash(&password_hash));
+    }
+
+    #[test]
+    fn outdated_parameters_verify_and_need_rehash() {
+        let password_hash = hash_with(Argon2::default());
+
+        assert!(verify_password(PASSWORD, &password_hash));
+        assert!(needs_rehash(&password_hash));
+    }
+
+    #[test]
+    fn outdated_algorithms_verify_and_need_rehash() {
+        let password_hash = hash_with(Argon2::new(Argon2i, V0x13, ARGON2_PARAMS.clone()));
+
+        assert!(verify_password(PASSWORD, &password_hash));
+        assert!(needs_rehash(&password_hash));
+    }
+
+    #[test]
+    fn corrupt_hashes_fail_without_panicking() {
+        for password_hash in ["", "not a hash", "$argon2id$v=19$m=65536,t=3,p=1$broken"] {
+            assert!(!verify_password(PASSWORD, password_hash));
+            assert!(needs_rehash(password_hash));
+        }
+    }
+}
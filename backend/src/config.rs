@@ -1,18 +1,65 @@
-use crate::AppError;
-use std::{env, fmt::Display, fs::read_to_string, str::FromStr};
+use crate::{AppError, api::web::models::WebsitePath};
+use ipnet::IpNet;
+use jsonwebtoken::DecodingKey;
+use once_cell::sync::Lazy;
+use regex::{Regex, RegexBuilder};
+use std::{collections::HashMap, env, fmt::Display, fs::read_to_string, net::IpAddr, str::FromStr};
+use strum_macros::{AsRefStr, EnumString};
 use tracing::{info, warn};
 
+static KID_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^[A-Za-z0-9][A-Za-z0-9._-]{0,63}$").unwrap());
+
 #[derive(Clone)]
 pub struct Server {
     pub rust_port: u16,
     pub svelte_url: String,
 }
 
+#[derive(Clone, EnumString, AsRefStr, PartialEq)]
+#[strum(serialize_all = "lowercase")]
+pub enum MailTransportKind {
+    Smtp,
+    File,
+    Memory,
+}
+
+#[derive(Clone)]
+pub struct Proxies {
+    pub trusted: Vec<IpNet>,
+    pub client_ip_headers: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct IpHashing {
+    pub grace_seconds: u32,
+}
+
+#[derive(Clone)]
+pub struct IpHashSecrets {
+    pub current: String,
+    pub previous: Option<String>,
+}
+
 #[derive(Clone)]
 pub struct Email {
+    pub transport: MailTransportKind,
+    pub outbox_path: String,
     pub from_email: String,
     pub from_email_server: String,
     pub from_email_password: String,
+    pub templates_path: String,
+    pub default_locale: String,
+}
+
+#[derive(Clone)]
+pub struct MailQueue {
+    pub workers: u8,
+    pub pool_size: u32,
+    pub max_attempts: u8,
+    pub retry_base_seconds: u16,
+    pub retry_max_seconds: u16,
+    pub poll_interval_ms: u16,
 }
 
 #[derive(Clone)]
@@ -23,6 +70,7 @@ pub struct Authentication {
     pub verify_lock_duration_seconds: u16,
     pub max_codes: u8,
     pub max_codes_duration_seconds: u16,
+    pub code_max_attempts: u8,
 }
 
 #[derive(Clone)]
@@ -30,6 +78,12 @@ pub struct Session {
     pub temporary_session_duration_seconds: u16,
     pub max_sessions: u8,
     pub session_duration_seconds: u16,
+    pub remember_session_duration_seconds: u32,
+    pub session_renewal_percent: u8,
+    pub session_max_lifetime_seconds: u32,
+    pub remember_session_max_lifetime_seconds: u32,
+    pub known_device_duration_seconds: u32,
+    pub freeze_link_duration_seconds: u32,
 }
 
 #[derive(Clone)]
@@ -38,6 +92,92 @@ pub struct WebsiteSpecific {
     pub home_limit_ms: u8,
 }
 
+#[derive(Clone, EnumString, AsRefStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum RateLimitAlgorithm {
+    SlidingWindow,
+    TokenBucket,
+}
+
+#[derive(Clone, EnumString, AsRefStr)]
+#[strum(serialize_all = "lowercase")]
+pub enum RateLimitKey {
+    Ip,
+    Email,
+    Session,
+}
+
+#[derive(Clone)]
+pub struct RateLimitPolicy {
+    pub algorithm: RateLimitAlgorithm,
+    pub key: RateLimitKey,
+    pub limit: u32,
+    pub window_ms: u64,
+}
+
+#[derive(Clone)]
+pub struct RateLimits {
+    pub visitors: Vec<RateLimitPolicy>,
+    pub authenticate: Vec<RateLimitPolicy>,
+    pub verify: Vec<RateLimitPolicy>,
+    pub forgot: Vec<RateLimitPolicy>,
+    pub resend: Vec<RateLimitPolicy>,
+    pub passkeys: Vec<RateLimitPolicy>,
+    pub account: Vec<RateLimitPolicy>,
+    pub post_item: Vec<RateLimitPolicy>,
+}
+
+#[derive(Clone)]
+pub struct EmailPolicy {
+    pub allowed_domains: Vec<String>,
+    pub allowed_patterns: Vec<Regex>,
+    pub denied_domains: Vec<String>,
+    pub denied_patterns: Vec<Regex>,
+}
+
+#[derive(Clone)]
+pub struct EmailPolicies {
+    pub swap: EmailPolicy,
+    pub home: EmailPolicy,
+}
+
+#[derive(Clone)]
+pub struct Admins {
+    pub swap: Vec<String>,
+    pub home: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct Passwords {
+    pub min_entropy_bits: f64,
+    pub blocklist_path: String,
+}
+
+#[derive(Clone)]
+pub struct Passkeys {
+    pub rp_id: String,
+    pub rp_name: String,
+}
+
+#[derive(Clone)]
+pub struct Oidc {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub redirect_base_url: String,
+    pub scopes: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct Secrets {
+    pub csrf_secret: String,
+    pub swap_api_keys: HashMap<String, DecodingKey>,
+    pub home_api_keys: HashMap<String, DecodingKey>,
+    pub ip_hash: IpHashSecrets,
+    pub code_hash_secret: String,
+    pub mail_queue_secret: String,
+}
+
 #[derive(Clone)]
 pub struct Bot {
     pub num_pictures: u8,
@@ -49,10 +189,20 @@ pub struct Bot {
 #[derive(Clone)]
 pub struct Config {
     pub server: Server,
+    pub proxies: Proxies,
+    pub ip_hashing: IpHashing,
     pub email: Email,
+    pub mail_queue: MailQueue,
     pub authentication: Authentication,
     pub session: Session,
     pub website_specific: WebsiteSpecific,
+    pub rate_limits: RateLimits,
+    pub email_policies: EmailPolicies,
+    pub admins: Admins,
+    pub passwords: Passwords,
+    pub passkeys: Passkeys,
+    pub oidc: Oidc,
+    pub secrets: Secrets,
     pub bot: Bot,
 }
 
@@ -65,18 +215,85 @@ impl Server {
     }
 }
 
+impl Proxies {
+    pub fn load() -> Result<Self, AppError> {
+        Ok(Self {
+            trusted: load_list("RUST_TRUSTED_PROXIES", "")?
+                .iter()
+                .map(|cidr| {
+                    cidr.parse()
+                        .or_else(|_| cidr.parse::<IpAddr>().map(IpNet::from))
+                        .map_err(|_| {
+                            AppError::Config(format!("Invalid RUST_TRUSTED_PROXIES entry {}", cidr))
+                        })
+                })
+                .collect::<Result<_, _>>()?,
+            client_ip_headers: load_list("RUST_CLIENT_IP_HEADERS", "x-forwarded-for")?,
+        })
+    }
+
+    pub fn is_trusted(&self, ip: &IpAddr) -> bool {
+        self.trusted.iter().any(|cidr| cidr.contains(ip))
+    }
+}
+
+impl IpHashing {
+    pub fn load(authentication: &Authentication) -> Result<Self, AppError> {
+        let ip_hashing = Self {
+            grace_seconds: try_load("RUST_IP_HASH_GRACE_SECS", "3600")?,
+        };
+
+        let longest_lock_seconds = [
+            authentication.auth_lock_duration_seconds,
+            authentication.verify_lock_duration_seconds,
+            authentication.max_codes_duration_seconds,
+        ]
+        .into_iter()
+        .max()
+        .unwrap_or(0);
+
+        if ip_hashing.grace_seconds < u32::from(longest_lock_seconds) {
+            return Err(AppError::Config(format!(
+                "RUST_IP_HASH_GRACE_SECS must cover the longest lock duration of {} seconds",
+                longest_lock_seconds
+            )));
+        }
+
+        Ok(ip_hashing)
+    }
+}
+
 impl Email {
+    pub fn load() -> Result<Self, AppError> {
+        let transport: MailTransportKind = try_load("RUST_EMAIL_TRANSPORT", "smtp")?;
+
+        let load_smtp_secret = |secret_name: &str, default: &str| match transport {
+            MailTransportKind::Smtp => read_secret(secret_name)
+                .map_err(|e| AppError::Config(format!("Failed to load {}: {}", secret_name, e))),
+            _ => Ok(read_secret(secret_name).unwrap_or_else(|_| default.to_string())),
+        };
+
+        Ok(Self {
+            from_email: load_smtp_secret("RUST_FROM_EMAIL", "noreply@localhost")?,
+            from_email_server: load_smtp_secret("RUST_FROM_EMAIL_SERVER", "")?,
+            from_email_password: load_smtp_secret("RUST_FROM_EMAIL_PASSWORD", "")?,
+            outbox_path: try_load("RUST_EMAIL_OUTBOX_PATH", "outbox")?,
+            templates_path: try_load("RUST_EMAIL_TEMPLATES_PATH", "templates/email")?,
+            default_locale: try_load("RUST_EMAIL_DEFAULT_LOCALE", "en")?,
+            transport,
+        })
+    }
+}
+
+impl MailQueue {
     pub fn load() -> Result<Self, AppError> {
         Ok(Self {
-            from_email: read_secret("RUST_FROM_EMAIL").unwrap_or_else(|e| {
-                panic!("Failed to load RUST_FROM_EMAIL: {}", e);
-            }),
-            from_email_server: read_secret("RUST_FROM_EMAIL_SERVER").unwrap_or_else(|e| {
-                panic!("Failed to load RUST_FROM_EMAIL_SERVER: {}", e);
-            }),
-            from_email_password: read_secret("RUST_FROM_EMAIL_PASSWORD").unwrap_or_else(|e| {
-                panic!("Failed to load RUST_FROM_EMAIL_PASSWORD: {}", e);
-            }),
+            workers: try_load("RUST_MAIL_WORKERS", "2")?,
+            pool_size: try_load("RUST_MAIL_POOL_SIZE", "4")?,
+            max_attempts: try_load("RUST_MAIL_MAX_ATTEMPTS", "5")?,
+            retry_base_seconds: try_load("RUST_MAIL_RETRY_BASE_SECS", "5")?,
+            retry_max_seconds: try_load("RUST_MAIL_RETRY_MAX_SECS", "300")?,
+            poll_interval_ms: try_load("RUST_MAIL_POLL_INTERVAL_MS", "500")?,
         })
     }
 }
@@ -90,6 +307,7 @@ impl Authentication {
             verify_lock_duration_seconds: try_load("RUST_VERIFY_LOCK_DURATION_SECS", "600")?,
             max_codes: try_load("RUST_MAX_CODES", "5")?,
             max_codes_duration_seconds: try_load("RUST_MAX_CODES_DURATION_SECS", "1800")?,
+            code_max_attempts: try_load("RUST_CODE_MAX_ATTEMPTS", "5")?,
         })
     }
 }
@@ -103,6 +321,18 @@ impl Session {
             )?,
             max_sessions: try_load("RUST_MAX_SESSIONS", "2")?,
             session_duration_seconds: try_load("RUST_SESSION_DURATION_SECS", "3600")?,
+            remember_session_duration_seconds: try_load(
+                "RUST_REMEMBER_SESSION_DURATION_SECS",
+                "604800",
+            )?,
+            session_renewal_percent: try_load("RUST_SESSION_RENEWAL_PERCENT", "50")?,
+            session_max_lifetime_seconds: try_load("RUST_SESSION_MAX_LIFETIME_SECS", "86400")?,
+            remember_session_max_lifetime_seconds: try_load(
+                "RUST_REMEMBER_SESSION_MAX_LIFETIME_SECS",
+                "2592000",
+            )?,
+            known_device_duration_seconds: try_load("RUST_KNOWN_DEVICE_DURATION_SECS", "7776000")?,
+            freeze_link_duration_seconds: try_load("RUST_FREEZE_LINK_DURATION_SECS", "604800")?,
         })
     }
 }
@@ -116,6 +346,156 @@ impl WebsiteSpecific {
     }
 }
 
+impl FromStr for RateLimitPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let [algorithm, key, limit, window] = value
+            .split(':')
+            .map(str::trim)
+            .collect::<Vec<_>>()
+            .try_into()
+            .map_err(|_| format!("expected algorithm:key:limit:window, got {}", value))?;
+
+        let policy = Self {
+            algorithm: algorithm
+                .parse()
+                .map_err(|_| format!("unknown algorithm {}", algorithm))?,
+            key: key.parse().map_err(|_| format!("unknown key {}", key))?,
+            limit: limit
+                .parse()
+                .map_err(|_| format!("invalid limit {}", limit))?,
+            window_ms: parse_window_ms(window)?,
+        };
+
+        if policy.limit == 0 || policy.window_ms == 0 {
+            return Err(format!("limit and window must be positive in {}", value));
+        }
+
+        Ok(policy)
+    }
+}
+
+impl RateLimits {
+    pub fn load(website_specific: &WebsiteSpecific) -> Result<Self, AppError> {
+        Ok(Self {
+            visitors: load_policies(
+                "RUST_RATE_LIMIT_VISITORS",
+                &format!("sliding_window:ip:1:{}ms", website_specific.home_limit_ms),
+            )?,
+            authenticate: load_policies(
+                "RUST_RATE_LIMIT_AUTHENTICATE",
+                "sliding_window:ip:30:1m,sliding_window:email:10:1m",
+            )?,
+            verify: load_policies("RUST_RATE_LIMIT_VERIFY", "sliding_window:ip:30:1m")?,
+            forgot: load_policies(
+                "RUST_RATE_LIMIT_FORGOT",
+                "sliding_window:ip:10:1m,sliding_window:email:3:1m",
+            )?,
+            resend: load_policies("RUST_RATE_LIMIT_RESEND", "sliding_window:ip:10:1m")?,
+            passkeys: load_policies("RUST_RATE_LIMIT_PASSKEYS", "sliding_window:ip:30:1m")?,
+            account: load_policies("RUST_RATE_LIMIT_ACCOUNT", "token_bucket:session:10:1m")?,
+            post_item: load_policies("RUST_RATE_LIMIT_POST_ITEM", "token_bucket:session:5:1m")?,
+        })
+    }
+}
+
+impl EmailPolicy {
+    pub fn load(prefix: &str) -> Result<Self, AppError> {
+        Ok(Self {
+            allowed_domains: load_list(&format!("{}_EMAIL_ALLOWED_DOMAINS", prefix), "purdue.edu")?,
+            allowed_patterns: load_patterns(&format!("{}_EMAIL_ALLOWED_PATTERNS", prefix))?,
+            denied_domains: load_list(&format!("{}_EMAIL_DENIED_DOMAINS", prefix), "")?,
+            denied_patterns: load_patterns(&format!("{}_EMAIL_DENIED_PATTERNS", prefix))?,
+        })
+    }
+}
+
+impl EmailPolicies {
+    pub fn load() -> Result<Self, AppError> {
+        Ok(Self {
+            swap: EmailPolicy::load("RUST_SWAP")?,
+            home: EmailPolicy::load("RUST_HOME")?,
+        })
+    }
+}
+
+impl Admins {
+    pub fn load() -> Result<Self, AppError> {
+        Ok(Self {
+            swap: load_list("RUST_SWAP_ADMIN_EMAILS", "")?,
+            home: load_list("RUST_HOME_ADMIN_EMAILS", "")?,
+        })
+    }
+}
+
+impl Passwords {
+    pub fn load() -> Result<Self, AppError> {
+        Ok(Self {
+            min_entropy_bits: try_load("RUST_PASSWORD_MIN_ENTROPY_BITS", "40")?,
+            blocklist_path: try_load("RUST_PASSWORD_BLOCKLIST_PATH", "data/common_passwords.txt")?,
+        })
+    }
+}
+
+impl Passkeys {
+    pub fn load() -> Result<Self, AppError> {
+        Ok(Self {
+            rp_id: try_load("RUST_PASSKEY_RP_ID", "localhost")?,
+            rp_name: try_load("RUST_PASSKEY_RP_NAME", "BoilerSwap")?,
+        })
+    }
+}
+
+impl Oidc {
+    pub fn load() -> Result<Self, AppError> {
+        let issuer = try_load::<String>("RUST_OIDC_ISSUER", "")?
+            .trim_end_matches('/')
+            .to_string();
+
+        Ok(Self {
+            client_id: try_load("RUST_OIDC_CLIENT_ID", "")?,
+            client_secret: match issuer.is_empty() {
+                true => None,
+                false => read_secret("RUST_OIDC_CLIENT_SECRET").ok(),
+            },
+            redirect_base_url: try_load::<String>(
+                "RUST_OIDC_REDIRECT_BASE_URL",
+                "http://localhost:5173",
+            )?
+            .trim_end_matches('/')
+            .to_string(),
+            scopes: load_list("RUST_OIDC_SCOPES", "openid,email")?,
+            issuer,
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.issuer.is_empty() && !self.client_id.is_empty()
+    }
+
+    pub fn redirect_uri(&self, website_path: &WebsitePath) -> String {
+        format!(
+            "{}/{}/auth/oidc",
+            self.redirect_base_url,
+            website_path.as_ref()
+        )
+    }
+}
+
+impl Secrets {
+    pub fn load() -> Result<Self, AppError> {
+        Ok(Self {
+            csrf_secret: load_secret("RUST_CSRF_SECRET")?,
+            swap_api_keys: load_api_keys("SWAP_API_TOKEN")?,
+            home_api_keys: load_api_keys("HOME_API_TOKEN")?,
+            ip_hash: load_ip_hash_secrets("RUST_IP_HASH_SECRET")?,
+            code_hash_secret: load_secret("RUST_CODE_HASH_SECRET")?,
+            mail_queue_secret: load_secret("RUST_MAIL_QUEUE_SECRET")?,
+        })
+    }
+}
+
 impl Bot {
     pub fn load() -> Result<Self, AppError> {
         Ok(Self {
@@ -129,17 +509,80 @@ impl Bot {
 
 impl Config {
     pub fn load() -> Result<Self, AppError> {
+        let website_specific = WebsiteSpecific::load()?;
+        let authentication = Authentication::load()?;
+
         Ok(Self {
             server: Server::load()?,
+            proxies: Proxies::load()?,
+            ip_hashing: IpHashing::load(&authentication)?,
             email: Email::load()?,
-            authentication: Authentication::load()?,
+            mail_queue: MailQueue::load()?,
+            authentication,
             session: Session::load()?,
-            website_specific: WebsiteSpecific::load()?,
+            rate_limits: RateLimits::load(&website_specific)?,
+            website_specific,
+            email_policies: EmailPolicies::load()?,
+            admins: Admins::load()?,
+            passwords: Passwords::load()?,
+            passkeys: Passkeys::load()?,
+            oidc: Oidc::load()?,
+            secrets: Secrets::load()?,
             bot: Bot::load()?,
         })
     }
 }
 
+#[cfg(test)]
+impl Config {
+    pub fn for_tests() -> Self {
+        let website_specific = WebsiteSpecific::load().unwrap();
+        let authentication = Authentication::load().unwrap();
+
+        Self {
+            server: Server::load().unwrap(),
+            proxies: Proxies::load().unwrap(),
+            ip_hashing: IpHashing::load(&authentication).unwrap(),
+            email: Email {
+                transport: MailTransportKind::Memory,
+                outbox_path: String::new(),
+                from_email: "noreply@localhost".to_string(),
+                from_email_server: String::new(),
+                from_email_password: String::new(),
+                templates_path: "templates/email".to_string(),
+                default_locale: "en".to_string(),
+            },
+            mail_queue: MailQueue::load().unwrap(),
+            authentication,
+            session: Session::load().unwrap(),
+            rate_limits: RateLimits::load(&website_specific).unwrap(),
+            website_specific,
+            email_policies: EmailPolicies::load().unwrap(),
+            admins: Admins {
+                swap: Vec::new(),
+                home: Vec::new(),
+            },
+            passwords: Passwords::load().unwrap(),
+            passkeys: Passkeys::load().unwrap(),
+            oidc: Oidc::load().unwrap(),
+            secrets: Secrets {
+                csrf_secret: "test-csrf-secret".to_string(),
+                swap_api_keys: parse_api_keys("current=test-swap-secret").unwrap(),
+                home_api_keys: parse_api_keys("current=test-home-secret").unwrap(),
+                ip_hash: parse_ip_hash_secrets("current=test-ip-secret").unwrap(),
+                code_hash_secret: "test-code-secret".to_string(),
+                mail_queue_secret: "test-mail-queue-secret".to_string(),
+            },
+            bot: Bot {
+                num_pictures: 4,
+                pictures_ttl: 86400,
+                max_bytes: 5_242_880,
+                photo_url: String::new(),
+            },
+        }
+    }
+}
+
 fn var(key: &str) -> Result<String, AppError> {
     env::var(key).map_err(|e| {
         warn!("Environment variable {} not found, using default", key);
@@ -157,6 +600,79 @@ pub fn read_secret(secret_name: &str) -> Result<String, AppError> {
         })
 }
 
+fn load_secret(secret_name: &str) -> Result<String, AppError> {
+    match read_secret(secret_name) {
+        Ok(secret) if !secret.is_empty() => Ok(secret),
+        Ok(_) => Err(AppError::Config(format!("{} is empty", secret_name))),
+        Err(e) => Err(AppError::Config(format!(
+            "Failed to load {}: {}",
+            secret_name, e
+        ))),
+    }
+}
+
+fn load_api_keys(secret_name: &str) -> Result<HashMap<String, DecodingKey>, AppError> {
+    parse_api_keys(&load_secret(secret_name)?)
+        .map_err(|e| AppError::Config(format!("Invalid {} value: {}", secret_name, e)))
+}
+
+fn load_ip_hash_secrets(secret_name: &str) -> Result<IpHashSecrets, AppError> {
+    parse_ip_hash_secrets(&load_secret(secret_name)?)
+        .map_err(|e| AppError::Config(format!("Invalid {} value: {}", secret_name, e)))
+}
+
+fn parse_api_keys(value: &str) -> Result<HashMap<String, DecodingKey>, String> {
+    Ok(parse_keyring(value)?
+        .into_iter()
+        .map(|(kid, secret)| (kid, DecodingKey::from_secret(secret.as_bytes())))
+        .collect())
+}
+
+fn parse_ip_hash_secrets(value: &str) -> Result<IpHashSecrets, String> {
+    let mut versions = parse_keyring(value)?.into_iter().map(|(_, secret)| secret);
+
+    let ip_hash_secrets = IpHashSecrets {
+        current: versions.next().ok_or("no keys".to_string())?,
+        previous: versions.next(),
+    };
+
+    if versions.next().is_some() {
+        return Err("expected the current version and at most one previous version".to_string());
+    }
+
+    Ok(ip_hash_secrets)
+}
+
+fn parse_keyring(value: &str) -> Result<Vec<(String, String)>, String> {
+    let mut keyring: Vec<(String, String)> = Vec::new();
+
+    for line in value.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let (kid, secret) = line
+            .split_once('=')
+            .ok_or("expected one kid=secret entry per line".to_string())?;
+
+        if !KID_REGEX.is_match(kid) {
+            return Err(format!("invalid kid {}", kid));
+        }
+
+        if secret.is_empty() {
+            return Err(format!("empty secret for kid {}", kid));
+        }
+
+        if keyring.iter().any(|(existing, _)| existing == kid) {
+            return Err(format!("duplicate kid {}", kid));
+        }
+
+        keyring.push((kid.to_string(), secret.to_string()));
+    }
+
+    if keyring.is_empty() {
+        return Err("no keys".to_string());
+    }
+
+    Ok(keyring)
+}
+
 pub fn try_load<T: FromStr>(key: &str, default: &str) -> Result<T, AppError>
 where
     T::Err: Display,
@@ -167,3 +683,186 @@ where
         .parse()
         .map_err(|e| AppError::Config(format!("Invalid {} value: {}", key, e)))
 }
+
+fn load_list(key: &str, default: &str) -> Result<Vec<String>, AppError> {
+    Ok(try_load::<String>(key, default)?
+        .split(',')
+        .map(|entry| entry.trim().to_lowercase())
+        .filter(|entry| !entry.is_empty())
+        .collect())
+}
+
+fn load_patterns(key: &str) -> Result<Vec<Regex>, AppError> {
+    parse_patterns(&try_load::<String>(key, "")?)
+        .map_err(|e| AppError::Config(format!("Invalid {} value: {}", key, e)))
+}
+
+fn parse_patterns(value: &str) -> Result<Vec<Regex>, regex::Error> {
+    value
+        .lines()
+        .map(str::trim)
+        .filter(|pattern| !pattern.is_empty())
+        .map(|pattern| RegexBuilder::new(pattern).case_insensitive(true).build())
+        .collect()
+}
+
+fn load_policies(key: &str, default: &str) -> Result<Vec<RateLimitPolicy>, AppError> {
+    try_load::<String>(key, default)?
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            entry
+                .parse()
+                .map_err(|e| AppError::Config(format!("Invalid {} value: {}", key, e)))
+        })
+        .collect()
+}
+
+fn parse_window_ms(window: &str) -> Result<u64, String> {
+    let (amount, multiplier) = if let Some(amount) = window.strip_suffix("ms") {
+        (amount, 1)
+    } else if let Some(amount) = window.strip_suffix('s') {
+        (amount, 1_000)
+    } else if let Some(amount) = window.strip_suffix('m') {
+        (amount, 60_000)
+    } else if let Some(amount) = window.strip_suffix('h') {
+        (amount, 3_600_000)
+    } else {
+        (window, 1_000)
+    };
+
+    amount
+        .parse::<u64>()
+        .map(|amount| amount * multiplier)
+        .map_err(|_| format!("invalid window {}", window))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns_keep_case_sensitive_escapes_and_quantifiers() {
+        let patterns =
+            parse_patterns(concat!(r"^\S+\.\D{2,4}@example\.com$", "\n\n  ^admin@  ")).unwrap();
+
+        assert_eq!(patterns.len(), 2);
+        assert_eq!(patterns[0].as_str(), r"^\S+\.\D{2,4}@example\.com$");
+        assert!(patterns[0].is_match("pete.ab@example.com"));
+        assert!(!patterns[0].is_match("pete.12@example.com"));
+        assert!(!patterns[0].is_match("pete.abcde@example.com"));
+    }
+
+    #[test]
+    fn patterns_match_case_insensitively() {
+        let patterns = parse_patterns(r"^[a-z]+@Purdue\.EDU$").unwrap();
+
+        assert!(patterns[0].is_match("PETE@purdue.edu"));
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected() {
+        assert!(parse_patterns(r"^(unclosed@example\.com$").is_err());
+    }
+
+    #[test]
+    fn parses_api_keys_by_kid() {
+        let api_keys = parse_api_keys("2024-a=first\n\n 2024-b=second:with=separators ").unwrap();
+
+        assert_eq!(api_keys.len(), 2);
+        assert!(api_keys.contains_key("2024-a"));
+        assert!(api_keys.contains_key("2024-b"));
+    }
+
+    #[test]
+    fn rejects_malformed_api_keys() {
+        for value in [
+            "",
+            "legacy-secret-without-kid",
+            "kid:secret",
+            "=secret",
+            "kid=",
+            "bad kid=secret",
+            "same=first\nsame=second",
+        ] {
+            assert!(parse_api_keys(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn parses_current_and_previous_ip_hash_secrets() {
+        let rotated = parse_ip_hash_secrets("2026-10=new\n2026-07=old").unwrap();
+        assert_eq!(rotated.current, "new");
+        assert_eq!(rotated.previous.as_deref(), Some("old"));
+
+        let single = parse_ip_hash_secrets("2026-10=new").unwrap();
+        assert_eq!(single.previous, None);
+
+        assert!(parse_ip_hash_secrets("a=1\nb=2\nc=3").is_err());
+        assert!(parse_ip_hash_secrets("unversioned-secret").is_err());
+    }
+
+    #[test]
+    fn rejects_ip_hash_grace_shorter_than_locks() {
+        let authentication = Authentication::load().unwrap();
+
+        let ip_hashing = IpHashing::load(&authentication).unwrap();
+        assert!(ip_hashing.grace_seconds >= u32::from(authentication.auth_lock_duration_seconds));
+
+        let longer_lock = Authentication {
+            auth_lock_duration_seconds: u16::MAX,
+            ..authentication
+        };
+        assert!(IpHashing::load(&longer_lock).is_err());
+    }
+
+    #[test]
+    fn parses_rate_limit_policies() {
+        let policy: RateLimitPolicy = "token_bucket:session:10:1m".parse().unwrap();
+
+        assert!(matches!(policy.algorithm, RateLimitAlgorithm::TokenBucket));
+        assert!(matches!(policy.key, RateLimitKey::Session));
+        assert_eq!(policy.limit, 10);
+        assert_eq!(policy.window_ms, 60_000);
+
+        assert_eq!(parse_window_ms("250ms"), Ok(250));
+        assert_eq!(parse_window_ms("30s"), Ok(30_000));
+        assert_eq!(parse_window_ms("2h"), Ok(7_200_000));
+        assert_eq!(parse_window_ms("5"), Ok(5_000));
+    }
+
+    #[test]
+    fn rejects_malformed_rate_limit_policies() {
+        for value in [
+            "sliding_window:ip:30",
+            "leaky_bucket:ip:30:1m",
+            "sliding_window:cookie:30:1m",
+            "sliding_window:ip:0:1m",
+            "sliding_window:ip:30:0s",
+            "sliding_window:ip:30:1d",
+        ] {
+            assert!(value.parse::<RateLimitPolicy>().is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn oidc_redirects_back_to_the_requesting_site() {
+        let oidc = Oidc {
+            issuer: "https://sso.example.com".to_string(),
+            client_id: "pickone".to_string(),
+            client_secret: None,
+            redirect_base_url: "https://pickone.cc".to_string(),
+            scopes: vec!["openid".to_string()],
+        };
+
+        assert_eq!(
+            oidc.redirect_uri(&WebsitePath::BoilerSwap),
+            "https://pickone.cc/swap/auth/oidc"
+        );
+        assert_eq!(
+            oidc.redirect_uri(&WebsitePath::Home),
+            "https://pickone.cc/home/auth/oidc"
+        );
+    }
+}
@@ -1,13 +1,14 @@
-use crate::AppError::{BadRequest, Unauthorized};
+use crate::AppError::{BadRequest, Csrf, Forbidden, TooManyRequests, Unauthorized};
 use anyhow::Error as anyhowError;
 use axum::{
-    http::StatusCode,
+    http::{StatusCode, header::RETRY_AFTER},
     response::{IntoResponse, Response},
 };
 use image::ImageError;
 use lettre::{
-    address::AddressError, error::Error as lettreGeneralError,
-    transport::smtp::Error as lettreTransportError,
+    address::AddressError,
+    error::Error as lettreGeneralError,
+    transport::{file::Error as lettreFileError, smtp::Error as lettreTransportError},
 };
 use meilisearch_sdk::errors::Error as meiliError;
 use prometheus::Error as prometheusError;
@@ -27,6 +28,7 @@ use thiserror::Error;
 use tokio::task::JoinError;
 use tokio_cron_scheduler::JobSchedulerError;
 use tracing::error;
+use webauthn_rs::prelude::WebauthnError;
 
 #[derive(Error, Debug)]
 pub enum AppError {
@@ -72,6 +74,9 @@ pub enum AppError {
     #[error("Lettre transport error: {0}")]
     LettreTransport(#[from] lettreTransportError),
 
+    #[error("Lettre file error: {0}")]
+    LettreFile(#[from] lettreFileError),
+
     #[error("Lettre address error: {0}")]
     LettreAddress(#[from] AddressError),
 
@@ -105,11 +110,26 @@ pub enum AppError {
     #[error("Reqwest error: {0}")]
     Reqwest(#[from] reqwestError),
 
+    #[error("Webauthn error: {0}")]
+    Webauthn(#[from] WebauthnError),
+
+    #[error("Encryption error")]
+    Encryption,
+
     #[error("Invalid Credentials: {0}")]
     Unauthorized(String),
 
     #[error("Malformed payload: {0}")]
     BadRequest(String),
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
+    #[error("Too many requests, retry after {0} seconds")]
+    TooManyRequests(u64),
+
+    #[error("Invalid CSRF token")]
+    Csrf,
 }
 
 impl IntoResponse for AppError {
@@ -117,6 +137,16 @@ impl IntoResponse for AppError {
         let (status, message) = match &self {
             Unauthorized(msg) => (StatusCode::UNAUTHORIZED, msg.clone()),
             BadRequest(msg) => (StatusCode::BAD_REQUEST, msg.clone()),
+            Forbidden(msg) => (StatusCode::FORBIDDEN, msg.clone()),
+            Csrf => (StatusCode::FORBIDDEN, self.to_string()),
+            TooManyRequests(retry_after) => {
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(RETRY_AFTER, retry_after.to_string())],
+                    format!("Try again in {} seconds", retry_after),
+                )
+                    .into_response();
+            }
             _ => {
                 error!("Server error: {}", self);
                 (
@@ -1,3 +1,5 @@
+#![allow(clippy::result_large_err, clippy::collapsible_if)]
+
 use crate::{
     api::{
         bot::{chat::start_bot, photo::photo_handler},
@@ -6,11 +8,23 @@ use crate::{
             database::schema::{BOILER_SWAP_KEYSPACE, columns::boiler_swap::items, tables},
         },
         web::{
+            cookies::CSRF_HEADER,
             handlers::{
-                api_token_check, authenticate_handler, delete_handler, forgot_handler,
-                resend_handler, verify_handler, visitors_handler,
+                admin_clear_locks_handler, admin_delete_item_handler, admin_freeze_handler,
+                admin_reset_items_handler, admin_unlock_handler, admin_user_handler,
+                api_token_check, audit_events_handler, authenticate_handler,
+                change_email_confirm_handler, change_email_handler, change_password_handler,
+                csrf_handler, delete_account_confirm_handler, delete_account_handler,
+                delete_handler, forgot_handler, freeze_handler, oidc_callback_handler,
+                oidc_start_handler, passkey_login_finish_handler, passkey_login_start_handler,
+                passkey_register_finish_handler, passkey_register_start_handler, resend_handler,
+                revoke_all_sessions_handler, revoke_session_handler, sessions_handler,
+                totp_confirm_handler, totp_disenroll_handler, totp_enroll_handler, verify_handler,
+                visitors_handler,
             },
+            mailer::start_mail_workers,
             models::{METRICS_ROUTE, RedisAction, WebsitePath, WebsiteRoute},
+            rate_limit::RateLimitLayer,
             swap::handlers::post_item_handler,
         },
     },
@@ -21,11 +35,11 @@ use crate::{
 };
 use axum::{
     Router,
-    http::{Method, header::CONTENT_TYPE},
+    http::{HeaderName, Method, header::CONTENT_TYPE},
     middleware,
     routing::{delete, get, post},
 };
-use std::{net::SocketAddr, time::Duration};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 use tokio::net::TcpListener;
 use tower_http::cors::{AllowOrigin, CorsLayer};
 use tracing::info;
@@ -51,6 +65,7 @@ async fn main() -> Result<(), AppError> {
     let (state, meili_reindex_future) = AppState::new().await?;
 
     start_bot(state.clone()).await?;
+    start_mail_workers(state.clone()).await?;
 
     info!("Server configuration");
     info!("rust_port = {}", state.config.server.rust_port);
@@ -62,7 +77,7 @@ async fn main() -> Result<(), AppError> {
             origin.as_bytes() == origin_state.config.server.svelte_url.as_bytes()
         }))
         .allow_methods([Method::GET, Method::OPTIONS, Method::POST, Method::DELETE])
-        .allow_headers([CONTENT_TYPE])
+        .allow_headers([CONTENT_TYPE, HeaderName::from_static(CSRF_HEADER)])
         .max_age(Duration::from_secs(60 * 60));
 
     let app = Router::new()
@@ -72,65 +87,50 @@ async fn main() -> Result<(), AppError> {
                 WebsitePath::Home.as_ref(),
                 WebsiteRoute::Api.as_ref()
             ),
-            post(visitors_handler),
-        )
-        .route(
-            &format!(
-                "/{}/{}/{}",
-                WebsitePath::BoilerSwap.as_ref(),
-                WebsiteRoute::Api.as_ref(),
-                WebsiteRoute::Authenticate.as_ref()
-            ),
-            post(authenticate_handler),
+            post(visitors_handler).layer(RateLimitLayer::new(
+                state.clone(),
+                WebsitePath::Home,
+                "visitors",
+                &state.config.rate_limits.visitors,
+            )),
         )
         .route(
             &format!(
-                "/{}/{}/{}",
+                "/{}/{}/post-item",
                 WebsitePath::BoilerSwap.as_ref(),
-                WebsiteRoute::Api.as_ref(),
-                WebsiteRoute::Verify.as_ref()
+                WebsiteRoute::Api.as_ref()
             ),
-            post(verify_handler),
+            post(post_item_handler).layer(RateLimitLayer::new(
+                state.clone(),
+                WebsitePath::BoilerSwap,
+                "post-item",
+                &state.config.rate_limits.post_item,
+            )),
         )
         .route(
             &format!(
-                "/{}/{}/{}",
+                "/{}/{}/{}/users/:email/items",
                 WebsitePath::BoilerSwap.as_ref(),
                 WebsiteRoute::Api.as_ref(),
-                WebsiteRoute::Delete.as_ref()
+                WebsiteRoute::Admin.as_ref()
             ),
-            delete(delete_handler),
+            delete(admin_reset_items_handler),
         )
         .route(
             &format!(
-                "/{}/{}/{}",
+                "/{}/{}/{}/items/:id",
                 WebsitePath::BoilerSwap.as_ref(),
                 WebsiteRoute::Api.as_ref(),
-                WebsiteRoute::Forgot.as_ref()
+                WebsiteRoute::Admin.as_ref()
             ),
-            post(forgot_handler),
-        )
-        .route(
-            &format!(
-                "/{}/{}/post-item",
-                WebsitePath::BoilerSwap.as_ref(),
-                WebsiteRoute::Api.as_ref()
-            ),
-            post(post_item_handler),
-        )
-        .route(
-            &format!(
-                "/{}/{}/{}",
-                WebsitePath::BoilerSwap.as_ref(),
-                WebsiteRoute::Api.as_ref(),
-                WebsiteRoute::Resend.as_ref()
-            ),
-            post(resend_handler),
+            delete(admin_delete_item_handler),
         )
         .route(
             &format!("/{}/:id", WebsitePath::Photos.as_ref()),
             get(photo_handler),
         )
+        .merge(account_routes(state.clone(), WebsitePath::BoilerSwap))
+        .merge(account_routes(state.clone(), WebsitePath::Home))
         .route(METRICS_ROUTE, get(metrics_handler))
         .route_layer(middleware::from_fn_with_state(
             state.clone(),
@@ -173,3 +173,320 @@ async fn main() -> Result<(), AppError> {
 
     Ok(cdc_future.await?)
 }
+
+fn account_routes(state: Arc<AppState>, website_path: WebsitePath) -> Router<Arc<AppState>> {
+    Router::new()
+        .route(
+            &format!(
+                "/{}/{}/{}",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Authenticate.as_ref()
+            ),
+            post(authenticate_handler).layer(RateLimitLayer::new(
+                state.clone(),
+                website_path.clone(),
+                WebsiteRoute::Authenticate.as_ref(),
+                &state.config.rate_limits.authenticate,
+            )),
+        )
+        .route(
+            &format!(
+                "/{}/{}/{}",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Verify.as_ref()
+            ),
+            post(verify_handler).layer(RateLimitLayer::new(
+                state.clone(),
+                website_path.clone(),
+                WebsiteRoute::Verify.as_ref(),
+                &state.config.rate_limits.verify,
+            )),
+        )
+        .route(
+            &format!(
+                "/{}/{}/{}",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Delete.as_ref()
+            ),
+            delete(delete_handler),
+        )
+        .route(
+            &format!(
+                "/{}/{}/{}",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Csrf.as_ref()
+            ),
+            get(csrf_handler),
+        )
+        .route(
+            &format!(
+                "/{}/{}/{}",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Freeze.as_ref()
+            ),
+            post(freeze_handler).layer(RateLimitLayer::new(
+                state.clone(),
+                website_path.clone(),
+                WebsiteRoute::Freeze.as_ref(),
+                &state.config.rate_limits.verify,
+            )),
+        )
+        .route(
+            &format!(
+                "/{}/{}/{}",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Forgot.as_ref()
+            ),
+            post(forgot_handler).layer(RateLimitLayer::new(
+                state.clone(),
+                website_path.clone(),
+                WebsiteRoute::Forgot.as_ref(),
+                &state.config.rate_limits.forgot,
+            )),
+        )
+        .route(
+            &format!(
+                "/{}/{}/{}",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Resend.as_ref()
+            ),
+            post(resend_handler).layer(RateLimitLayer::new(
+                state.clone(),
+                website_path.clone(),
+                WebsiteRoute::Resend.as_ref(),
+                &state.config.rate_limits.resend,
+            )),
+        )
+        .route(
+            &format!(
+                "/{}/{}/{}",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Sessions.as_ref()
+            ),
+            get(sessions_handler).delete(revoke_all_sessions_handler),
+        )
+        .route(
+            &format!(
+                "/{}/{}/{}/:id",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Sessions.as_ref()
+            ),
+            delete(revoke_session_handler),
+        )
+        .route(
+            &format!(
+                "/{}/{}/{}",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Totp.as_ref()
+            ),
+            post(totp_enroll_handler).delete(totp_disenroll_handler),
+        )
+        .route(
+            &format!(
+                "/{}/{}/{}/confirm",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Totp.as_ref()
+            ),
+            post(totp_confirm_handler),
+        )
+        .route(
+            &format!(
+                "/{}/{}/{}/register/start",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Passkeys.as_ref()
+            ),
+            post(passkey_register_start_handler),
+        )
+        .route(
+            &format!(
+                "/{}/{}/{}/register/finish",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Passkeys.as_ref()
+            ),
+            post(passkey_register_finish_handler),
+        )
+        .route(
+            &format!(
+                "/{}/{}/{}/login/start",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Passkeys.as_ref()
+            ),
+            post(passkey_login_start_handler).layer(RateLimitLayer::new(
+                state.clone(),
+                website_path.clone(),
+                WebsiteRoute::Passkeys.as_ref(),
+                &state.config.rate_limits.passkeys,
+            )),
+        )
+        .route(
+            &format!(
+                "/{}/{}/{}/delete",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Account.as_ref()
+            ),
+            post(delete_account_handler).layer(RateLimitLayer::new(
+                state.clone(),
+                website_path.clone(),
+                WebsiteRoute::Account.as_ref(),
+                &state.config.rate_limits.account,
+            )),
+        )
+        .route(
+            &format!(
+                "/{}/{}/{}/delete/confirm",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Account.as_ref()
+            ),
+            post(delete_account_confirm_handler).layer(RateLimitLayer::new(
+                state.clone(),
+                website_path.clone(),
+                WebsiteRoute::Account.as_ref(),
+                &state.config.rate_limits.account,
+            )),
+        )
+        .route(
+            &format!(
+                "/{}/{}/{}/password",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Account.as_ref()
+            ),
+            post(change_password_handler).layer(RateLimitLayer::new(
+                state.clone(),
+                website_path.clone(),
+                WebsiteRoute::Account.as_ref(),
+                &state.config.rate_limits.account,
+            )),
+        )
+        .route(
+            &format!(
+                "/{}/{}/{}/email",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Account.as_ref()
+            ),
+            post(change_email_handler).layer(RateLimitLayer::new(
+                state.clone(),
+                website_path.clone(),
+                WebsiteRoute::Account.as_ref(),
+                &state.config.rate_limits.account,
+            )),
+        )
+        .route(
+            &format!(
+                "/{}/{}/{}/email/confirm",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Account.as_ref()
+            ),
+            post(change_email_confirm_handler).layer(RateLimitLayer::new(
+                state.clone(),
+                website_path.clone(),
+                WebsiteRoute::Account.as_ref(),
+                &state.config.rate_limits.account,
+            )),
+        )
+        .route(
+            &format!(
+                "/{}/{}/{}/events",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Account.as_ref()
+            ),
+            get(audit_events_handler),
+        )
+        .route(
+            &format!(
+                "/{}/{}/{}/users/:email",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Admin.as_ref()
+            ),
+            get(admin_user_handler),
+        )
+        .route(
+            &format!(
+                "/{}/{}/{}/users/:email/unlock",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Admin.as_ref()
+            ),
+            post(admin_unlock_handler),
+        )
+        .route(
+            &format!(
+                "/{}/{}/{}/users/:email/freeze",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Admin.as_ref()
+            ),
+            post(admin_freeze_handler),
+        )
+        .route(
+            &format!(
+                "/{}/{}/{}/users/:email/locks",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Admin.as_ref()
+            ),
+            delete(admin_clear_locks_handler),
+        )
+        .route(
+            &format!(
+                "/{}/{}/{}/login/finish",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Passkeys.as_ref()
+            ),
+            post(passkey_login_finish_handler).layer(RateLimitLayer::new(
+                state.clone(),
+                website_path.clone(),
+                WebsiteRoute::Passkeys.as_ref(),
+                &state.config.rate_limits.passkeys,
+            )),
+        )
+        .route(
+            &format!(
+                "/{}/{}/{}/start",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Oidc.as_ref()
+            ),
+            post(oidc_start_handler).layer(RateLimitLayer::new(
+                state.clone(),
+                website_path.clone(),
+                WebsiteRoute::Oidc.as_ref(),
+                &state.config.rate_limits.authenticate,
+            )),
+        )
+        .route(
+            &format!(
+                "/{}/{}/{}/callback",
+                website_path.as_ref(),
+                WebsiteRoute::Api.as_ref(),
+                WebsiteRoute::Oidc.as_ref()
+            ),
+            post(oidc_callback_handler).layer(RateLimitLayer::new(
+                state.clone(),
+                website_path.clone(),
+                WebsiteRoute::Oidc.as_ref(),
+                &state.config.rate_limits.authenticate,
+            )),
+        )
+}
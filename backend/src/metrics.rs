@@ -12,13 +12,22 @@ use redis::{AsyncTypedCommands, Script, aio::ConnectionManager};
 use std::sync::Arc;
 use strum_macros::{AsRefStr, EnumString};
 
-#[derive(EnumString, AsRefStr, PartialEq)]
+#[derive(EnumString, AsRefStr, PartialEq, Clone)]
 pub enum RedisMetricAction {
     #[strum(serialize = "visitors")]
     Visitors,
 
     #[strum(serialize = "items")]
     Items,
+
+    #[strum(serialize = "emails_sent")]
+    EmailsSent,
+
+    #[strum(serialize = "emails_failed")]
+    EmailsFailed,
+
+    #[strum(serialize = "emails_retried")]
+    EmailsRetried,
 }
 
 static DECR_METRIC_SCRIPT: Lazy<Script> = Lazy::new(|| {
@@ -40,6 +49,10 @@ pub async fn metrics_handler(State(state): State<Arc<AppState>>) -> Result<Strin
     let swap_items = IntGauge::new("swap_items", "Total items on BoilerSwap").unwrap();
     let bot_visitors = IntCounter::new("bot_visitors", "Total visitors on BoilerCuts").unwrap();
     let home_visitors = IntCounter::new("home_visitors", "Total visitors on Home").unwrap();
+    let emails_sent = IntCounter::new("emails_sent", "Total emails sent").unwrap();
+    let emails_failed =
+        IntCounter::new("emails_failed", "Total emails moved to dead letters").unwrap();
+    let emails_retried = IntCounter::new("emails_retried", "Total email retries").unwrap();
 
     pull_metric(
         state.clone(),
@@ -70,9 +83,22 @@ pub async fn metrics_handler(State(state): State<Arc<AppState>>) -> Result<Strin
     )
     .await?;
 
+    for (metric_action, counter) in [
+        (RedisMetricAction::EmailsSent, &emails_sent),
+        (RedisMetricAction::EmailsFailed, &emails_failed),
+        (RedisMetricAction::EmailsRetried, &emails_retried),
+    ] {
+        for website_path in [WebsitePath::BoilerSwap, WebsitePath::Home] {
+            pull_metric(state.clone(), website_path, metric_action.clone(), counter).await?;
+        }
+    }
+
     registry.register(Box::new(swap_visitors))?;
     registry.register(Box::new(bot_visitors))?;
     registry.register(Box::new(home_visitors))?;
+    registry.register(Box::new(emails_sent))?;
+    registry.register(Box::new(emails_failed))?;
+    registry.register(Box::new(emails_retried))?;
 
     let metric_families = registry.gather();
 
@@ -3,12 +3,18 @@ use crate::{
     api::{
         microservices::{
             database::init::{DatabaseQueries, init_database},
+            mail::{MailTransport, init_mail_transport},
             meilisearch::init_meilisearch,
             redis::init_redis,
         },
         web::{
             models::{RedisAction, WebsitePath},
-            swap::cdc::{expire_ttl, spawn_ttl_task},
+            password::PasswordPolicy,
+            swap::{
+                cdc::{expire_ttl, spawn_ttl_task},
+                database::backfill_user_items,
+            },
+            templates::EmailTemplates,
         },
     },
     config::Config,
@@ -19,6 +25,7 @@ use redis::aio::ConnectionManager;
 use scylla::client::session::Session;
 use std::sync::{Arc, atomic::Ordering::Relaxed};
 use tokio::task::JoinHandle;
+use webauthn_rs::prelude::{Url, Webauthn, WebauthnBuilder};
 
 pub struct AppState {
     pub config: Config,
@@ -26,6 +33,10 @@ pub struct AppState {
     pub database_queries: DatabaseQueries,
     pub redis_connection_manager: ConnectionManager,
     pub meili_client: Arc<Client>,
+    pub webauthn: Arc<Webauthn>,
+    pub email_templates: EmailTemplates,
+    pub mailer: Arc<dyn MailTransport>,
+    pub password_policy: PasswordPolicy,
 }
 
 impl AppState {
@@ -39,9 +50,20 @@ impl AppState {
         let meili_future = init_meilisearch(database_session.clone(), &database_queries);
 
         let config = Config::load()?;
+        let webauthn = init_webauthn(&config)?;
+        let email_templates =
+            EmailTemplates::load(&config.email.templates_path, &config.email.default_locale)?;
+        let mailer = init_mail_transport(&config)?;
+        let password_policy = PasswordPolicy::load(&config.passwords)?;
 
         let redis_connection_manager = redis_future.await?;
         expire_ttl_future.await?;
+        backfill_user_items(
+            database_session.clone(),
+            &database_queries,
+            redis_connection_manager.clone(),
+        )
+        .await?;
         let (meili_client, meili_reindex_future, item_counter) = meili_future.await?;
 
         set_redis_metric(
@@ -63,8 +85,23 @@ impl AppState {
                 database_queries,
                 redis_connection_manager,
                 meili_client,
+                webauthn,
+                email_templates,
+                mailer,
+                password_policy,
             }),
             meili_reindex_future,
         ))
     }
 }
+
+pub fn init_webauthn(config: &Config) -> Result<Arc<Webauthn>, AppError> {
+    let rp_origin = Url::parse(&config.server.svelte_url)
+        .map_err(|e| AppError::Config(format!("Invalid SVELTE_URL value: {}", e)))?;
+
+    Ok(Arc::new(
+        WebauthnBuilder::new(&config.passkeys.rp_id, &rp_origin)?
+            .rp_name(&config.passkeys.rp_name)
+            .build()?,
+    ))
+}
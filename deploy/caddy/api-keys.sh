@@ -0,0 +1,30 @@
+#!/bin/sh
+set -eu
+
+mkdir -p /etc/caddy/api-keys
+
+for name in SWAP_API_TOKEN HOME_API_TOKEN; do
+	out="/etc/caddy/api-keys/$name"
+	: >"$out"
+
+	while IFS= read -r line || [ -n "$line" ]; do
+		[ -z "$line" ] && continue
+
+		kid="${line%%=*}"
+		secret="${line#*=}"
+
+		case "$kid" in
+		"$line" | "" | *[!A-Za-z0-9._-]*)
+			echo "Malformed entry in $name, expected kid=secret" >&2
+			exit 1
+			;;
+		esac
+
+		if [ -z "$secret" ]; then
+			echo "Empty secret for kid $kid in $name" >&2
+			exit 1
+		fi
+
+		printf 'crypto key %s verify %s\n' "$kid" "$secret" >>"$out"
+	done <"/run/secrets/$name"
+done
@@ -6,10 +6,12 @@ import {
 	PUBLIC_MEILI_PATH
 } from '$env/static/public'
 import { SignJWT } from 'jose'
+import { API_TOKEN_MAX_AGE_SECS, mintApiToken } from '$lib/server/apiToken'
 
 export const handle: Handle = async ({ event, resolve }) => {
 	let token: string
 	let tokenPath: string
+	let audience: string
 	let search: boolean = false
 
 	if (
@@ -20,31 +22,30 @@ export const handle: Handle = async ({ event, resolve }) => {
 			case event.url.pathname.startsWith('/swap'):
 				token = env.SWAP_API_TOKEN!
 				tokenPath = PUBLIC_SWAP_BACKEND_PATH
+				audience = 'swap'
 				search = true
 				break
 			case event.url.pathname === '/':
 				token = env.HOME_API_TOKEN!
 				tokenPath = PUBLIC_HOME_BACKEND_PATH
+				audience = 'home'
 				search = false
 				break
 			default:
 				token = 'Well'
 				tokenPath = 'Oh'
+				audience = 'None'
 				search = false
 		}
 
-		const jwt = await new SignJWT({})
-			.setProtectedHeader({ alg: 'HS256' })
-			.setIssuedAt()
-			.setExpirationTime('5m')
-			.sign(new TextEncoder().encode(token))
+		const jwt = await mintApiToken(token, audience)
 
 		event.cookies.set('api_token', jwt, {
 			path: tokenPath,
 			httpOnly: true,
 			sameSite: 'strict',
 			secure: true,
-			maxAge: 60 * 5
+			maxAge: API_TOKEN_MAX_AGE_SECS
 		})
 	}
 
@@ -0,0 +1,29 @@
+import { SignJWT } from 'jose'
+
+export const API_TOKEN_MAX_AGE_SECS = 60 * 5
+
+export async function mintApiToken(keyring: string, audience: string): Promise<string> {
+	const line = keyring
+		.split('\n')
+		.map((entry) => entry.trim())
+		.find((entry) => entry !== '')
+
+	if (!line) {
+		throw new Error(`No signing key for ${audience}`)
+	}
+
+	const separator = line.indexOf('=')
+	const kid = line.slice(0, separator)
+	const secret = line.slice(separator + 1)
+
+	if (separator === -1 || !/^[A-Za-z0-9][A-Za-z0-9._-]{0,63}$/.test(kid) || secret === '') {
+		throw new Error(`Malformed signing key for ${audience}, expected kid=secret`)
+	}
+
+	return new SignJWT({})
+		.setProtectedHeader({ alg: 'HS256', kid })
+		.setAudience(audience)
+		.setIssuedAt()
+		.setExpirationTime(`${API_TOKEN_MAX_AGE_SECS}s`)
+		.sign(new TextEncoder().encode(secret))
+}
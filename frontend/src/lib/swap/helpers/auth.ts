@@ -1,9 +1,10 @@
 import { goto } from '$app/navigation'
-import { PUBLIC_SWAP_BACKEND_PATH, PUBLIC_SVELTE_SWAP_ROOT } from '$env/static/public'
-import { Status, type Account } from '$lib/swap/models'
+import { PUBLIC_SVELTE_SWAP_ROOT } from '$env/static/public'
+import { Status, type Account, type OidcCallbackPayload } from '$lib/swap/models'
 import { appState } from '$lib/swap/AppState.svelte'
 import {
 	fetchBackend,
+	fetchWithCsrf,
 	isLimited,
 	isSignedIn,
 	isUpdating,
@@ -79,6 +80,97 @@ export async function verify(authCode: string): Promise<void> {
 	} catch (err) {}
 }
 
+export async function verifyLink(linkToken: string): Promise<void> {
+	if (isLimited() || !/^[A-Za-z0-9_-]{43}$/.test(linkToken)) {
+		appState.setAuthError('This sign-in link is invalid')
+
+		return
+	}
+
+	try {
+		appState.nowLimited()
+
+		await fetchBackend('/verify', { token: linkToken })
+
+		appState.setStatus(Status.isSignedIn, true)
+		goto(PUBLIC_SVELTE_SWAP_ROOT + '/browse')
+	} catch (err) {}
+}
+
+export async function startOidc(): Promise<void> {
+	if (isLimited()) {
+		return
+	}
+
+	appState.nowLimited()
+
+	const response = await fetchWithCsrf('/oidc/start', {
+		method: 'POST'
+	})
+
+	if (!response.ok) {
+		appState.setAuthError((await response.text()).slice(0, 100))
+
+		return
+	}
+
+	const { url } = await response.json()
+
+	window.location.assign(url)
+}
+
+export async function finishOidc(payload: OidcCallbackPayload): Promise<void> {
+	if (payload.code === '' || payload.state === '') {
+		appState.setAuthError('This sign-in attempt is invalid')
+
+		return
+	}
+
+	const response = await fetchWithCsrf('/oidc/callback', {
+		method: 'POST',
+		headers: {
+			'Content-Type': 'application/json'
+		},
+		body: JSON.stringify(payload)
+	})
+
+	if (!response.ok) {
+		appState.setAuthError((await response.text()).slice(0, 100))
+
+		return
+	}
+
+	const { second_factor } = await response.json()
+
+	if (second_factor) {
+		appState.setStatus(Status.isVerifying, true)
+		goto(PUBLIC_SVELTE_SWAP_ROOT + '/auth/verify')
+
+		return
+	}
+
+	appState.setStatus(Status.isSignedIn, true)
+	goto(PUBLIC_SVELTE_SWAP_ROOT + '/browse')
+}
+
+export async function freezeAccount(linkToken: string): Promise<boolean> {
+	if (!/^[A-Za-z0-9_-]{43}$/.test(linkToken)) {
+		appState.setAuthError('This link is invalid or has already been used')
+
+		return false
+	}
+
+	try {
+		await fetchBackend('/freeze', { token: linkToken })
+
+		appState.setStatus(Status.isSignedIn, false)
+
+		return true
+	} catch (err) {
+		return false
+	}
+}
+
 export async function verify_forget(authCode: string) {
 	if (isLimited() || !isResetting() || !isCodeGood(authCode)) {
 		return
@@ -116,9 +208,8 @@ export async function signout() {
 
 	appState.nowLimited()
 
-	const response = await fetch(PUBLIC_SWAP_BACKEND_PATH + '/delete', {
-		method: 'DELETE',
-		credentials: 'include'
+	const response = await fetchWithCsrf('/delete', {
+		method: 'DELETE'
 	})
 
 	if (!response.ok) {
@@ -135,9 +226,8 @@ export async function resend(resendSeconds: number) {
 
 	appState.nowLimited()
 
-	const response = await fetch(PUBLIC_SWAP_BACKEND_PATH + '/resend', {
-		method: 'POST',
-		credentials: 'include'
+	const response = await fetchWithCsrf('/resend', {
+		method: 'POST'
 	})
 
 	if (!response.ok) {
@@ -43,11 +43,11 @@ export function isSignUpGood(account: Account, confirmPassword: string): boolean
 }
 
 export function isEmailGood(email: string): boolean {
-	if (/.+@purdue\.edu$/.test(email) && email.length <= Number(PUBLIC_MAX_CHARS)) {
+	if (/^[^@\s]+@[^@\s]+$/.test(email) && email.length <= Number(PUBLIC_MAX_CHARS)) {
 		return true
 	}
 
-	appState.setAuthError('Email must be a Purdue address')
+	appState.setAuthError('Enter a valid email address')
 
 	return false
 }
@@ -76,18 +76,45 @@ export function isCodeGood(code: string): boolean {
 	return false
 }
 
+function getCsrfToken(): string {
+	return (
+		document.cookie
+			.split('; ')
+			.find((cookie) => cookie.startsWith('csrf_token='))
+			?.slice('csrf_token='.length) ?? ''
+	)
+}
+
+export async function fetchWithCsrf(path: string, init: RequestInit): Promise<Response> {
+	const send = () =>
+		fetch(PUBLIC_SWAP_BACKEND_PATH + path, {
+			...init,
+			headers: { ...init.headers, 'x-csrf-token': getCsrfToken() },
+			credentials: 'include'
+		})
+
+	const response = await send()
+
+	if (response.status !== 403 || (await response.clone().text()) !== 'Invalid CSRF token') {
+		return response
+	}
+
+	await fetch(PUBLIC_SWAP_BACKEND_PATH + '/csrf', { credentials: 'include' })
+
+	return send()
+}
+
 export async function fetchBackend(path: string, payload: Account | TokenPayload) {
-	const response = await fetch(PUBLIC_SWAP_BACKEND_PATH + path, {
+	const response = await fetchWithCsrf(path, {
 		method: 'POST',
 		headers: {
 			'Content-Type': 'application/json'
 		},
-		credentials: 'include',
 		body: JSON.stringify(payload)
 	})
 
 	if (!response.ok) {
-		appState.setAuthError((await response.text()).slice(0, 50))
+		appState.setAuthError((await response.text()).slice(0, 100))
 
 		throw new Error(`HTTP error! status: ${response.status}`)
 	}
@@ -4,6 +4,11 @@ export type TokenPayload = {
 	token: string
 }
 
+export type OidcCallbackPayload = {
+	code: string
+	state: string
+}
+
 export const TabOptionsIterable = ['Login', 'Signup', 'Reset'] as const
 
 export type TabOptions = (typeof TabOptionsIterable)[number]